@@ -0,0 +1,78 @@
+//! Query lexed per request vs prepared ahead of time, on an in-memory-sized SQLite table.
+
+use std::{collections::HashMap, hint::black_box, path::Path};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use xepak_rest::{
+    cfg::XepakConf,
+    server::RequestInput,
+    storage::{PreparedQuery, ResourceRequest, Storage, init_storage_connectors},
+    types::XepakValue,
+};
+
+const QUERY: &str = "SELECT id, name FROM users
+    WHERE id = {{id}} AND (name = {{name}} OR {{name}} IS NULL) AND id > {{min_id}}
+    LIMIT {{-limit-}}";
+
+async fn init_storage() -> Storage {
+    sqlx::any::install_default_drivers();
+
+    let file = std::env::temp_dir().join(format!("xepak-bench-{}.sqlite3", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    std::fs::File::create(&file).unwrap();
+
+    let config: XepakConf = toml::from_str(&format!(
+        "[[storage]]\ntype = \"sqlite\"\nfile = {:?}",
+        file.to_string_lossy()
+    ))
+    .unwrap();
+    let storage = init_storage_connectors(Path::new("."), &config)
+        .await
+        .unwrap()
+        .remove("")
+        .unwrap();
+
+    let args = RequestInput::new_in_script(Default::default(), 0, 0);
+    for statement in [
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)",
+        "INSERT INTO users VALUES (1, 'alice'), (2, 'bob')",
+    ] {
+        storage
+            .execute(ResourceRequest::new(statement, &args))
+            .await
+            .unwrap();
+    }
+    storage
+}
+
+fn prepared_query(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let storage = rt.block_on(init_storage());
+
+    let args = RequestInput::new_in_script(
+        HashMap::from([
+            ("id".to_string(), XepakValue::from(1)),
+            ("name".to_string(), XepakValue::Null),
+            ("min_id".to_string(), XepakValue::from(0)),
+        ]),
+        10,
+        0,
+    );
+    let prepared = PreparedQuery::new(QUERY).unwrap();
+
+    c.bench_function("query_lexed", |b| {
+        b.iter(|| {
+            rt.block_on(storage.query(ResourceRequest::new(black_box(QUERY), &args)))
+                .unwrap()
+        })
+    });
+    c.bench_function("query_prepared", |b| {
+        b.iter(|| {
+            rt.block_on(storage.query_prepared(black_box(&prepared), &args))
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, prepared_query);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+//! Serialization cost of a typical id-lookup response (single record).
+
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use xepak_rest::types::{Record, RecordOutput, ResourceOutput, XepakValue};
+
+fn id_lookup_record() -> Record {
+    Record::from([
+        ("id".to_string(), XepakValue::Integer(42)),
+        ("name".to_string(), XepakValue::Text("alice".to_string())),
+        ("score".to_string(), XepakValue::Float(4.5)),
+        ("active".to_string(), XepakValue::Boolean(true)),
+    ])
+}
+
+fn single_record(c: &mut Criterion) {
+    let record = id_lookup_record();
+    let records = ResourceOutput::Records(vec![record.clone()]);
+
+    // Single record response serializes the record taken from the fetched rows as is
+    c.bench_function("single_record_json", |b| {
+        b.iter(|| serde_json::to_vec(black_box(&record)).unwrap())
+    });
+    c.bench_function("single_record_cbor", |b| {
+        b.iter(|| minicbor::to_vec(black_box(&RecordOutput(&record))).unwrap())
+    });
+
+    c.bench_function("records_json", |b| {
+        b.iter(|| serde_json::to_vec(black_box(&records)).unwrap())
+    });
+}
+
+criterion_group!(benches, single_record);
+criterion_main!(benches);
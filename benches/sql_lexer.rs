@@ -0,0 +1,68 @@
+//! Key args lexer throughput on typical and pathological queries.
+//! Pathological inputs are measured in two sizes, time must grow linearly.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use xepak_rest::sql_key_args::SqlLexer;
+
+fn many_placeholders(count: usize) -> String {
+    let conditions: Vec<String> = (0..count)
+        .map(|i| format!("c{i} = {{{{arg_{i}}}}}"))
+        .collect();
+    format!("SELECT * FROM t WHERE {}", conditions.join(" AND "))
+}
+
+fn long_literal(len: usize) -> String {
+    format!(
+        "SELECT * FROM t WHERE note = '{}' AND id = {{{{id}}}}",
+        "x".repeat(len)
+    )
+}
+
+fn heavy_escaping(len: usize) -> String {
+    format!(
+        "SELECT * FROM t WHERE a = '{}' AND b = \"{}\" AND id = {{{{id}}}}",
+        "\\'".repeat(len / 2),
+        "\"\"".repeat(len / 2)
+    )
+}
+
+/// Strings opened and closed over and over, with curly braces inside.
+fn deeply_quoted(len: usize) -> String {
+    format!(
+        "SELECT {} {{{{id}}}}",
+        "'{{x}}''\"{{y}}\"'".repeat(len / 16)
+    )
+}
+
+fn lex(sql: &str) -> usize {
+    SqlLexer::new(sql).count()
+}
+
+fn sql_lexer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sql_lexer");
+
+    let inputs = [
+        ("many_placeholders", many_placeholders(200)),
+        ("long_literal", long_literal(10_000)),
+        ("heavy_escaping", heavy_escaping(10_000)),
+    ];
+    for (name, sql) in &inputs {
+        group.throughput(Throughput::Bytes(sql.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| lex(black_box(sql))));
+    }
+
+    for len in [1_000, 10_000] {
+        let sql = deeply_quoted(len);
+        group.throughput(Throughput::Bytes(sql.len() as u64));
+        group.bench_with_input(BenchmarkId::new("deeply_quoted", len), &sql, |b, sql| {
+            b.iter(|| lex(black_box(sql)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, sql_lexer);
+criterion_main!(benches);
@@ -0,0 +1,267 @@
+//! Static heuristic lint of specs for SQL injection foot-guns (`--audit` CLI mode).
+//! It only catches obvious patterns, clean report is not a guarantee.
+
+use std::{collections::HashSet, fmt};
+
+use strum::Display;
+
+use crate::{
+    cfg::{EndpointSpecs, ResourceSpecs, XepakSpecs},
+    sql_key_args::ParametrizedQueryRef,
+    storage::IDENT_ARG_PREFIX,
+};
+
+/// Script calls returning request controlled text.
+/// Limit, offset and method are not here, they can't carry arbitrary text.
+const REQUEST_DATA_CALLS: [&str; 4] = ["get_arg(", "header(", ".path()", ".route()"];
+
+/// Keywords that make a string literal look like a part of SQL query
+const SQL_KEYWORDS: [&str; 8] = [
+    "SELECT ", "INSERT ", "UPDATE ", "DELETE ", "WHERE ", "ORDER BY", " FROM ", " VALUES",
+];
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditRule {
+    /// Request data added to `query_builder` instead of a `{{key}}` argument
+    QueryBuilderInlinedArg,
+    /// Request data concatenated or interpolated into a query string
+    ScriptInlinedArg,
+    /// Query text assembled by concatenation (may be fine, worth a look)
+    ScriptConcatQuery,
+    /// `{{!ident}}` argument without `identifiers` allowlist in the schema
+    IdentWithoutAllowlist,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditFinding {
+    pub rule: AuditRule,
+    /// Endpoint URI or script id
+    pub location: String,
+    /// Line in the script (1-based)
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for AuditFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "{}:{line} [{}] {}",
+                self.location, self.rule, self.message
+            ),
+            None => write!(f, "{} [{}] {}", self.location, self.rule, self.message),
+        }
+    }
+}
+
+/// Scan endpoints and shared scripts for risky query building patterns.
+pub fn audit_specs(specs: &XepakSpecs) -> Vec<AuditFinding> {
+    let mut findings = Vec::new();
+
+    for script in &specs.script {
+        audit_script(
+            &format!("script {}", script.id),
+            &script.script,
+            &mut findings,
+        );
+    }
+
+    for ep in &specs.endpoint {
+        let location = format!("endpoint {}", ep.uri.first());
+        if let ResourceSpecs::QueryScript { script, .. } = &ep.resource {
+            audit_script(&location, script, &mut findings);
+        }
+        for query in endpoint_queries(ep) {
+            audit_identifiers(&location, ep, specs, query, &mut findings);
+        }
+    }
+
+    findings
+}
+
+/// Static queries of the endpoint resource.
+fn endpoint_queries(ep: &EndpointSpecs) -> Vec<&str> {
+    match &ep.resource {
+        ResourceSpecs::Query { query, .. }
+        | ResourceSpecs::WebSocketQuery { query, .. }
+        | ResourceSpecs::SseQuery { query, .. } => vec![query.as_str()],
+        ResourceSpecs::QuerySwitch { cases, .. } => cases.values().map(String::as_str).collect(),
+        ResourceSpecs::Composite { parts } => parts.iter().map(|p| p.query.as_str()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn audit_identifiers(
+    location: &str,
+    ep: &EndpointSpecs,
+    specs: &XepakSpecs,
+    query: &str,
+    findings: &mut Vec<AuditFinding>,
+) {
+    let pquery = ParametrizedQueryRef::new(query);
+    for name in pquery
+        .get_args()
+        .iter()
+        .filter_map(|arg| arg.strip_prefix(IDENT_ARG_PREFIX))
+    {
+        let allowlisted = ep
+            .schema
+            .get(name)
+            .or_else(|| specs.default_schema.get(name))
+            .is_some_and(|s| !s.identifiers.is_empty());
+        if !allowlisted {
+            findings.push(AuditFinding {
+                rule: AuditRule::IdentWithoutAllowlist,
+                location: location.to_string(),
+                line: None,
+                message: format!("{{{{!{name}}}}} has no identifiers allowlist in the schema"),
+            });
+        }
+    }
+}
+
+/// Line based heuristic: variables assigned from request data are tracked,
+/// lines that glue them (or request data calls) into strings are reported.
+fn audit_script(location: &str, script: &str, findings: &mut Vec<AuditFinding>) {
+    let mut tainted: HashSet<&str> = HashSet::new();
+
+    for (idx, line) in script.lines().enumerate() {
+        let code = line.split("//").next().unwrap_or_default();
+        let reads_request = REQUEST_DATA_CALLS.iter().any(|c| code.contains(c))
+            || identifiers(code).any(|word| tainted.contains(word));
+
+        if reads_request && let Some(name) = assigned_variable(code) {
+            tainted.insert(name);
+        }
+
+        let glues = code.contains('+') || code.contains("${");
+        let builder = code.contains(".add(")
+            || code.contains("add_joined_parts(")
+            || code.contains("query_builder(");
+        let sql_literal = {
+            let upper = code.to_ascii_uppercase();
+            code.contains(['"', '`']) && SQL_KEYWORDS.iter().any(|k| upper.contains(k))
+        };
+
+        let finding = if reads_request && builder {
+            Some((
+                AuditRule::QueryBuilderInlinedArg,
+                "request data is added to query builder as SQL text, use {{key}} arguments",
+            ))
+        } else if reads_request && glues && (sql_literal || assigned_variable(code).is_none()) {
+            Some((
+                AuditRule::ScriptInlinedArg,
+                "request data is concatenated into query, use {{key}} arguments",
+            ))
+        } else if glues && sql_literal {
+            Some((
+                AuditRule::ScriptConcatQuery,
+                "query is assembled by string concatenation",
+            ))
+        } else {
+            None
+        };
+
+        if let Some((rule, message)) = finding {
+            findings.push(AuditFinding {
+                rule,
+                location: location.to_string(),
+                line: Some(idx + 1),
+                message: message.to_string(),
+            });
+        }
+    }
+}
+
+fn identifiers(code: &str) -> impl Iterator<Item = &str> {
+    code.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty())
+}
+
+/// Variable name of `let name = ...` (or `name = ...`) statement.
+fn assigned_variable(code: &str) -> Option<&str> {
+    let code = code.trim_start();
+    let code = code.strip_prefix("let ").unwrap_or(code);
+    let (name, rest) = code.split_once('=')?;
+    let name = name.trim();
+    // `==` comparison is not an assignment
+    if rest.starts_with('=') || name.is_empty() || identifiers(name).count() != 1 {
+        return None;
+    }
+    identifiers(name).next().filter(|n| *n == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn risky_patterns_reported() {
+        let specs: XepakSpecs = toml::from_str(
+            r#"
+            [[script]]
+            id = "safe"
+            script = """
+            let q = query_builder("SELECT id FROM users");
+            if ctx.has_arg("name") { q.add("WHERE name = {{name}}"); }
+            q.build()
+            """
+
+            [[endpoint]]
+            uri = "/search"
+
+            [endpoint.resource]
+            type = "query_script"
+            script = """
+            let name = ctx.get_arg("name");
+            let q = query_builder("SELECT id FROM users");
+            q.add("WHERE name = '" + name + "'");
+            let table = "users";
+            "SELECT * FROM " + table
+            """
+
+            [[endpoint]]
+            uri = "/sorted"
+            schema = { dir = { identifiers = ["asc", "desc"] } }
+
+            [endpoint.resource]
+            type = "query"
+            query = "SELECT id FROM users ORDER BY {{!sort}} {{!dir}}"
+            "#,
+        )
+        .unwrap();
+
+        let findings: Vec<String> = audit_specs(&specs).iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            findings,
+            vec![
+                "endpoint /search:3 [query_builder_inlined_arg] request data is added to query builder as SQL text, use {{key}} arguments",
+                "endpoint /search:5 [script_concat_query] query is assembled by string concatenation",
+                "endpoint /sorted [ident_without_allowlist] {{!sort}} has no identifiers allowlist in the schema",
+            ]
+        );
+    }
+
+    #[test]
+    fn header_inlined_into_query() {
+        let specs: XepakSpecs = toml::from_str(
+            r#"
+            [[endpoint]]
+            uri = "/tagged"
+
+            [endpoint.resource]
+            type = "query_script"
+            script = """
+            "SELECT '" + ctx.header("x-tag") + "' AS tag"
+            """
+            "#,
+        )
+        .unwrap();
+
+        let findings = audit_specs(&specs);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, AuditRule::ScriptInlinedArg);
+    }
+}
@@ -1,14 +1,21 @@
 use std::collections::HashSet;
 
+use actix_web::http::header::AUTHORIZATION;
 use serde::Deserialize;
 use sqlx_core::HashMap;
+use strum::Display;
 
-use crate::{XepakError, server::processor::PreProcessorHandler};
+use crate::{
+    XepakError,
+    server::processor::{PRIORITY_AUTHENTICATE, PRIORITY_AUTHORIZE, PreProcessorHandler},
+};
 
 pub type SimpleAuthRegistry = HashMap<String, (String, HashSet<String>)>;
 
 pub const API_KEY_HEADER: &str = "x-api-key";
 
+const BEARER_PREFIX: &str = "Bearer ";
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct SimpleAuthSpecs {
     id: String,
@@ -90,13 +97,28 @@ impl CheckAuthConf {
             CheckAuthConf::And { nested } => Self::And {
                 nested: nested.iter().cloned().map(|v| v.normalize()).collect(),
             },
-            CheckAuthConf::Or { nested } => Self::And {
+            CheckAuthConf::Or { nested } => Self::Or {
                 nested: nested.iter().cloned().map(|v| v.normalize()).collect(),
             },
         }
     }
 
-    fn is_allowed(&self, id: &str, roles: &HashSet<String>) -> bool {
+    /// Combine checks into one where at least one of them must succeed.
+    /// Returns `None` if there are no checks.
+    pub fn any_of(checks: &[CheckAuthConf]) -> Option<Self> {
+        match checks {
+            [] => None,
+            [check] => Some(check.normalize()),
+            _ => Some(
+                CheckAuthConf::Or {
+                    nested: checks.to_vec(),
+                }
+                .normalize(),
+            ),
+        }
+    }
+
+    pub fn is_allowed(&self, id: &str, roles: &HashSet<String>) -> bool {
         match self {
             CheckAuthConf::Role { v } => roles.contains(v),
             CheckAuthConf::Id { v } => id == v,
@@ -124,6 +146,103 @@ impl CheckAuthConf {
     }
 }
 
+/// Way the client passes credentials.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Display)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AuthMethod {
+    /// API key in the [`API_KEY_HEADER`] header
+    ApiKey,
+    /// API key as a bearer token in the `Authorization` header
+    Bearer,
+}
+
+impl AuthMethod {
+    /// Credentials passed with this method (if any).
+    fn credentials<'a>(
+        &self,
+        req: &'a actix_web::HttpRequest,
+    ) -> Result<Option<&'a str>, XepakError> {
+        match self {
+            AuthMethod::ApiKey => req
+                .headers()
+                .get(API_KEY_HEADER)
+                .map(|v| {
+                    v.to_str().map_err(|e| {
+                        XepakError::Input(format!("Wrong {API_KEY_HEADER} value: {e}"))
+                    })
+                })
+                .transpose(),
+            AuthMethod::Bearer => Ok(req
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix(BEARER_PREFIX))
+                .map(str::trim)),
+        }
+    }
+}
+
+/// Authenticate with the first of `methods` that has valid credentials.
+/// Fails only if none of them succeeds (unless nothing was passed and `allow_no_auth` is set).
+pub struct AnyAuthenticationProcessor {
+    methods: Vec<AuthMethod>,
+    allow_no_auth: bool,
+}
+
+impl AnyAuthenticationProcessor {
+    pub fn new_boxed(methods: &[AuthMethod], allow_no_auth: bool) -> Box<Self> {
+        Box::new(Self {
+            methods: methods.to_vec(),
+            allow_no_auth,
+        })
+    }
+}
+
+impl PreProcessorHandler for AnyAuthenticationProcessor {
+    fn priority(&self) -> u16 {
+        PRIORITY_AUTHENTICATE
+    }
+
+    fn handle(
+        &self,
+        req: &actix_web::HttpRequest,
+        state: &actix_web::web::Data<crate::server::XepakAppData>,
+        _body: &actix_web::web::Bytes,
+        input: &mut crate::server::RequestInput,
+    ) -> Result<(), XepakError> {
+        if input.is_authenticated() {
+            tracing::warn!("Already authenticated! Why?");
+            return Ok(());
+        }
+
+        let mut has_credentials = false;
+        for method in &self.methods {
+            let api_key = match method.credentials(req) {
+                Ok(Some(api_key)) => api_key,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::debug!("Skipping auth method {method:?}: {e}");
+                    has_credentials = true;
+                    continue;
+                }
+            };
+            has_credentials = true;
+
+            if let Some((auth_id, auth_roles)) = state.get_auth_data(api_key) {
+                input.set_auth(auth_id.to_string(), auth_roles.clone(), *method);
+                return Ok(());
+            }
+            tracing::debug!("Auth method {method:?} failed, trying the next one");
+        }
+
+        if !has_credentials && self.allow_no_auth {
+            return Ok(());
+        }
+        Err(XepakError::Forbidden("Not authenticated".to_string()))
+    }
+}
+
 pub struct SimpleAuthenticationProcessor {
     allow_no_auth: bool,
 }
@@ -139,6 +258,10 @@ impl SimpleAuthenticationProcessor {
 }
 
 impl PreProcessorHandler for SimpleAuthenticationProcessor {
+    fn priority(&self) -> u16 {
+        PRIORITY_AUTHENTICATE
+    }
+
     fn handle(
         &self,
         req: &actix_web::HttpRequest,
@@ -154,20 +277,19 @@ impl PreProcessorHandler for SimpleAuthenticationProcessor {
         let not_auth_err = Err(XepakError::Forbidden("Not authenticated".to_string()));
 
         // get API key value from headers
-        let Some(api_key_value) = req.headers().get(API_KEY_HEADER) else {
+        let Some(api_key) = AuthMethod::ApiKey.credentials(req)? else {
+            if self.allow_no_auth {
+                return Ok(());
+            }
             return not_auth_err;
         };
 
-        let api_key = api_key_value
-            .to_str()
-            .map_err(|e| XepakError::Input(format!("Wrong {API_KEY_HEADER} value: {e}")))?;
-
         // check in registry if key exists or error
         let Some((auth_id, auth_roles)) = state.get_auth_data(api_key) else {
             return not_auth_err;
         };
 
-        input.set_auth(auth_id.to_string(), auth_roles.clone());
+        input.set_auth(auth_id.to_string(), auth_roles.clone(), AuthMethod::ApiKey);
 
         Ok(())
     }
@@ -179,20 +301,9 @@ pub struct AuthorizeProcessor {
 
 impl AuthorizeProcessor {
     pub fn new(checks: &[CheckAuthConf]) -> Self {
-        let check = if checks.len() > 1 {
-            Some(
-                CheckAuthConf::Or {
-                    nested: checks.to_vec(),
-                }
-                .normalize(),
-            )
-        } else if checks.len() == 1 {
-            Some(checks[0].clone())
-        } else {
-            None
-        };
-
-        Self { check }
+        Self {
+            check: CheckAuthConf::any_of(checks),
+        }
     }
 
     pub fn new_boxed(checks: &[CheckAuthConf]) -> Box<Self> {
@@ -201,6 +312,10 @@ impl AuthorizeProcessor {
 }
 
 impl PreProcessorHandler for AuthorizeProcessor {
+    fn priority(&self) -> u16 {
+        PRIORITY_AUTHORIZE
+    }
+
     fn handle(
         &self,
         _req: &actix_web::HttpRequest,
@@ -229,3 +344,192 @@ impl PreProcessorHandler for AuthorizeProcessor {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use crate::testing::TestEnv;
+
+    #[actix_web::test]
+    async fn query_filtered_by_auth_id() {
+        let app = TestEnv::new(
+            "query_filtered_by_auth_id",
+            r#"
+            [[simple_auth]]
+            id = "alice"
+            key = "alice-key"
+            roles = ["user", "admin"]
+
+            [[simple_auth]]
+            id = "bob"
+            key = "bob-key"
+            "#,
+            "CREATE TABLE notes (owner TEXT, title TEXT);
+            INSERT INTO notes VALUES ('alice', 'a1'), ('bob', 'b1'), ('alice', 'a2')",
+        )
+        .await
+        .service(&[r#"
+            uri = "/notes"
+            processor = [{ type = "simple_auth" }]
+
+            [resource]
+            type = "query"
+            query = """SELECT title, {{-auth-roles-}} AS roles FROM notes
+                WHERE owner = {{-auth-id-}} ORDER BY title"""
+            "#])
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/notes?owner=bob")
+            .insert_header(("x-api-key", "alice-key"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {"title": "a1", "roles": "ADMIN,USER"},
+                {"title": "a2", "roles": "ADMIN,USER"}
+            ])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/notes")
+            .insert_header(("x-api-key", "bob-key"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"title": "b1", "roles": ""}]));
+    }
+
+    #[actix_web::test]
+    async fn row_filter_for_non_admins() {
+        let app = TestEnv::new(
+            "row_filter_for_non_admins",
+            r#"
+            [[simple_auth]]
+            id = "root"
+            key = "root-key"
+            roles = ["admin"]
+
+            [[simple_auth]]
+            id = "bob"
+            key = "bob-key"
+            roles = ["user"]
+            "#,
+            "CREATE TABLE docs (owner TEXT, title TEXT);
+            INSERT INTO docs VALUES ('alice', 'a1'), ('bob', 'b1'), ('bob', 'b2')",
+        )
+        .await
+        .service(&[r#"
+            uri = "/docs"
+            processor = [{ type = "simple_auth" }]
+            row_filter = { condition = "owner = {{-auth-id-}}", unless = [{ kind = "role", v = "admin" }] }
+
+            [resource]
+            type = "query"
+            query = "SELECT title FROM docs WHERE {{-row-filter-}} ORDER BY title"
+            "#])
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/docs")
+            .insert_header(("x-api-key", "root-key"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"title": "a1"}, {"title": "b1"}, {"title": "b2"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/docs")
+            .insert_header(("x-api-key", "bob-key"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"title": "b1"}, {"title": "b2"}]));
+    }
+
+    #[actix_web::test]
+    async fn auth_methods_tried_in_order() {
+        let app = TestEnv::new(
+            "auth_methods_tried_in_order",
+            r#"
+            [[simple_auth]]
+            id = "alice"
+            key = "alice-key"
+            "#,
+            "",
+        )
+        .await
+        .service(&[r#"
+            uri = "/whoami"
+            processor = [{ type = "any_auth", methods = [{ kind = "api_key" }, { kind = "bearer" }] }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{-auth-id-}} AS id"
+            "#])
+        .await;
+
+        // Wrong API key, but the bearer token (second method) is valid
+        let req = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header(("x-api-key", "wrong-key"))
+            .insert_header(("Authorization", "Bearer alice-key"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"id": "alice"}]));
+
+        let req = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header(("Authorization", "Bearer wrong-key"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::get().uri("/whoami").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn auth_id_header_under_flag() {
+        for (conf, exposed) in [("", false), ("auth_id_header = true", true)] {
+            let conf = format!(
+                "{conf}\n[[simple_auth]]\nid = \"alice\"\nkey = \"alice-key\"\nroles = [\"admin\"]"
+            );
+            let app = TestEnv::new("auth_id_header_under_flag", &conf, "")
+                .await
+                .service(&[r#"
+                uri = "/whoami"
+                processor = [{ type = "simple_auth" }]
+
+                [resource]
+                type = "query"
+                query = "SELECT {{-auth-id-}} AS id"
+                "#])
+                .await;
+
+            let req = test::TestRequest::get()
+                .uri("/whoami")
+                .insert_header(("x-api-key", "alice-key"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let headers = resp.headers();
+            if exposed {
+                assert_eq!(headers.get("X-Auth-Id").unwrap(), "alice");
+                assert_eq!(headers.get("X-Auth-Method").unwrap(), "api_key");
+            } else {
+                assert!(headers.get("X-Auth-Id").is_none());
+                assert!(headers.get("X-Auth-Method").is_none());
+            }
+            let identity = resp
+                .response()
+                .extensions()
+                .get::<crate::server::AuthIdentity>()
+                .cloned();
+            assert_eq!(identity.unwrap().to_string(), "alice (api_key)");
+        }
+    }
+}
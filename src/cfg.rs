@@ -1,9 +1,21 @@
-use std::{collections::HashSet, fs, path::PathBuf, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::Arc,
+};
 
 use serde::Deserialize;
 
 use crate::{
-    XepakError, auth::SimpleAuthSpecs, schema::Schema, server::processor::PreProcessor,
+    XepakError,
+    auth::{CheckAuthConf, SimpleAuthSpecs},
+    schema::Schema,
+    server::{
+        ArgSource, client_ip::IpCidr, compress::CompressionAlgorithm, cors::CorsConf,
+        health::HealthEndpointsConf, keyset::KeysetSpecs, processor::PreProcessor, tls::TlsConf,
+        version::VersionEndpointConf,
+    },
     storage::StorageSettings,
 };
 
@@ -14,6 +26,15 @@ pub struct XepakConf {
     #[serde(default = "default_port")]
     pub port: u16,
 
+    /// Listen on this unix socket instead of TCP port (relative to the config dir).
+    /// The socket file is removed before binding and after shutdown, `tls` can't be combined with it.
+    #[serde(default)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Serve HTTPS (with HTTP/2 by default) instead of plain HTTP on the TCP port.
+    #[serde(default)]
+    pub tls: Option<TlsConf>,
+
     /// Path to the directory with specs.
     #[serde(default = "default_specs_dir")]
     pub specs_dir: PathBuf,
@@ -24,6 +45,119 @@ pub struct XepakConf {
 
     #[serde(default)]
     pub simple_auth: Vec<SimpleAuthSpecs>,
+
+    /// Response header name for the page limit (default `X-Limit`).
+    #[serde(default)]
+    pub limit_header: Option<String>,
+
+    /// Response header name for the page offset (default `X-Offset`).
+    #[serde(default)]
+    pub offset_header: Option<String>,
+
+    /// Key argument bound to the page limit in queries (default `-limit-`, i.e. `{{-limit-}}`).
+    #[serde(default)]
+    pub limit_key: Option<String>,
+
+    /// Key argument bound to the page offset in queries (default `-offset-`).
+    #[serde(default)]
+    pub offset_key: Option<String>,
+
+    /// Respond with 414 if request path with query string is longer.
+    #[serde(default)]
+    pub max_uri_length: Option<usize>,
+
+    /// Respond with 414 if request query string is longer.
+    #[serde(default)]
+    pub max_query_length: Option<usize>,
+
+    /// Proxies (CIDRs) allowed to set the client IP with `X-Forwarded-For`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpCidr>,
+
+    /// Max number of query string and body arguments of a single request.
+    #[serde(default)]
+    pub max_args: Option<usize>,
+
+    /// Max length in bytes of a single text argument.
+    #[serde(default)]
+    pub max_arg_length: Option<usize>,
+
+    /// Respond with 413 if request body is larger (in bytes), gzip bodies are checked
+    /// after decompression.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+
+    /// Respond with 413 if compressed request body inflates to more bytes
+    /// (`max_body_size` still applies).
+    #[serde(default)]
+    pub max_decompressed_size: Option<usize>,
+
+    /// Respond with 413 if compressed request body inflates more times than this.
+    #[serde(default)]
+    pub max_decompression_ratio: Option<usize>,
+
+    /// Respond with 504 if request is not handled within this time (milliseconds).
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Include the database error text into error responses (development only,
+    /// it may leak schema details).
+    #[serde(default)]
+    pub expose_db_errors: bool,
+
+    /// Respond with 500 on a panic in the endpoint handler instead of dropping the request.
+    #[serde(default = "default_catch_panics")]
+    pub catch_panics: bool,
+
+    /// Serialize response record keys sorted by name (otherwise key order is arbitrary).
+    #[serde(default)]
+    pub sort_keys: bool,
+
+    /// Allow clients to request indented JSON with `?pretty=1` (or `?pretty=true`).
+    #[serde(default)]
+    pub allow_pretty: bool,
+
+    /// Query parameter that selects the response format overriding the `Accept` header
+    /// (`json`, `cbor`, `text` or `csv`).
+    #[serde(default = "default_format_arg")]
+    pub format_arg: String,
+
+    /// Add `X-Auth-Id` and `X-Auth-Method` headers to responses of authenticated requests
+    /// (debugging access issues, roles and keys are never exposed).
+    #[serde(default)]
+    pub auth_id_header: bool,
+
+    /// Storage queries that take longer (milliseconds) are logged as warnings.
+    #[serde(default)]
+    pub slow_query_ms: Option<u64>,
+
+    /// Response compression algorithms allowed for negotiation (empty disables compression).
+    #[serde(default)]
+    pub compression: Vec<CompressionAlgorithm>,
+
+    /// Warn on start if there are more endpoints (every worker keeps own copy of each handler).
+    #[serde(default)]
+    pub max_endpoints: Option<usize>,
+
+    /// Fail to start instead of warning when `max_endpoints` is exceeded.
+    #[serde(default)]
+    pub max_endpoints_strict: bool,
+
+    /// Message of the `not_found` error returned for paths without an endpoint.
+    #[serde(default = "default_not_found_message")]
+    pub not_found_message: String,
+
+    /// Built-in endpoint with the build version (`/version` by default).
+    #[serde(default)]
+    pub version_endpoint: VersionEndpointConf,
+
+    /// Built-in liveness and readiness probes (`/livez` and `/readyz` by default).
+    #[serde(default)]
+    pub health_endpoints: HealthEndpointsConf,
+
+    /// Cross-origin requests policy for all endpoints (disabled if not set).
+    #[serde(default)]
+    pub cors: Option<CorsConf>,
 }
 
 impl XepakConf {
@@ -46,6 +180,9 @@ pub struct XepakSpecs {
     pub script: Vec<RhaiScript>,
     #[serde(default)]
     pub endpoint: Vec<EndpointSpecs>,
+    /// Schema entries shared by all endpoints, endpoint `schema` entries override them.
+    #[serde(default)]
+    pub default_schema: Schema,
 }
 
 impl XepakSpecs {
@@ -57,6 +194,16 @@ impl XepakSpecs {
 
         self.script.extend(other.script);
         self.endpoint.extend(other.endpoint);
+        self.default_schema.extend(other.default_schema);
+    }
+
+    /// Merge `default_schema` into each endpoint schema keeping endpoint entries.
+    pub fn apply_default_schema(&mut self) {
+        for ep in &mut self.endpoint {
+            for (name, arg) in &self.default_schema {
+                ep.schema.entry(name.clone()).or_insert_with(|| arg.clone());
+            }
+        }
     }
 
     pub fn validate(&self) -> bool {
@@ -72,8 +219,24 @@ impl XepakSpecs {
 
         let mut ids = HashSet::new();
         for ep in &self.endpoint {
-            if !ids.insert(ep.uri.clone()) {
-                tracing::warn!("Duplicate endpoint for URI: {}", ep.uri);
+            for uri in ep.uri.iter() {
+                if !ids.insert(uri.clone()) {
+                    tracing::warn!("Duplicate endpoint for URI: {uri}");
+                    result = false;
+                }
+            }
+
+            if let ResourceSpecs::QuerySwitch {
+                cases,
+                default: Some(default),
+                ..
+            } = &ep.resource
+                && !cases.contains_key(default)
+            {
+                tracing::warn!(
+                    "Default case \"{default}\" not found in cases for URI: {}",
+                    ep.uri
+                );
                 result = false;
             }
         }
@@ -91,9 +254,38 @@ pub struct RhaiScript {
     pub script: String,
 }
 
+/// Single URI pattern or a list of patterns served by the same endpoint
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EndpointUri {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl EndpointUri {
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        match self {
+            EndpointUri::One(uri) => std::slice::from_ref(uri).iter(),
+            EndpointUri::Many(uris) => uris.iter(),
+        }
+    }
+
+    /// First URI pattern, used where the endpoint needs a single name
+    pub fn first(&self) -> &str {
+        self.iter().next().map(String::as_str).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for EndpointUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let uris: Vec<&str> = self.iter().map(String::as_str).collect();
+        write!(f, "{}", uris.join(", "))
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct EndpointSpecs {
-    pub uri: String,
+    pub uri: EndpointUri,
 
     pub resource: ResourceSpecs,
     /// Expected (allowed) input arguments (URI path args already included)
@@ -112,18 +304,153 @@ pub struct EndpointSpecs {
     #[serde(default = "default_offset_key")]
     pub offset_arg: String,
 
+    /// Enables keyset (cursor) pagination
+    #[serde(default)]
+    pub keyset: Option<KeysetSpecs>,
+
     /// Response will be a single record instead of a list.
     /// Will return 404 if no record available
     #[serde(default)]
     pub single_record_response: bool,
 
+    /// Require `If-Match` header with expected row version, bound as `{{-if-match-}}`.
+    /// Query is executed as an update and 412 is returned if no rows were affected.
+    #[serde(default)]
+    pub if_match: bool,
+
+    /// Respond with 204 No Content (without body) on success.
+    /// Query is executed as a write, see `on_no_rows` for the case when no rows were affected.
+    #[serde(default)]
+    pub no_content: bool,
+
+    /// Response when `no_content` write affected no rows.
+    /// Only resources executing a single write statement support it (not `bulk_insert`,
+    /// `composite` or streamed ones).
+    #[serde(default)]
+    pub on_no_rows: NoRowsPolicy,
+
+    /// Wrap successful response into `{"data": ..., "meta": {...}}` object,
+    /// pagination details are returned in `meta` instead of headers.
+    /// Errors are wrapped into `{"error": {...}}` object.
+    #[serde(default)]
+    pub envelope: bool,
+
+    /// Respond with a single value extracted from JSON column of the first record
+    #[serde(default)]
+    pub json_pointer: Option<JsonPointerSpecs>,
+
+    /// Always respond with indented JSON.
+    #[serde(default)]
+    pub pretty: bool,
+
+    /// Always respond with this content type regardless of the `Accept` header.
+    #[serde(default)]
+    pub force_content_type: Option<ResponseFormat>,
+
+    /// CORS policy replacing the global one for this endpoint.
+    #[serde(default)]
+    pub cors: Option<CorsConf>,
+
+    /// Respond with an object of records keyed by this column value instead of a list.
+    #[serde(default)]
+    pub key_by: Option<String>,
+
+    /// What to do when `key_by` column has the same value in more than one record.
+    #[serde(default)]
+    pub on_duplicate_key: DuplicateKeyPolicy,
+
+    /// Allow clients to request only some of record fields with `fields` argument
+    /// (comma-separated names, unknown names are ignored).
+    #[serde(default)]
+    pub allow_sparse_fields: bool,
+
+    /// Integers with absolute value above this are serialized as strings in JSON responses.
+    /// Use 9007199254740991 (2^53 - 1) to stay safe for JavaScript clients.
+    #[serde(default)]
+    pub json_big_int_as_string: Option<u64>,
+
+    /// Data source for read (`SELECT`) queries instead of the resource one (e.g. replica)
+    #[serde(default)]
+    pub read_data_source: Option<String>,
+
+    /// Data source for any other queries instead of the resource one (e.g. primary)
+    #[serde(default)]
+    pub write_data_source: Option<String>,
+
+    /// Keep responses of write requests with `Idempotency-Key` header for this time (seconds),
+    /// repeated request with the same key gets stored response without executing the query
+    /// (409 while the first request with the key is still running).
+    #[serde(default)]
+    pub idempotency_ttl_secs: Option<u64>,
+
+    /// Requests handled at the same time, the rest wait for `concurrency_wait_ms`
+    /// (or are not queued at all) and get 503.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+
+    /// Time to wait for a free slot when `max_concurrency` is reached (milliseconds).
+    #[serde(default)]
+    pub concurrency_wait_ms: Option<u64>,
+
+    /// Pool connections this endpoint may hold at the same time, requests over the cap
+    /// wait for a connection used by this endpoint instead of taking one from the pool.
+    /// Streamed responses (CSV, SSE, WebSocket) count until they are finished.
+    #[serde(default)]
+    pub max_db_conns: Option<usize>,
+
+    /// Argument sources by precedence for args present in more than one of them,
+    /// default is `["path", "body", "query"]`. When set, query string args are read
+    /// for requests with body too (otherwise they are skipped).
+    #[serde(default)]
+    pub arg_precedence: Option<Vec<ArgSource>>,
+
+    /// Stream query rows as CSV for requests accepting `text/csv`, rows are written as they
+    /// are fetched (the header is taken from the first row columns).
+    #[serde(default)]
+    pub stream_csv: bool,
+
+    /// Columns of the tabular (CSV) output in this order, other columns are dropped
+    /// and missing ones are empty.
+    #[serde(default)]
+    pub columns: Vec<String>,
+
+    /// Log input args and response body of this endpoint at info level
+    /// (secret-like fields such as `password` or `token` are redacted).
+    #[serde(default)]
+    pub debug_io: bool,
+
+    /// Logged args and response are truncated to this number of characters.
+    #[serde(default = "default_debug_io_max_length")]
+    pub debug_io_max_length: usize,
+
+    /// Round float values in the response to this number of decimal digits.
+    #[serde(default)]
+    pub float_precision: Option<u32>,
+
     /// This logic handle requests to extract/validate data
     #[serde(default)]
     pub processor: Vec<PreProcessor>,
 
+    /// Restrict rows visible to the caller
+    #[serde(default)]
+    pub row_filter: Option<RowFilterSpecs>,
+
+    /// Report all invalid arguments at once in the `errors` list of the response
+    /// (`[{"field": ..., "message": ...}]`) instead of failing on the first one.
+    #[serde(default)]
+    pub collect_validation_errors: bool,
+
+    /// Reject (400) any query string or body argument that is not defined in the `schema`.
+    /// Path arguments captured from the URI pattern are always allowed.
+    /// Note that pagination arguments (limit, offset, cursor) must be in the schema too.
     #[serde(default)]
     pub strict_schema: bool,
 
+    /// Reject (400) query string parameters that are not in `args` or `schema`.
+    /// Pagination (limit, offset, cursor) and `pretty` parameters are always allowed.
+    #[serde(default)]
+    pub reject_unknown_query: bool,
+
     #[serde(default)]
     pub schema: Schema,
 }
@@ -144,6 +471,195 @@ pub enum ResourceSpecs {
         data_source: String,
         script: String,
     },
+
+    /// Select one of the vetted queries by the argument value.
+    /// Useful for things that can't be bound as query parameters (e.g. sort column).
+    QuerySwitch {
+        #[serde(default)]
+        data_source: String,
+        /// Argument name which value selects a query from `cases`
+        on_arg: String,
+        cases: HashMap<String, String>,
+        /// Case used when argument is not provided
+        #[serde(default)]
+        default: Option<String>,
+    },
+
+    /// Generates `UPDATE` that sets only columns provided in the request.
+    /// Columns not in the `columns` allowlist are ignored, request without any of them is 400.
+    PartialUpdate {
+        #[serde(default)]
+        data_source: String,
+        table: String,
+        /// Updatable columns (argument names are the same)
+        columns: Vec<String>,
+        /// Key columns for the WHERE clause, all of them must be provided
+        key: Vec<String>,
+        /// Columns returned from updated rows (`RETURNING` clause)
+        #[serde(default)]
+        returning: Vec<String>,
+    },
+
+    /// Inserts a single row with columns provided in the request (others get DB defaults).
+    /// Responds with `{"inserted": <count>}` or with the full new row if `return_row` is set.
+    Insert {
+        #[serde(default)]
+        data_source: String,
+        table: String,
+        /// Insertable columns (argument names are the same)
+        columns: Vec<String>,
+        /// Return the inserted row with DB generated values (`RETURNING *`)
+        #[serde(default)]
+        return_row: bool,
+    },
+
+    /// Inserts every object from JSON array body as a row within a single transaction.
+    /// Responds with `{"inserted": <count>}`, any failure rolls back all rows.
+    BulkInsert {
+        #[serde(default)]
+        data_source: String,
+        table: String,
+        /// Inserted columns, each object must have all of them
+        columns: Vec<String>,
+    },
+
+    /// Executes independent queries concurrently (each could use own data source)
+    /// and combines results into a single object keyed by part name.
+    /// Parts depending on values exposed by previous parts are executed sequentially.
+    Composite { parts: Vec<QueryPart> },
+
+    /// WebSocket that pushes query results (JSON text or CBOR binary frames as requested)
+    /// right after connect and then every `poll_ms` until the client disconnects.
+    WebSocketQuery {
+        #[serde(default)]
+        data_source: String,
+        query: String,
+        #[serde(default = "default_poll_ms")]
+        poll_ms: u64,
+    },
+
+    /// Server-Sent Events stream with JSON query results as the `data` of an event
+    /// sent right away and then every `poll_ms` until the client disconnects.
+    SseQuery {
+        #[serde(default)]
+        data_source: String,
+        query: String,
+        #[serde(default = "default_poll_ms")]
+        poll_ms: u64,
+    },
+
+    /// Responds with the literal `body` (JSON or CBOR as requested) without touching storage.
+    Static {
+        body: serde_json::Value,
+        /// Response status code, 200 by default
+        #[serde(default)]
+        status: Option<u16>,
+    },
+
+    /// Responds with redirect to the `to` location without touching storage.
+    /// Path args are interpolated into the target, e.g. `/v2/users/{id}`.
+    Redirect {
+        to: String,
+        /// 301 Moved Permanently instead of 302 Found
+        #[serde(default)]
+        permanent: bool,
+    },
+}
+
+/// Response for the write that affected no rows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoRowsPolicy {
+    /// 204 anyway
+    #[default]
+    NoContent,
+    /// 404 Not Found
+    NotFound,
+    /// 409 Conflict
+    Conflict,
+}
+
+/// Response body encoding by the content type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum ResponseFormat {
+    #[serde(rename = "application/json")]
+    Json,
+    #[serde(rename = "application/cbor")]
+    Cbor,
+    /// Text value as is, anything else as JSON text
+    #[serde(rename = "text/plain")]
+    Text,
+    /// Records as CSV rows with a header line
+    #[serde(rename = "text/csv")]
+    Csv,
+}
+
+impl ResponseFormat {
+    /// Format by the short name used in the query string (e.g. `?format=csv`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "cbor" => Some(Self::Cbor),
+            "text" => Some(Self::Text),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// Records with the same `key_by` value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateKeyPolicy {
+    /// Log a warning and keep the last record
+    #[default]
+    LastWins,
+    /// Respond with 500
+    Error,
+}
+
+/// Extract value from JSON text stored in the `column` by JSON Pointer (RFC 6901),
+/// e.g. `/address/city`. Missing column or path results in 404.
+#[derive(Clone, Debug, Deserialize)]
+pub struct JsonPointerSpecs {
+    pub column: String,
+
+    pub pointer: String,
+}
+
+/// Placeholder in the query replaced with the row filter condition.
+pub const ROW_FILTER_PLACEHOLDER: &str = "{{-row-filter-}}";
+
+/// Row filter condition is inserted into query at the [`ROW_FILTER_PLACEHOLDER`]
+/// (e.g. `WHERE {{-row-filter-}}`) unless the caller passes any of `unless` checks.
+/// Condition may use key args like `owner = {{-auth-id-}}`, they are bound as usual.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RowFilterSpecs {
+    pub condition: String,
+
+    /// Callers passing any of these checks see all rows (e.g. admins)
+    #[serde(default)]
+    pub unless: Vec<CheckAuthConf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct QueryPart {
+    /// Key of the part result in the response object
+    pub name: String,
+
+    #[serde(default)]
+    pub data_source: String,
+
+    pub query: String,
+
+    /// Part result will be a single record (or null) instead of a list.
+    #[serde(default)]
+    pub single_record: bool,
+
+    /// Columns of the first result record available to the next parts as `{{@<name>.<column>}}`
+    /// (null if there are no records). Parts are executed one by one if any part exposes columns.
+    #[serde(default)]
+    pub expose: Vec<String>,
 }
 
 pub fn load_conf_file(file_path: &str) -> Result<XepakConf, XepakError> {
@@ -170,7 +686,12 @@ pub fn load_specs_from_dir(dir_path: PathBuf) -> Result<XepakSpecs, XepakError>
     for entry in dir_content {
         let entry = entry.map_err(Arc::new)?;
         let path = entry.path();
-        if !path.is_file() || path.extension().unwrap_or_default().to_ascii_lowercase() != "toml" {
+        if !path.is_file()
+            || !path
+                .extension()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case("toml")
+        {
             continue;
         }
         let buf = fs::read(&path)
@@ -195,6 +716,30 @@ fn default_specs_dir() -> PathBuf {
     PathBuf::from("./specs")
 }
 
+fn default_catch_panics() -> bool {
+    true
+}
+
+fn default_poll_ms() -> u64 {
+    1000
+}
+
+fn default_debug_io_max_length() -> usize {
+    2048
+}
+
+fn default_format_arg() -> String {
+    "format".to_string()
+}
+
+fn default_max_body_size() -> usize {
+    crate::server::body::DEFAULT_MAX_BODY_SIZE
+}
+
+fn default_not_found_message() -> String {
+    "Endpoint not found".to_string()
+}
+
 fn default_limit_key() -> String {
     "limit".to_string()
 }
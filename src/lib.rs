@@ -1,19 +1,30 @@
+#[cfg(feature = "server")]
+pub mod audit;
+#[cfg(feature = "server")]
 pub mod auth;
+#[cfg(feature = "server")]
 pub mod cfg;
 pub mod schema;
+#[cfg(feature = "server")]
 pub mod script;
+#[cfg(feature = "server")]
 pub mod server;
-mod sql_key_args;
+pub mod sql_key_args;
+#[cfg(feature = "server")]
 pub mod storage;
 pub mod types;
 
+#[cfg(all(test, feature = "server"))]
+mod testing;
+
 use std::sync::Arc;
 
+#[cfg(feature = "server")]
 use rhai::{EvalAltResult, ParseError};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::types::XepakType;
+use crate::{schema::ArgError, types::XepakType};
 
 /*
 
@@ -35,12 +46,48 @@ pub enum XepakError {
     #[error("Input error: {0}")]
     Input(String),
 
+    /// Input validation failures for every invalid argument
+    #[error("Input error: {}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))]
+    InvalidInput(Vec<ArgError>),
+
     #[error("Record not found: {0}")]
     NotFound(String),
 
     #[error("{0}")]
     Forbidden(String),
 
+    /// Service can't handle request right now, but it may recover later
+    #[error("{0}")]
+    Unavailable(String),
+
+    /// Request URI or query string is too long
+    #[error("{0}")]
+    UriTooLong(String),
+
+    /// Request was not handled in time
+    #[error("{0}")]
+    Timeout(String),
+
+    /// Request precondition (e.g. `If-Match` version) does not match current state
+    #[error("{0}")]
+    PreconditionFailed(String),
+
+    /// Request conflicts with the current state (e.g. write affected no rows)
+    #[error("{0}")]
+    Conflict(String),
+
+    /// Request body is larger than allowed (after decompression)
+    #[error("{0}")]
+    PayloadTooLarge(String),
+
+    /// Requested byte range is malformed or outside of the response body
+    #[error("{0}")]
+    RangeNotSatisfiable(String),
+
+    /// Request body content type or encoding is not supported
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+
     /// Server error with message that will be displayed to client
     #[error("{0}")]
     WeScrewed(String),
@@ -51,12 +98,18 @@ pub enum XepakError {
     #[error("Can't covert: {0}")]
     Convert(String),
 
+    #[cfg(feature = "server")]
+    #[error("Storage error: {0}")]
+    Storage(#[from] Arc<sqlx::Error>),
+
     #[error("Decode error: {0}")]
     Decode(String),
 
+    #[cfg(feature = "server")]
     #[error("Script parse error: {0}")]
     ScriptParse(#[from] ParseError),
 
+    #[cfg(feature = "server")]
     #[error("Script execution error: {0}")]
     Script(#[from] Arc<EvalAltResult>),
 
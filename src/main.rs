@@ -2,8 +2,11 @@ use std::{io, path::PathBuf, sync::Arc};
 
 use xepak_rest::{
     XepakError,
-    cfg::{load_conf_file, load_specs_from_dir},
-    server::init_server,
+    audit::audit_specs,
+    cfg::{XepakSpecs, load_conf_file, load_specs_from_dir},
+    server::{
+        init_app_data, init_server_with_data, remove_unix_socket, shutdown, unix_socket_path,
+    },
 };
 
 const ENV_PORT: &str = "XEPAK_PORT";
@@ -39,7 +42,7 @@ async fn main() -> Result<(), XepakError> {
 
     let conf_dir = PathBuf::from(&file_path)
         .parent()
-        .expect(format!("Can't get a dir for path {file_path}").as_str())
+        .unwrap_or_else(|| panic!("Can't get a dir for path {file_path}"))
         .to_path_buf();
 
     let specs_dir = if xepak_conf.specs_dir.is_relative() {
@@ -52,9 +55,20 @@ async fn main() -> Result<(), XepakError> {
 
     tracing::debug!("Specs: {xepak_specs:?}");
 
-    let server = init_server(conf_dir, xepak_conf, xepak_specs).await?;
+    if args.audit {
+        return audit(&xepak_specs);
+    }
+
+    let unix_socket = unix_socket_path(&conf_dir, &xepak_conf);
+    let app_data = init_app_data(&conf_dir, &xepak_conf).await?;
+    let server = init_server_with_data(conf_dir, xepak_conf, xepak_specs, app_data.clone())?;
 
-    server.await.map_err(Arc::new)?;
+    let result = server.await;
+    shutdown(&app_data).await;
+    if let Some(path) = unix_socket {
+        remove_unix_socket(&path);
+    }
+    result.map_err(Arc::new)?;
 
     Ok(())
 }
@@ -65,13 +79,40 @@ struct AppArgs {
     port: Option<u16>,
     log: Option<String>,
     config_file: Option<String>,
+    /// Report risky query building patterns in specs instead of starting the server
+    audit: bool,
+}
+
+/// Print audit findings, any finding fails the run.
+fn audit(specs: &XepakSpecs) -> Result<(), XepakError> {
+    let findings = audit_specs(specs);
+    for finding in &findings {
+        println!("{finding}");
+    }
+    if findings.is_empty() {
+        println!("No risky patterns found");
+        Ok(())
+    } else {
+        Err(XepakError::Cfg(format!(
+            "Audit found {} risky pattern(s)",
+            findings.len()
+        )))
+    }
 }
 
 fn parse_cli_args() -> io::Result<AppArgs> {
-    let cli = getopt3::new(getopt3::hideBin(std::env::args()), "p:l:");
+    // getopt3 has no long options, so `--audit` is taken out beforehand
+    let mut argv: Vec<String> = getopt3::hideBin(std::env::args()).into_iter().collect();
+    let audit = argv.iter().any(|a| a == "--audit");
+    argv.retain(|a| a != "--audit");
+
+    let cli = getopt3::new(argv, "p:l:");
     match cli {
         Ok(opts) => {
-            let mut args: AppArgs = Default::default();
+            let mut args: AppArgs = AppArgs {
+                audit,
+                ..Default::default()
+            };
             if let Some(port_str) = opts.options.get(&'p') {
                 let port_num = port_str
                     .parse::<u16>()
@@ -92,12 +133,12 @@ fn parse_cli_args() -> io::Result<AppArgs> {
 }
 
 fn update_from_env(mut args: AppArgs) -> AppArgs {
-    if args.port.is_none() {
-        if let Some(pn) = std::env::var(ENV_PORT).ok().map(|p| p.parse::<u16>()) {
-            match pn {
-                Ok(p) => args.port = Some(p),
-                Err(e) => tracing::error!("Can't parse port number: {}", e),
-            }
+    if args.port.is_none()
+        && let Some(pn) = std::env::var(ENV_PORT).ok().map(|p| p.parse::<u16>())
+    {
+        match pn {
+            Ok(p) => args.port = Some(p),
+            Err(e) => tracing::error!("Can't parse port number: {}", e),
         }
     }
 
@@ -1,10 +1,10 @@
 use std::collections::HashMap;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     XepakError,
-    types::{XepakType, XepakValue},
+    types::{DateTimeFormat, XepakType, XepakValue},
 };
 
 /// Schema for input/output arguments
@@ -12,7 +12,7 @@ pub type Schema = HashMap<String, ArgSchema>;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ArgSchema {
-    /// Type of the argument
+    /// Type of the argument (`boolean` also maps the result column of the same name)
     #[serde(default, rename = "type")]
     pub ty: XepakType,
 
@@ -25,6 +25,45 @@ pub struct ArgSchema {
 
     #[serde(default)]
     pub validate: Vec<ArgSchemaValidator>,
+
+    /// Allowed values for the `enum` type
+    #[serde(default)]
+    pub variants: Vec<String>,
+
+    /// Allowed identifiers (e.g. column names) for `{{!arg}}` query placeholders.
+    #[serde(default)]
+    pub identifiers: Vec<String>,
+
+    /// Quote the identifier for the storage backend (e.g. `"name"`) when inserted into query.
+    #[serde(default)]
+    pub quote_identifier: bool,
+
+    /// Accepted input formats of the `date_time` type (RFC 3339 by default)
+    #[serde(default)]
+    pub format: DateTimeFormats,
+}
+
+/// Single date/time format or a list of formats tried in order
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum DateTimeFormats {
+    One(DateTimeFormat),
+    Many(Vec<DateTimeFormat>),
+}
+
+impl Default for DateTimeFormats {
+    fn default() -> Self {
+        Self::One(DateTimeFormat::Rfc3339)
+    }
+}
+
+impl DateTimeFormats {
+    pub fn as_slice(&self) -> &[DateTimeFormat] {
+        match self {
+            Self::One(format) => std::slice::from_ref(format),
+            Self::Many(formats) => formats,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Deserialize)]
@@ -64,6 +103,21 @@ pub enum ArgSchemaValidator {
     },
 }
 
+/// Reject client supplied (query or body) argument with the output scope.
+/// Read-only values (e.g. generated ids) must not be set by clients,
+/// path arguments come from the route and are not checked.
+pub fn reject_output_arg(schema: &Schema, arg_name: &str) -> Result<(), XepakError> {
+    match schema.get(arg_name) {
+        Some(ArgSchema {
+            scope: ArgSchemaScope::Output,
+            ..
+        }) => Err(XepakError::Input(format!(
+            "Argument \"{arg_name}\" is output only and can't be set"
+        ))),
+        _ => Ok(()),
+    }
+}
+
 /// Convert [`XepakValue`] to another [`XepakValue`] according to the [`Schema`].
 /// Note that null is always converts ot itself.
 /// If argument name is not in the schema, it will not be converted (if `strict` is false).
@@ -74,7 +128,6 @@ pub fn convert_with_schema(
     value: XepakValue,
     strict: bool,
 ) -> Result<XepakValue, XepakError> {
-    // TODO dealt with input scope here somehow
     let Some(aschema) = schema.get(arg_name) else {
         if strict {
             return Err(XepakError::Input(format!(
@@ -90,24 +143,66 @@ pub fn convert_with_schema(
         return Ok(value);
     }
 
-    Ok(match aschema.ty {
+    let converted = match aschema.ty {
         XepakType::Null => {
             return Err(XepakError::Input(format!(
                 "Can't convert argument \"{arg_name}\" to Null type! Why are you doing this?"
             )));
         }
-        XepakType::Text => XepakValue::Text(value.as_string()),
-        XepakType::Boolean => XepakValue::Boolean(value.as_bool()?),
-        XepakType::Int => XepakValue::Integer(value.as_int()?),
-        XepakType::Float => XepakValue::Float(value.as_float()?),
+        XepakType::Text => Ok(XepakValue::Text(value.as_string())),
+        XepakType::Boolean => value.as_bool().map(XepakValue::Boolean),
+        XepakType::Int => value.as_int().map(XepakValue::Integer),
+        XepakType::Float => value.as_float().map(XepakValue::Float),
+        XepakType::Uuid => value.as_uuid().map(XepakValue::Uuid),
+        XepakType::DateTime => value
+            .as_datetime(aschema.format.as_slice())
+            .map(XepakValue::Text),
+        XepakType::Enum => return to_enum_variant(aschema, arg_name, &value),
+    };
+
+    // Value came from the client, so it is an input error
+    converted.map_err(|e| {
+        XepakError::Input(format!(
+            "Argument \"{arg_name}\" is not a valid {}: {e}",
+            aschema.ty
+        ))
     })
 }
 
+/// Match value with enum variants (case insensitive) and return the variant as declared.
+fn to_enum_variant(
+    aschema: &ArgSchema,
+    arg_name: &str,
+    value: &XepakValue,
+) -> Result<XepakValue, XepakError> {
+    let text = value.as_string();
+    let variant = aschema.variants.iter().find(|v| **v == text).or_else(|| {
+        aschema
+            .variants
+            .iter()
+            .find(|v| v.eq_ignore_ascii_case(&text))
+    });
+
+    match variant {
+        Some(v) => Ok(XepakValue::Text(v.clone())),
+        None => Err(XepakError::Input(format!(
+            "Argument \"{arg_name}\" must be one of [{}], but got \"{text}\"",
+            aschema.variants.join(", ")
+        ))),
+    }
+}
+
+/// Apply schema validators to the values and return the first error.
+/// Values are checked in the order of argument names, so the same input always gives the same error.
 pub fn validate_with_schema(
     schema: &Schema,
     values: &HashMap<String, XepakValue>,
 ) -> Result<(), XepakError> {
-    for (arg_name, value) in values {
+    let mut names: Vec<&String> = values.keys().collect();
+    names.sort();
+
+    for arg_name in names {
+        let value = &values[arg_name];
         if let Some(arg_schema) = schema.get(arg_name) {
             for validator in &arg_schema.validate {
                 apply_validator(validator, arg_name, value)?;
@@ -117,6 +212,44 @@ pub fn validate_with_schema(
     Ok(())
 }
 
+/// Validation failure of a single argument
+#[derive(Debug, Clone, Serialize)]
+pub struct ArgError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Apply schema validators to all values and collect failures instead of returning the first one.
+/// Only the first failed validator is reported for each argument.
+pub fn collect_schema_errors(
+    schema: &Schema,
+    values: &HashMap<String, XepakValue>,
+    errors: &mut Vec<ArgError>,
+) {
+    let mut names: Vec<&String> = values.keys().collect();
+    names.sort();
+
+    for arg_name in names {
+        let Some(arg_schema) = schema.get(arg_name) else {
+            continue;
+        };
+        let value = &values[arg_name];
+        for validator in &arg_schema.validate {
+            if let Err(err) = apply_validator(validator, arg_name, value) {
+                let message = match err {
+                    XepakError::Input(msg) => msg,
+                    err => err.to_string(),
+                };
+                errors.push(ArgError {
+                    field: arg_name.clone(),
+                    message,
+                });
+                break;
+            }
+        }
+    }
+}
+
 pub fn apply_validator(
     validator: &ArgSchemaValidator,
     name: &str,
@@ -217,3 +350,91 @@ pub fn apply_validator(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_schema() -> Schema {
+        toml::from_str(
+            r#"
+            status = { type = "enum", variants = ["active", "disabled"] }
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn enum_in_set_value() {
+        let schema = status_schema();
+
+        let value = convert_with_schema(&schema, "status", "active".into(), true).unwrap();
+        assert_eq!(value.as_string(), "active");
+
+        // Normalized to the declared variant
+        let value = convert_with_schema(&schema, "status", "DISABLED".into(), true).unwrap();
+        assert_eq!(value.as_string(), "disabled");
+
+        let value = convert_with_schema(&schema, "status", XepakValue::Null, true).unwrap();
+        assert!(value.is_null());
+    }
+
+    #[test]
+    fn output_scope_arg_rejected() {
+        let schema: Schema = toml::from_str(
+            r#"
+            id = { type = "int", scope = "output" }
+            title = { type = "text", scope = "input" }
+            "#,
+        )
+        .unwrap();
+
+        let err = reject_output_arg(&schema, "id").unwrap_err();
+        assert!(matches!(err, XepakError::Input(msg) if msg.contains("\"id\"")));
+        reject_output_arg(&schema, "title").unwrap();
+        reject_output_arg(&schema, "unknown").unwrap();
+
+        // Conversion itself is scope agnostic, e.g. for path args
+        let value = convert_with_schema(&schema, "id", "1".into(), false).unwrap();
+        assert!(matches!(value, XepakValue::Integer(1)));
+    }
+
+    #[test]
+    fn datetime_accepted_formats() {
+        let schema: Schema = toml::from_str(
+            r#"
+            at = { type = "date_time", format = ["rfc3339", "date", "epoch"] }
+            strict_at = { type = "date_time" }
+            "#,
+        )
+        .unwrap();
+
+        for (input, expected) in [
+            ("2024-05-01T10:30:00+02:00".into(), "2024-05-01T08:30:00Z"),
+            ("2024-05-01".into(), "2024-05-01T00:00:00Z"),
+            ("1714552200".into(), "2024-05-01T08:30:00Z"),
+            (XepakValue::Integer(1714552200), "2024-05-01T08:30:00Z"),
+        ] {
+            let value = convert_with_schema(&schema, "at", input, true).unwrap();
+            assert_eq!(value.as_string(), expected);
+        }
+
+        // Only RFC 3339 by default
+        let err = convert_with_schema(&schema, "strict_at", "2024-05-01".into(), true).unwrap_err();
+        assert!(matches!(err, XepakError::Input(msg) if msg.contains("rfc3339")));
+
+        let err = convert_with_schema(&schema, "at", "01/05/2024".into(), true).unwrap_err();
+        assert!(matches!(err, XepakError::Input(_)));
+    }
+
+    #[test]
+    fn enum_out_of_set_value() {
+        let schema = status_schema();
+
+        let err = convert_with_schema(&schema, "status", "deleted".into(), true).unwrap_err();
+        assert!(matches!(err, XepakError::Input(msg) if msg.contains("\"deleted\"")));
+
+        let err = convert_with_schema(&schema, "status", XepakValue::Integer(1), true).unwrap_err();
+        assert!(matches!(err, XepakError::Input(_)));
+    }
+}
@@ -4,13 +4,13 @@ use actix_web::web::Data;
 use rhai::{
     AST, CustomType, Dynamic, Engine, EvalAltResult, NativeCallContext, ParseError, Position, Scope,
 };
-use sqlx_core::logger;
 use tokio::runtime::Handle;
 
 use crate::{
     XepakError,
     server::{RequestInput, XepakAppData},
-    storage::ResourceRequest,
+    sql_key_args::ParametrizedQueryRef,
+    storage::{IDENT_ARG_PREFIX, ResourceRequest, SqlDialect, Storage},
     types::XepakValue,
 };
 
@@ -36,6 +36,37 @@ impl RhaiRequestContext {
             None => Dynamic::UNIT,
         }
     }
+
+    pub fn path(&mut self) -> String {
+        self.args.get_path().to_string()
+    }
+
+    /// Endpoint URI pattern, e.g. `/user/{id}`
+    pub fn route(&mut self) -> String {
+        self.args.get_route().to_string()
+    }
+
+    pub fn method(&mut self) -> String {
+        self.args.get_method().to_string()
+    }
+
+    /// Page size parsed from the limit argument (`fetch_limit` if not provided)
+    pub fn limit(&mut self) -> i64 {
+        self.args.get_limit() as i64
+    }
+
+    /// Rows to skip parsed from the offset argument
+    pub fn offset(&mut self) -> i64 {
+        self.args.get_offset() as i64
+    }
+
+    /// Header value (case-insensitive name) or `()` if not present
+    pub fn header(&mut self, name: &str) -> Dynamic {
+        match self.args.get_header(name) {
+            Some(v) => Dynamic::from(v.to_string()),
+            None => Dynamic::UNIT,
+        }
+    }
 }
 
 impl CustomType for RhaiRequestContext {
@@ -43,6 +74,12 @@ impl CustomType for RhaiRequestContext {
         builder.with_name("RhaiRequestContext");
         builder.with_fn("has_arg", Self::has_arg);
         builder.with_fn("get_arg", Self::get_arg);
+        builder.with_fn("path", Self::path);
+        builder.with_fn("route", Self::route);
+        builder.with_fn("method", Self::method);
+        builder.with_fn("header", Self::header);
+        builder.with_fn("limit", Self::limit);
+        builder.with_fn("offset", Self::offset);
     }
 }
 
@@ -136,6 +173,10 @@ pub fn build_rhai_engine(state: &XepakAppData) -> Engine {
         tracing::debug!("RHAI: {} @ {pos:?} > {s}", src.unwrap_or_default());
     });
 
+    rhai.register_fn("log_info", log_info);
+    rhai.register_fn("log_warn", log_warn);
+    rhai.register_fn("log_error", log_error);
+
     rhai.build_type::<RhaiRequestContext>();
     rhai.build_type::<RhaiQueryBuilder>();
     rhai.build_type::<XepakValue>();
@@ -174,9 +215,137 @@ pub fn build_rhai_engine(state: &XepakAppData) -> Engine {
         },
     );
 
+    // Default storage dialect, scripts query only the default storage
+    let dialect = state
+        .get_data_source("")
+        .map_or(SqlDialect::Sqlite, Storage::get_dialect);
+    rhai.register_fn(
+        "quote_ident",
+        move |ctx: NativeCallContext, name: &str| -> Result<String, Box<EvalAltResult>> {
+            dialect
+                .quote_ident(name)
+                .map_err(|err| to_eval_alt_result_ctx(err, Some(ctx)))
+        },
+    );
+
+    rhai.register_fn("with_missing_as_null", with_missing_as_null);
+    rhai.register_fn("url_decode", url_decode);
+    rhai.register_fn("parse_query", parse_query);
+    rhai.register_fn("json_parse", json_parse);
+    rhai.register_fn("json_stringify", json_stringify);
+
     rhai
 }
 
+/// Add unit `()` (bound as SQL NULL) for every argument referenced by the query
+/// but absent in `args`, so conditionally built args maps could be passed as is.
+/// Reserved (`{{-limit-}}`) and identifier (`{{!name}}`) arguments are skipped.
+pub fn with_missing_as_null(query: &str, mut args: rhai::Map) -> rhai::Map {
+    let pquery = ParametrizedQueryRef::new(query);
+    for arg in pquery.get_args() {
+        if arg.starts_with('-') || arg.starts_with(IDENT_ARG_PREFIX) {
+            continue;
+        }
+        if !args.contains_key(*arg) {
+            args.insert((*arg).into(), Dynamic::UNIT);
+        }
+    }
+    args
+}
+
+/// Decode percent-encoded string (`+` is kept as is).
+pub fn url_decode(s: &str) -> Result<String, Box<EvalAltResult>> {
+    percent_encoding::percent_decode_str(s)
+        .decode_utf8()
+        .map(|v| v.into_owned())
+        .map_err(|e| to_eval_alt_result(XepakError::Decode(format!("Can't URL-decode: {e}"))))
+}
+
+/// Parse query string (`a=1&b=2`) into a map of decoded strings, last duplicate key wins.
+pub fn parse_query(s: &str) -> Result<rhai::Map, Box<EvalAltResult>> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(s).map_err(|e| {
+        to_eval_alt_result(XepakError::Decode(format!("Can't parse query string: {e}")))
+    })?;
+
+    Ok(pairs
+        .into_iter()
+        .map(|(k, v)| (k.into(), Dynamic::from(v)))
+        .collect())
+}
+
+pub fn json_parse(s: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+    let value: serde_json::Value = serde_json::from_str(s)
+        .map_err(|e| to_eval_alt_result(XepakError::Decode(format!("Can't parse JSON: {e}"))))?;
+    Ok(json_to_dynamic(value))
+}
+
+pub fn json_stringify(value: Dynamic) -> Result<String, Box<EvalAltResult>> {
+    let value = dynamic_to_json(&value).map_err(to_eval_alt_result)?;
+    Ok(value.to_string())
+}
+
+/// Integers that fit `i64` become Rhai int, other numbers are floats.
+pub fn json_to_dynamic(value: serde_json::Value) -> Dynamic {
+    match value {
+        serde_json::Value::Null => Dynamic::UNIT,
+        serde_json::Value::Bool(v) => Dynamic::from_bool(v),
+        serde_json::Value::Number(v) => match v.as_i64() {
+            Some(i) => Dynamic::from_int(i),
+            None => Dynamic::from_float(v.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(v) => Dynamic::from(v),
+        serde_json::Value::Array(values) => {
+            Dynamic::from_array(values.into_iter().map(json_to_dynamic).collect())
+        }
+        serde_json::Value::Object(values) => Dynamic::from_map(
+            values
+                .into_iter()
+                .map(|(k, v)| (k.into(), json_to_dynamic(v)))
+                .collect(),
+        ),
+    }
+}
+
+pub fn dynamic_to_json(v: &Dynamic) -> Result<serde_json::Value, XepakError> {
+    let r = if v.is_unit() {
+        serde_json::Value::Null
+    } else if let Ok(b) = v.as_bool() {
+        serde_json::Value::Bool(b)
+    } else if let Ok(i) = v.as_int() {
+        serde_json::Value::from(i)
+    } else if let Ok(f) = v.as_float() {
+        serde_json::Number::from_f64(f)
+            .map(serde_json::Value::Number)
+            .ok_or_else(|| XepakError::Convert(format!("{f} can't be represented in JSON")))?
+    } else if v.is_char() || v.is_string() {
+        serde_json::Value::String(v.to_string())
+    } else if let Some(values) = v.read_lock::<rhai::Array>() {
+        serde_json::Value::Array(
+            values
+                .iter()
+                .map(dynamic_to_json)
+                .collect::<Result<_, _>>()?,
+        )
+    } else if let Some(values) = v.read_lock::<rhai::Map>() {
+        serde_json::Value::Object(
+            values
+                .iter()
+                .map(|(k, v)| Ok((k.to_string(), dynamic_to_json(v)?)))
+                .collect::<Result<_, XepakError>>()?,
+        )
+    } else {
+        return Err(XepakError::Convert(format!(
+            "{} can't be converted to JSON",
+            v.type_name()
+        )));
+    };
+
+    Ok(r)
+}
+
+/// Convert script args map into request args.
+/// Unit `()` value is bound as SQL NULL, while a key absent in the map is a missing argument,
+/// and query referencing it fails to bind (use `with_missing_as_null` to bind them as NULL).
 fn prepare_args(args_in: rhai::Map) -> Result<HashMap<String, XepakValue>, XepakError> {
     let mut args = HashMap::new();
 
@@ -268,7 +437,7 @@ pub fn storage_query_value(
 }
 
 pub async fn execute_script_blocking(
-    state: Data<XepakAppData>,
+    _state: Data<XepakAppData>,
     uri: String,
     rhai: Arc<Option<Engine>>,
     ast: Arc<Option<AST>>,
@@ -290,23 +459,21 @@ pub async fn execute_script_blocking(
 
         match rhai.eval_ast_with_scope::<Dynamic>(&mut scope, ast) {
             Ok(result) => Ok(result),
-            Err(e) => {
-                return Err(if let EvalAltResult::ErrorRuntime(ref value, pos) = *e {
-                    if let Some(xerror) = value.clone().try_cast::<XepakError>() {
-                        // no need to log here, this could be an expected behavior
-                        if !xerror.is_expectable() {
-                            tracing::error!("Script {pos}: {xerror}");
-                        }
-                        xerror
-                    } else {
-                        tracing::error!("Script {pos}: {e}");
-                        Arc::new(*e).into()
+            Err(e) => Err(if let EvalAltResult::ErrorRuntime(ref value, pos) = *e {
+                if let Some(xerror) = value.clone().try_cast::<XepakError>() {
+                    // no need to log here, this could be an expected behavior
+                    if !xerror.is_expectable() {
+                        tracing::error!("Script {pos}: {xerror}");
                     }
+                    xerror
                 } else {
-                    tracing::error!("Script execution: {e}");
+                    tracing::error!("Script {pos}: {e}");
                     Arc::new(*e).into()
-                });
-            }
+                }
+            } else {
+                tracing::error!("Script execution: {e}");
+                Arc::new(*e).into()
+            }),
         }
     })
     .await
@@ -320,6 +487,7 @@ pub fn xepak_to_dynamic(value: &XepakValue) -> Dynamic {
         XepakValue::Integer(v) => Dynamic::from_int(*v as i64),
         XepakValue::Float(v) => Dynamic::from_float(*v),
         XepakValue::Text(v) => Dynamic::from(v.clone()),
+        XepakValue::Uuid(v) => Dynamic::from(v.to_string()),
     }
 }
 
@@ -353,6 +521,30 @@ pub fn dynamic_to_xepak(v: &Dynamic) -> Result<XepakValue, XepakError> {
     Ok(r)
 }
 
+pub fn log_info(ctx: NativeCallContext, message: &str) {
+    tracing::info!(
+        "RHAI: {} @ {:?} > {message}",
+        ctx.call_source().unwrap_or_default(),
+        ctx.call_position()
+    );
+}
+
+pub fn log_warn(ctx: NativeCallContext, message: &str) {
+    tracing::warn!(
+        "RHAI: {} @ {:?} > {message}",
+        ctx.call_source().unwrap_or_default(),
+        ctx.call_position()
+    );
+}
+
+pub fn log_error(ctx: NativeCallContext, message: &str) {
+    tracing::error!(
+        "RHAI: {} @ {:?} > {message}",
+        ctx.call_source().unwrap_or_default(),
+        ctx.call_position()
+    );
+}
+
 pub fn error_input(ctx: NativeCallContext, message: String) -> Result<(), Box<EvalAltResult>> {
     let err = XepakError::Input(message.to_string());
     Err(EvalAltResult::ErrorRuntime(Dynamic::from(err), ctx.call_position()).into())
@@ -384,3 +576,219 @@ pub fn to_eval_alt_result_ctx(
     let pos = ctx.map(|c| c.call_position()).unwrap_or(Position::NONE);
     Box::new(EvalAltResult::ErrorRuntime(Dynamic::from(err), pos))
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing::{LogCapture, sqlite_app_data, test_service};
+
+    async fn eval(app_data: &XepakAppData, script: &str) -> Result<Dynamic, XepakError> {
+        let engine = build_rhai_engine(app_data);
+        let ast = build_rhai_ast(&engine, script).unwrap();
+        execute_script_blocking(
+            Data::new(app_data.clone()),
+            "/test".to_string(),
+            Arc::new(Some(engine)),
+            Arc::new(Some(ast)),
+            RequestInput::new_in_script(Default::default(), 0, 0),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn url_decode_and_parse_query() {
+        let app_data = sqlite_app_data("script-url", &[], "").await;
+
+        let result = eval(&app_data, r#"url_decode("hello%20world")"#)
+            .await
+            .unwrap();
+        assert_eq!(result.into_string().unwrap(), "hello world");
+
+        let result = eval(&app_data, r#"parse_query("a=1&b=2&c=x%20y")"#)
+            .await
+            .unwrap();
+        let map = result.cast::<rhai::Map>();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["a"].clone().into_string().unwrap(), "1");
+        assert_eq!(map["b"].clone().into_string().unwrap(), "2");
+        assert_eq!(map["c"].clone().into_string().unwrap(), "x y");
+    }
+
+    #[tokio::test]
+    async fn json_parse_and_stringify() {
+        let app_data = sqlite_app_data("script-json", &[], "").await;
+
+        let script = r#"
+            let obj = json_parse(`{"user": {"name": "bob", "tags": [1, 2]}}`);
+            obj.user.name
+        "#;
+        let result = eval(&app_data, script).await.unwrap();
+        assert_eq!(result.into_string().unwrap(), "bob");
+
+        let script = r#"json_stringify(json_parse(`{"tags": [1, 2.5, null, true]}`))"#;
+        let result = eval(&app_data, script).await.unwrap();
+        assert_eq!(
+            result.into_string().unwrap(),
+            r#"{"tags":[1,2.5,null,true]}"#
+        );
+
+        assert!(eval(&app_data, r#"json_parse("{oops")"#).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn log_functions_use_levels() {
+        let app_data = sqlite_app_data("script-log", &[], "").await;
+        let engine = build_rhai_engine(&app_data);
+
+        // Script runs on the current thread, so the thread local subscriber sees it
+        let logs = LogCapture::start();
+        engine
+            .run(r#"log_warn("low balance"); log_error("broken"); log_info("done");"#)
+            .unwrap();
+
+        assert_eq!(
+            logs.messages(tracing::Level::WARN),
+            vec!["RHAI:  @ 1:1 > low balance"]
+        );
+        assert_eq!(logs.messages(tracing::Level::ERROR).len(), 1);
+        assert_eq!(logs.messages(tracing::Level::INFO).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn quote_ident_for_storage_dialect() {
+        let app_data = sqlite_app_data("script-quote-ident", &[""], "").await;
+
+        let script = r#"
+            let column = quote_ident("order");
+            storage_query_value(`SELECT 'ok' AS ${column}`, #{})
+        "#;
+        let result = eval(&app_data, script).await.unwrap();
+        assert_eq!(result.into_string().unwrap(), "ok");
+
+        let result = eval(&app_data, r#"quote_ident("a\"b")"#).await.unwrap();
+        assert_eq!(result.into_string().unwrap(), r#""a""b""#);
+
+        assert!(eval(&app_data, r#"quote_ident("")"#).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_arg_bound_as_null() {
+        let app_data = sqlite_app_data("script-missing-null", &[""], "").await;
+
+        let script = r#"
+            let query = "SELECT {{name}} IS NULL AS v";
+            let args = #{};
+            if false { args.name = "bob"; }
+            storage_query_value(query, with_missing_as_null(query, args))
+        "#;
+        let result = eval(&app_data, script).await.unwrap();
+        assert_eq!(result.as_int().unwrap(), 1);
+
+        // Provided values are kept
+        let script = r#"
+            let query = "SELECT {{name}} AS v";
+            storage_query_value(query, with_missing_as_null(query, #{ name: "bob" }))
+        "#;
+        let result = eval(&app_data, script).await.unwrap();
+        assert_eq!(result.into_string().unwrap(), "bob");
+
+        // Missing argument without the helper is an error
+        let script = r#"storage_query_value("SELECT {{name}}", #{})"#;
+        assert!(eval(&app_data, script).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn script_branches_on_method() {
+        let app = test_service(
+            "script-method",
+            "",
+            &[r#"
+            uri = "/method"
+
+            [resource]
+            type = "query_script"
+            script = """
+            let tag = ctx.header("x-tag");
+            if ctx.method() == "POST" {
+                "SELECT 'post' AS m, '" + tag + "' AS h"
+            } else {
+                "SELECT 'get' AS m, '" + tag + "' AS h"
+            }
+            """
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/method")
+            .insert_header(("X-Tag", "a"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"m": "get", "h": "a"}]));
+
+        let req = test::TestRequest::post()
+            .uri("/method")
+            .insert_header(("x-tag", "b"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"m": "post", "h": "b"}]));
+    }
+
+    #[actix_web::test]
+    async fn script_reads_path_and_route() {
+        let app = test_service(
+            "script-path",
+            "",
+            &[r#"
+            uri = "/user/{id}/info"
+
+            [resource]
+            type = "query_script"
+            script = """
+            "SELECT '" + ctx.path() + "' AS path, '" + ctx.route() + "' AS route"
+            """
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/user/42/info?x=1")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"path": "/user/42/info", "route": "/user/{id}/info"}])
+        );
+    }
+
+    #[actix_web::test]
+    async fn script_reads_limit_and_offset() {
+        let app = test_service(
+            "script-limit",
+            "",
+            &[r#"
+            uri = "/page"
+            fetch_limit = 50
+
+            [resource]
+            type = "query_script"
+            script = """
+            "SELECT " + ctx.limit() + " AS l, " + ctx.offset() + " AS o"
+            """
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/page?limit=5&offset=10")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"l": 5, "o": 10}]));
+
+        let req = test::TestRequest::get().uri("/page").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"l": 50, "o": 0}]));
+    }
+}
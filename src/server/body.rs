@@ -0,0 +1,205 @@
+use std::io::Read;
+
+use actix_web::{
+    HttpRequest,
+    http::header::CONTENT_ENCODING,
+    web::{Bytes, Payload},
+};
+use flate2::read::MultiGzDecoder;
+
+use crate::XepakError;
+
+/// Actix `PayloadConfig` default, used before body size became configurable.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 256 * 1024;
+
+/// Request body size limits.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    /// Max body size in bytes, compressed bodies are checked after decompression
+    pub max_size: usize,
+    /// Max size in bytes of a decompressed body
+    pub max_decompressed_size: Option<usize>,
+    /// Max ratio of decompressed to compressed body size
+    pub max_decompression_ratio: Option<usize>,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_BODY_SIZE,
+            max_decompressed_size: None,
+            max_decompression_ratio: None,
+        }
+    }
+}
+
+impl BodyLimits {
+    /// Max decompressed size for the compressed body of `compressed_len` bytes.
+    fn decompressed_cap(&self, compressed_len: usize) -> usize {
+        let ratio_cap = self
+            .max_decompression_ratio
+            .map_or(usize::MAX, |ratio| compressed_len.saturating_mul(ratio));
+        self.max_size
+            .min(self.max_decompressed_size.unwrap_or(usize::MAX))
+            .min(ratio_cap)
+    }
+}
+
+fn too_large(max: usize) -> XepakError {
+    XepakError::PayloadTooLarge(format!("Request body exceeds max size {max} bytes"))
+}
+
+/// Read request body decoding it according to `Content-Encoding` (only `gzip` is supported).
+pub async fn read_body(
+    req: &HttpRequest,
+    payload: Payload,
+    limits: BodyLimits,
+) -> Result<Bytes, XepakError> {
+    let encoding = match req.headers().get(CONTENT_ENCODING) {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| XepakError::UnsupportedMediaType("Invalid Content-Encoding".to_string()))?
+            .trim()
+            .to_ascii_lowercase(),
+        None => String::new(),
+    };
+
+    let gzip = match encoding.as_str() {
+        "" | "identity" => false,
+        "gzip" | "x-gzip" => true,
+        other => {
+            return Err(XepakError::UnsupportedMediaType(format!(
+                "Unsupported Content-Encoding \"{other}\", expected gzip or identity"
+            )));
+        }
+    };
+
+    let raw = payload
+        .to_bytes_limited(limits.max_size)
+        .await
+        .map_err(|_| too_large(limits.max_size))?
+        .map_err(|e| XepakError::Input(format!("Can't read request body: {e}")))?;
+
+    if !gzip {
+        return Ok(raw);
+    }
+    gunzip(&raw, limits.decompressed_cap(raw.len()))
+}
+
+/// Inflate gzip data, stops as soon as the output exceeds `max_size`
+/// (so zip bombs are never inflated in full).
+fn gunzip(data: &[u8], max_size: usize) -> Result<Bytes, XepakError> {
+    let mut out = Vec::new();
+    MultiGzDecoder::new(data)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| XepakError::Input(format!("Invalid gzip request body: {e}")))?;
+    if out.len() > max_size {
+        return Err(XepakError::PayloadTooLarge(format!(
+            "Decompressed request body exceeds max size {max_size} bytes"
+        )));
+    }
+    Ok(Bytes::from(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use actix_web::{
+        http::{StatusCode, header::CONTENT_TYPE},
+        test,
+    };
+    use flate2::{Compression, write::GzEncoder};
+
+    use super::*;
+    use crate::{
+        server::CONTENT_TYPE_JSON,
+        testing::{TestEnv, test_service},
+    };
+
+    #[actix_web::test]
+    async fn gzip_body_decoded_into_args() {
+        let app = test_service(
+            "gzip-body",
+            "",
+            &[r#"
+            uri = "/echo"
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{name}} AS name"
+            "#],
+        )
+        .await;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(br#"{"name": "xepak"}"#).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .insert_header((CONTENT_ENCODING, "gzip"))
+            .set_payload(encoder.finish().unwrap())
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"name": "xepak"}]));
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .insert_header((CONTENT_ENCODING, "compress"))
+            .set_payload(r#"{"name": "xepak"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_web::test]
+    async fn gzip_bomb_rejected() {
+        let app = TestEnv::new(
+            "gzip-bomb",
+            "max_decompressed_size = 4096\nmax_decompression_ratio = 20",
+            "",
+        )
+        .await
+        .service(&[r#"
+            uri = "/echo"
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{name}} AS name"
+            "#])
+        .await;
+
+        let gzip = |data: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let post = |body: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/echo")
+                .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+                .insert_header((CONTENT_ENCODING, "gzip"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        // Kilobytes of spaces inflating past the absolute cap
+        let padded = format!(r#"{{"name": "xepak"{}}}"#, " ".repeat(64 * 1024));
+        let resp = test::call_service(&app, post(gzip(padded.as_bytes()))).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Below the absolute cap, but compressed too well
+        let padded = format!(r#"{{"name": "xepak"{}}}"#, " ".repeat(3 * 1024));
+        let compressed = gzip(padded.as_bytes());
+        assert!(padded.len() > compressed.len() * 20);
+        let resp = test::call_service(&app, post(compressed)).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let resp = test::call_service(&app, post(gzip(br#"{"name": "xepak"}"#))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use actix_web::HttpRequest;
+use serde::Deserialize;
+
+use crate::XepakError;
+
+pub const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+/// IP network like `10.0.0.0/8` or `::1/128`, a single address without the prefix length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| self.contains(&IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+/// First `prefix` bits of the addresses are equal.
+fn prefix_eq(net: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full = usize::from(prefix / 8);
+    if net[..full] != ip[..full] {
+        return false;
+    }
+    let rest = prefix % 8;
+    if rest == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rest);
+    net[full] & mask == ip[full] & mask
+}
+
+impl FromStr for IpCidr {
+    type Err = XepakError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || XepakError::Cfg(format!("Invalid IP network \"{s}\""));
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for IpCidr {
+    type Error = XepakError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+fn is_trusted(trusted: &[IpCidr], ip: &IpAddr) -> bool {
+    trusted.iter().any(|net| net.contains(ip))
+}
+
+/// Effective client IP: the peer address unless the peer is a trusted proxy.
+/// Behind trusted proxies `X-Forwarded-For` is walked from the right (the entries appended
+/// by our own proxies) and the first untrusted address is the client. Entries left of it
+/// are set by the client and could be spoofed, so they are never used.
+pub fn client_ip(req: &HttpRequest, trusted: &[IpCidr]) -> Option<IpAddr> {
+    let mut client = req.peer_addr()?.ip();
+    if !is_trusted(trusted, &client) {
+        return Some(client);
+    }
+
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+
+    for entry in forwarded.into_iter().rev() {
+        let Ok(ip) = entry.parse::<IpAddr>() else {
+            // Garbage from the client, the last known hop is the best guess
+            break;
+        };
+        client = ip;
+        if !is_trusted(trusted, &ip) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn nets(list: &[&str]) -> Vec<IpCidr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn peer(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 40000)
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let net: IpCidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!net.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+
+        let net: IpCidr = "fd00::/8".parse().unwrap();
+        assert!(net.contains(&"fd12::1".parse().unwrap()));
+        assert!(!net.contains(&"fe80::1".parse().unwrap()));
+
+        let net: IpCidr = "192.168.1.10".parse().unwrap();
+        assert_eq!(net.to_string(), "192.168.1.10/32");
+        assert!(!net.contains(&"192.168.1.11".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("proxy.local".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn direct_connection_uses_peer() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        // Header from untrusted peer is ignored
+        let req = TestRequest::default()
+            .peer_addr(peer("203.0.113.7"))
+            .insert_header((X_FORWARDED_FOR, "198.51.100.1"))
+            .to_http_request();
+        assert_eq!(
+            client_ip(&req, &trusted),
+            Some("203.0.113.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn forwarded_for_behind_trusted_proxy() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        // Spoofed entry on the left is skipped, 10.0.0.5 is our inner proxy
+        let req = TestRequest::default()
+            .peer_addr(peer("10.0.0.1"))
+            .insert_header((X_FORWARDED_FOR, "1.2.3.4, 198.51.100.1, 10.0.0.5"))
+            .to_http_request();
+        assert_eq!(
+            client_ip(&req, &trusted),
+            Some("198.51.100.1".parse().unwrap())
+        );
+
+        // No header, proxy itself is the client
+        let req = TestRequest::default()
+            .peer_addr(peer("10.0.0.1"))
+            .to_http_request();
+        assert_eq!(client_ip(&req, &trusted), Some("10.0.0.1".parse().unwrap()));
+
+        // Invalid entry stops at the last trusted hop
+        let req = TestRequest::default()
+            .peer_addr(peer("10.0.0.1"))
+            .insert_header((X_FORWARDED_FOR, "unknown, 10.0.0.5"))
+            .to_http_request();
+        assert_eq!(client_ip(&req, &trusted), Some("10.0.0.5".parse().unwrap()));
+    }
+}
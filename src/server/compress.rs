@@ -0,0 +1,117 @@
+use actix_web::{
+    dev::ServiceRequest,
+    http::header::{ACCEPT_ENCODING, HeaderValue},
+};
+use serde::Deserialize;
+use strum::Display;
+
+/// Response compression algorithms that could be negotiated with client.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    Gzip,
+    Deflate,
+    Br,
+    Zstd,
+}
+
+/// Rewrite `Accept-Encoding` header leaving only allowed algorithms in it,
+/// so the compression middleware negotiates only between them.
+/// Client preferences (q-values) are kept, the `*` wildcard is expanded to allowed algorithms.
+pub fn filter_accept_encoding(req: &mut ServiceRequest, allowed: &[CompressionAlgorithm]) {
+    let Some(accept) = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return;
+    };
+
+    let mut filtered = Vec::new();
+    for entry in accept.split(',') {
+        let entry = entry.trim();
+        let (name, params) = match entry.split_once(';') {
+            Some((name, params)) => (name.trim(), Some(params.trim())),
+            None => (entry, None),
+        };
+
+        if name == "*" {
+            for a in allowed {
+                filtered.push(match params {
+                    Some(p) => format!("{a};{p}"),
+                    None => a.to_string(),
+                });
+            }
+        } else if allowed
+            .iter()
+            .any(|a| a.to_string().eq_ignore_ascii_case(name))
+        {
+            filtered.push(entry.to_string());
+        }
+    }
+
+    let headers = req.headers_mut();
+    if filtered.is_empty() {
+        // Nothing acceptable left, so response will not be compressed
+        headers.remove(ACCEPT_ENCODING);
+    } else if let Ok(value) = HeaderValue::from_str(&filtered.join(", ")) {
+        headers.insert(ACCEPT_ENCODING, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App, HttpResponse,
+        dev::Service,
+        http::header::CONTENT_ENCODING,
+        middleware::Compress,
+        test::{self, TestRequest},
+        web,
+    };
+
+    use super::*;
+
+    async fn content_encoding(allowed: Vec<CompressionAlgorithm>, accept: &str) -> Option<String> {
+        let app = test::init_service(
+            App::new()
+                .wrap(Compress::default())
+                .wrap_fn(move |mut req, srv| {
+                    filter_accept_encoding(&mut req, &allowed);
+                    srv.call(req)
+                })
+                .route(
+                    "/",
+                    web::get().to(|| async { HttpResponse::Ok().body("x".repeat(1024)) }),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((ACCEPT_ENCODING, accept))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        resp.headers()
+            .get(CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[actix_web::test]
+    async fn brotli_negotiated() {
+        use CompressionAlgorithm::*;
+
+        let encoding = content_encoding(vec![Gzip, Br], "br").await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+
+        let encoding = content_encoding(vec![Gzip, Br], "gzip;q=0.5, br;q=0.9").await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+
+        let encoding = content_encoding(vec![Gzip], "br, gzip;q=0.5").await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+
+        let encoding = content_encoding(vec![Gzip], "br").await;
+        assert_eq!(encoding, None);
+    }
+}
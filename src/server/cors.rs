@@ -0,0 +1,202 @@
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::{
+        Method,
+        header::{
+            ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD, HeaderValue, ORIGIN, VARY,
+        },
+    },
+};
+use serde::Deserialize;
+
+use crate::XepakError;
+
+/// Cross-origin requests policy. Endpoint policy replaces the global one entirely.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CorsConf {
+    /// Allowed origins (e.g. `https://app.example.com`), `*` allows any origin
+    pub allowed_origins: Vec<String>,
+
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// Headers allowed in requests, headers requested by the browser are allowed if empty
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+
+    /// Seconds the browser may cache preflight response
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
+}
+
+impl CorsConf {
+    /// Value of `Access-Control-Allow-Origin` for the request origin (if it is allowed).
+    fn allow_origin<'a>(&'a self, origin: &'a str) -> Option<&'a str> {
+        if self.allowed_origins.iter().any(|o| o == "*") {
+            Some("*")
+        } else {
+            self.allowed_origins
+                .iter()
+                .find(|o| o.eq_ignore_ascii_case(origin))
+                .map(|_| origin)
+        }
+    }
+
+    fn origin(req: &HttpRequest) -> Option<&str> {
+        req.headers().get(ORIGIN).and_then(|o| o.to_str().ok())
+    }
+
+    /// Preflight is `OPTIONS` with `Origin` and `Access-Control-Request-Method`.
+    pub fn is_preflight(req: &HttpRequest) -> bool {
+        req.method() == Method::OPTIONS
+            && req.headers().contains_key(ORIGIN)
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Respond to preflight request, not allowed origin or method is forbidden.
+    pub fn preflight_response(&self, req: &HttpRequest) -> Result<HttpResponse, XepakError> {
+        let origin = Self::origin(req).unwrap_or_default();
+        let Some(allow_origin) = self.allow_origin(origin) else {
+            return Err(XepakError::Forbidden(format!(
+                "Origin \"{origin}\" is not allowed"
+            )));
+        };
+
+        let method = req
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| m.to_str().ok())
+            .unwrap_or_default();
+        if !self
+            .allowed_methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method))
+        {
+            return Err(XepakError::Forbidden(format!(
+                "Method \"{method}\" is not allowed for cross-origin requests"
+            )));
+        }
+
+        let mut resp = HttpResponse::NoContent();
+        resp.insert_header((ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin))
+            .insert_header((
+                ACCESS_CONTROL_ALLOW_METHODS,
+                self.allowed_methods.join(", "),
+            ));
+        if allow_origin != "*" {
+            resp.insert_header((VARY, "Origin"));
+        }
+
+        if !self.allowed_headers.is_empty() {
+            resp.insert_header((
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                self.allowed_headers.join(", "),
+            ));
+        } else if let Some(requested) = req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS) {
+            resp.insert_header((ACCESS_CONTROL_ALLOW_HEADERS, requested.clone()));
+        }
+        if let Some(max_age) = self.max_age {
+            resp.insert_header((ACCESS_CONTROL_MAX_AGE, max_age));
+        }
+
+        Ok(resp.finish())
+    }
+
+    /// Add `Access-Control-Allow-Origin` to the response if request origin is allowed.
+    pub fn append_headers(&self, req: &HttpRequest, resp: &mut HttpResponse) {
+        let Some(allow_origin) = Self::origin(req).and_then(|o| self.allow_origin(o)) else {
+            return;
+        };
+        let Ok(value) = HeaderValue::from_str(allow_origin) else {
+            return;
+        };
+
+        let headers = resp.headers_mut();
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        if allow_origin != "*" {
+            headers.append(VARY, HeaderValue::from_static("Origin"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::*;
+    use crate::testing::TestEnv;
+
+    #[actix_web::test]
+    async fn endpoint_cors_overrides_global() {
+        let app = TestEnv::new(
+            "endpoint-cors",
+            "[cors]\nallowed_origins = [\"https://app.example\"]",
+            "",
+        )
+        .await
+        .service(&[
+            r#"
+            uri = "/private"
+            resource = { type = "static", body = { ok = true } }
+            "#,
+            r#"
+            uri = "/public"
+            cors = { allowed_origins = ["*"], allowed_methods = ["GET"] }
+            resource = { type = "static", body = { ok = true } }
+            "#,
+        ])
+        .await;
+
+        let get = |uri: &str, origin: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header((ORIGIN, origin.to_string()))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, get("/private", "https://app.example")).await;
+        assert_eq!(
+            resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example"
+        );
+        let resp = test::call_service(&app, get("/private", "https://other.example")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let resp = test::call_service(&app, get("/public", "https://other.example")).await;
+        assert_eq!(
+            resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "*"
+        );
+
+        let preflight = |uri: &str, method: &str| {
+            test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri(uri)
+                .insert_header((ORIGIN, "https://other.example"))
+                .insert_header((ACCESS_CONTROL_REQUEST_METHOD, method.to_string()))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, preflight("/public", "GET")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "*"
+        );
+
+        // Endpoint policy replaces the global one, so only its methods are allowed
+        let resp = test::call_service(&app, preflight("/public", "DELETE")).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(&app, preflight("/private", "GET")).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,271 @@
+use actix_web::{HttpRequest, HttpResponse, http::header::ACCEPT, web::Bytes};
+use futures_util::{StreamExt, stream};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{XepakError, storage::RecordColumns, types::XepakValue};
+
+pub const CONTENT_TYPE_CSV: &str = "text/csv; charset=utf-8";
+
+/// Rows written into a single body chunk if they are already fetched.
+const ROWS_PER_CHUNK: usize = 64;
+
+pub fn accepts_csv(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/csv"))
+}
+
+/// Append CSV line, fields with separators, quotes or line breaks are quoted (RFC 4180).
+fn push_line<'a>(out: &mut String, fields: impl Iterator<Item = &'a str>) {
+    for (idx, field) in fields.enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+fn push_row(out: &mut String, row: &RecordColumns, positions: Option<&[Option<usize>]>) {
+    let cell = |value: &XepakValue| match value {
+        XepakValue::Null => String::new(),
+        value => value.as_string(),
+    };
+    let values: Vec<String> = match positions {
+        Some(positions) => positions
+            .iter()
+            .map(|p| {
+                p.and_then(|idx| row.get(idx))
+                    .map(|(_, v)| cell(v))
+                    .unwrap_or_default()
+            })
+            .collect(),
+        None => row.iter().map(|(_, value)| cell(value)).collect(),
+    };
+    push_line(out, values.iter().map(String::as_str));
+}
+
+/// Serialized records, rows keep the serializer key order (`serde_json::Value` sorts keys).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CsvRows {
+    Rows(Vec<IndexMap<String, Value>>),
+    Row(IndexMap<String, Value>),
+    Empty,
+}
+
+/// CSV text of the records (or a single record) at once. Columns are the declared `columns`
+/// or keys of the first record. Anything other than records is an input error.
+pub fn records_to_csv<T: Serialize>(data: &T, columns: &[String]) -> Result<String, XepakError> {
+    let json = serde_json::to_string(data).map_err(|e| XepakError::WeScrewed(e.to_string()))?;
+    let rows = match serde_json::from_str(&json) {
+        Ok(CsvRows::Rows(rows)) => rows,
+        Ok(CsvRows::Row(row)) => vec![row],
+        Ok(CsvRows::Empty) => Vec::new(),
+        Err(_) => {
+            return Err(XepakError::Input(
+                "CSV format is available only for records".to_string(),
+            ));
+        }
+    };
+
+    let header: Vec<String> = match rows.first() {
+        _ if !columns.is_empty() => columns.to_vec(),
+        Some(row) => row.keys().cloned().collect(),
+        None => Vec::new(),
+    };
+
+    let cell = |value: Option<&Value>| match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    };
+
+    let mut out = String::new();
+    if !header.is_empty() {
+        push_line(&mut out, header.iter().map(String::as_str));
+    }
+    for row in &rows {
+        let values: Vec<String> = header.iter().map(|c| cell(row.get(c))).collect();
+        push_line(&mut out, values.iter().map(String::as_str));
+    }
+    Ok(out)
+}
+
+/// Stream CSV with the header followed by the `first` row and rows from `rows`.
+/// Header is the declared `columns` (missing cells are empty, other columns are dropped)
+/// or the `first` row columns if nothing declared.
+/// Error after the response is started can't change the status, so it aborts the body.
+pub fn stream_response(
+    first: Option<RecordColumns>,
+    rows: mpsc::Receiver<Result<RecordColumns, XepakError>>,
+    columns: &[String],
+) -> HttpResponse {
+    // Rows of the same query have the same columns, so positions are resolved once
+    let positions: Option<Vec<Option<usize>>> = match &first {
+        Some(row) if !columns.is_empty() => Some(
+            columns
+                .iter()
+                .map(|c| row.iter().position(|(name, _)| name == c))
+                .collect(),
+        ),
+        _ => None,
+    };
+
+    let mut head = String::new();
+    if !columns.is_empty() {
+        push_line(&mut head, columns.iter().map(String::as_str));
+    } else if let Some(row) = &first {
+        push_line(&mut head, row.iter().map(|(name, _)| name.as_str()));
+    }
+    if let Some(row) = &first {
+        push_row(&mut head, row, positions.as_deref());
+    }
+
+    let tail = stream::unfold((rows, positions), |(mut rows, positions)| async move {
+        let mut chunk = String::new();
+        match rows.recv().await? {
+            Ok(row) => push_row(&mut chunk, &row, positions.as_deref()),
+            Err(e) => {
+                tracing::error!("CSV stream failed: {e}");
+                return Some((Err(e), (rows, positions)));
+            }
+        }
+
+        for _ in 1..ROWS_PER_CHUNK {
+            match rows.try_recv() {
+                Ok(Ok(row)) => push_row(&mut chunk, &row, positions.as_deref()),
+                Ok(Err(e)) => {
+                    // Rows in the chunk are lost anyway, the response is broken
+                    tracing::error!("CSV stream failed: {e}");
+                    return Some((Err(e), (rows, positions)));
+                }
+                Err(_) => break,
+            }
+        }
+
+        Some((Ok(Bytes::from(chunk)), (rows, positions)))
+    });
+
+    let body = stream::once(async move { Ok(Bytes::from(head)) }).chain(tail);
+    HttpResponse::Ok()
+        .content_type(CONTENT_TYPE_CSV)
+        .streaming(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{StatusCode, header::CONTENT_TYPE},
+        test,
+    };
+
+    use super::*;
+    use crate::testing::test_service;
+
+    #[actix_web::test]
+    async fn large_table_streamed_as_csv() {
+        let app = test_service(
+            "stream-csv",
+            "CREATE TABLE big (id INTEGER, name TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 20000)
+            INSERT INTO big SELECT i, 'item, ' || i FROM n",
+            &[r#"
+            uri = "/export"
+            stream_csv = true
+
+            [resource]
+            type = "query"
+            query = "SELECT id, name FROM big ORDER BY id"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/export")
+            .insert_header((ACCEPT, "text/csv"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_CSV);
+
+        let body = test::read_body(resp).await;
+        let text = std::str::from_utf8(&body).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 20001);
+        assert_eq!(lines[0], "id,name");
+        assert_eq!(lines[1], "1,\"item, 1\"");
+        assert_eq!(lines[20000], "20000,\"item, 20000\"");
+    }
+
+    #[actix_web::test]
+    async fn csv_columns_declared_order() {
+        let app = test_service(
+            "csv-columns",
+            "",
+            &[r#"
+            uri = "/export"
+            stream_csv = true
+            columns = ["name", "missing", "id"]
+
+            [resource]
+            type = "query"
+            query = "SELECT 1 AS id, 'neo' AS name, 'x' AS extra"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/export")
+            .insert_header((ACCEPT, "text/csv"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "name,missing,id\r\nneo,,1\r\n");
+    }
+
+    #[actix_web::test]
+    async fn csv_header_in_query_order() {
+        let app = test_service(
+            "csv-query-order",
+            "",
+            &[
+                r#"
+                uri = "/buffered"
+                force_content_type = "text/csv"
+
+                [resource]
+                type = "query"
+                query = "SELECT 'neo' AS name, 1 AS id"
+                "#,
+                r#"
+                uri = "/streamed"
+                stream_csv = true
+
+                [resource]
+                type = "query"
+                query = "SELECT 'neo' AS name, 1 AS id"
+                "#,
+            ],
+        )
+        .await;
+
+        for uri in ["/buffered", "/streamed"] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((ACCEPT, "text/csv"))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            assert_eq!(body, "name,id\r\nneo,1\r\n", "{uri}");
+        }
+    }
+}
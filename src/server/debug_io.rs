@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use actix_web::{
+    HttpResponse,
+    body::{BoxBody, MessageBody},
+    http::header::CONTENT_TYPE,
+};
+
+use crate::server::{CONTENT_TYPE_JSON, RequestInput};
+
+/// Replacement for values of the secret-like fields.
+const REDACTED: &str = "***";
+
+/// Parts of the field name that mark it as a secret (compared in lowercase).
+const SECRET_NAME_PARTS: [&str; 6] = ["password", "passwd", "secret", "token", "api_key", "apikey"];
+
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_NAME_PARTS.iter().any(|part| name.contains(part))
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    match text.char_indices().nth(max_length) {
+        Some((end, _)) => format!("{}... ({} bytes total)", &text[..end], text.len()),
+        None => text.to_string(),
+    }
+}
+
+/// Log path and input args of the request, secret-like args are redacted.
+pub fn log_input(uri: &str, input: &RequestInput, max_length: usize) {
+    let args: BTreeMap<&str, String> = input
+        .path_args
+        .iter()
+        .chain(input.args.iter())
+        .map(|(name, value)| {
+            let value = if is_secret_name(name) {
+                REDACTED.to_string()
+            } else {
+                format!("{value:?}")
+            };
+            (name.as_str(), value)
+        })
+        .collect();
+
+    tracing::info!(
+        "Request {} {} [{uri}] args: {}",
+        input.get_method(),
+        input.get_path(),
+        truncate(&format!("{args:?}"), max_length)
+    );
+}
+
+/// Log serialized response body, secret-like fields of JSON objects are redacted.
+/// Streaming bodies are not logged.
+pub fn log_response(uri: &str, resp: HttpResponse, max_length: usize) -> HttpResponse {
+    let status = resp.status();
+    let is_json = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|ct| ct.as_bytes().starts_with(CONTENT_TYPE_JSON.as_bytes()));
+
+    let (resp, body) = resp.into_parts();
+    let body = match body.try_into_bytes() {
+        Ok(body) => body,
+        Err(body) => {
+            tracing::info!("Response [{uri}] {status}: <stream>");
+            return resp.set_body(body);
+        }
+    };
+
+    let text = if is_json {
+        match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(mut json) => {
+                redact_json(&mut json);
+                json.to_string()
+            }
+            Err(_) => String::from_utf8_lossy(&body).to_string(),
+        }
+    } else {
+        format!("<{} bytes>", body.len())
+    };
+
+    tracing::info!("Response [{uri}] {status}: {}", truncate(&text, max_length));
+
+    resp.set_body(BoxBody::new(body))
+}
+
+fn redact_json(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret_name(key) {
+                    *value = REDACTED.into();
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use crate::testing::{LogCapture, test_service};
+
+    #[actix_web::test]
+    async fn debug_io_logged_for_flagged_endpoint() {
+        let app = test_service(
+            "debug-io",
+            "",
+            &[
+                r#"
+                uri = "/debug"
+                debug_io = true
+
+                [resource]
+                type = "query"
+                query = "SELECT {{name}} AS name, 's3cr3t' AS password"
+                "#,
+                r#"
+                uri = "/quiet"
+
+                [resource]
+                type = "query"
+                query = "SELECT 1 AS id"
+                "#,
+            ],
+        )
+        .await;
+
+        let logs = LogCapture::start();
+        let req = test::TestRequest::get()
+            .uri("/debug?name=neo&api_token=abc")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"name": "neo", "password": "s3cr3t"}])
+        );
+
+        let req = test::TestRequest::get().uri("/quiet").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let messages = logs.messages(tracing::Level::INFO);
+        let logged: Vec<&String> = messages.iter().filter(|m| m.contains("[/debug]")).collect();
+        assert_eq!(logged.len(), 2, "{messages:?}");
+        assert!(
+            logged[0].contains("neo") && !logged[0].contains("abc"),
+            "{logged:?}"
+        );
+        assert!(!logged[1].contains("s3cr3t"), "{logged:?}");
+        assert!(
+            !messages.iter().any(|m| m.contains("[/quiet]")),
+            "{messages:?}"
+        );
+    }
+}
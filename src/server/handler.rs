@@ -1,46 +1,133 @@
-use std::{collections::HashMap, pin::Pin, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use actix_web::{
     Handler, HttpRequest, HttpResponse, HttpResponseBuilder,
-    body::BoxBody,
+    body::{BodySize, BoxBody, MessageBody},
     dev::HttpServiceFactory,
     http::{
         StatusCode,
-        header::{ACCEPT, CONTENT_TYPE},
+        header::{ACCEPT, CONTENT_TYPE, IF_MATCH, LOCATION},
     },
     web::{self, Bytes, Data},
 };
-use getopt3::new;
+use futures_util::FutureExt;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
 use rhai::{AST, Engine};
 use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 use crate::{
     XepakError,
-    auth::{AuthorizeProcessor, SimpleAuthenticationProcessor},
-    cfg::{EndpointSpecs, ResourceSpecs},
+    auth::{
+        AnyAuthenticationProcessor, AuthorizeProcessor, CheckAuthConf,
+        SimpleAuthenticationProcessor,
+    },
+    cfg::{
+        DuplicateKeyPolicy, EndpointSpecs, EndpointUri, JsonPointerSpecs, NoRowsPolicy, QueryPart,
+        ROW_FILTER_PLACEHOLDER, ResourceSpecs, ResponseFormat,
+    },
+    schema::ArgSchemaScope,
     script::{build_rhai_ast, build_rhai_engine, execute_script_blocking},
     server::{
-        CONTENT_TYPE_CBOR, CONTENT_TYPE_JSON, LIMIT_HEADER, OFFSET_HEADER, RequestInput,
-        XepakAppData,
+        ArgSource, CONTENT_TYPE_CBOR, CONTENT_TYPE_JSON, CONTENT_TYPE_TEXT, DEFAULT_ARG_PRECEDENCE,
+        Envelope, ErrorEnvelope, PaginationHeaders, RequestInput, ResponseMeta, XepakAppData, body,
+        client_error_object,
+        cors::CorsConf,
+        csv, debug_io,
+        idempotency::{Claim, IdempotencyStore},
+        keyset::encode_cursor,
         processor::{
             BodyToArgsProcessor, InputArgsValidator, PreProcessor, PreProcessorHandler,
             QueryArgsProcessor,
         },
-        to_error_object,
+        range, sse, to_error_object, websocket,
     },
-    storage::ResourceRequest,
-    types::XepakValue,
+    sql_key_args::SqlLexer,
+    storage::{PreparedQuery, ResourceRequest, Storage, is_read_query},
+    types::{JsonOutput, Record, RecordOutput, ResourceOutput, SortedKeys, XepakValue},
 };
 
-type EndpointHandlerArgs = (HttpRequest, Data<XepakAppData>, Bytes);
+/// Streamed response body keeping the `max_concurrency` slot or `max_db_conns` permit
+/// until it is finished or dropped.
+struct PermitBody {
+    body: BoxBody,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl MessageBody for PermitBody {
+    type Error = <BoxBody as MessageBody>::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Pin::new(&mut self.get_mut().body).poll_next(cx)
+    }
+}
+
+fn hold_permit(resp: HttpResponse, permit: Option<OwnedSemaphorePermit>) -> HttpResponse {
+    match permit {
+        Some(permit) => resp.map_body(|_, body| {
+            BoxBody::new(PermitBody {
+                body,
+                _permit: permit,
+            })
+        }),
+        None => resp,
+    }
+}
+
+/// Argument with comma-separated fields to return (see `allow_sparse_fields`)
+const FIELDS_ARG: &str = "fields";
+
+/// Query string flag for indented JSON (see `allow_pretty`)
+const PRETTY_ARG: &str = "pretty";
+
+type EndpointHandlerArgs = (HttpRequest, Data<XepakAppData>, web::Payload);
 
 #[derive(Clone)]
 pub struct EndpointHandler {
     ep: Arc<EndpointSpecs>,
+    /// URI pattern this handler instance is registered for
+    uri: Arc<str>,
     rhai_engine: Arc<Option<Engine>>,
     handler_script: Arc<Option<AST>>,
+    /// Static query lexed once on start
+    prepared_query: Arc<Option<PreparedQuery>>,
     // processor_scrips: Arc<HashMap<usize, AST>>,
     processors: Arc<Vec<Box<dyn PreProcessorHandler + Send + Sync>>>,
+    /// Checks from the row filter specs that allow to skip filtering
+    row_filter_unless: Arc<Option<CheckAuthConf>>,
+    sort_keys: bool,
+    allow_pretty: bool,
+    /// Query parameter selecting the response format
+    format_arg: Arc<str>,
+    /// Endpoint CORS policy or the global one
+    cors: Option<Arc<CorsConf>>,
+    catch_panics: bool,
+    expose_db_errors: bool,
+    pagination_headers: Arc<PaginationHeaders>,
+    /// Responses of write requests by idempotency key
+    idempotency: Arc<Option<IdempotencyStore>>,
+    arg_precedence: Arc<Vec<ArgSource>>,
+    /// Slots for concurrent requests shared by all workers
+    concurrency: Option<Arc<Semaphore>>,
+    /// Caps pool connections held by this endpoint (see `max_db_conns`)
+    db_conns: Option<Arc<Semaphore>>,
+    /// Schema args with the input scope, they are removed from the response records
+    input_only_fields: Arc<Vec<String>>,
 }
 
 impl EndpointHandler {
@@ -65,16 +152,34 @@ impl EndpointHandler {
         };
 
         let mut processors: Vec<Box<dyn PreProcessorHandler + Send + Sync>> = vec![
-            Box::new(QueryArgsProcessor {}),
-            Box::new(InputArgsValidator {}),
+            QueryArgsProcessor::new_boxed(
+                ep.arg_precedence.is_some(),
+                ep.reject_unknown_query.then(|| known_query_args(&ep, app)),
+            ),
+            InputArgsValidator::new_boxed(ep.collect_validation_errors),
         ];
 
         for p in &ep.processor {
             match p {
-                PreProcessor::ParseBodyArgs => processors.push(BodyToArgsProcessor::new_boxed()),
+                PreProcessor::ParseBodyArgs {
+                    require_content_type,
+                } => {
+                    let accept_array = matches!(ep.resource, ResourceSpecs::BulkInsert { .. });
+                    processors.push(BodyToArgsProcessor::new_boxed(
+                        accept_array,
+                        *require_content_type,
+                    ))
+                }
                 PreProcessor::SimpleAuth { allow_no_auth } => {
                     processors.push(SimpleAuthenticationProcessor::new_boxed(*allow_no_auth))
                 }
+                PreProcessor::AnyAuth {
+                    methods,
+                    allow_no_auth,
+                } => processors.push(AnyAuthenticationProcessor::new_boxed(
+                    methods,
+                    *allow_no_auth,
+                )),
                 PreProcessor::Authorize { allow } => {
                     processors.push(AuthorizeProcessor::new_boxed(allow.as_slice()))
                 }
@@ -83,12 +188,124 @@ impl EndpointHandler {
 
         processors.sort_by_key(|b| std::cmp::Reverse(b.priority()));
 
+        if let ResourceSpecs::Static {
+            status: Some(status),
+            ..
+        } = &ep.resource
+            && StatusCode::from_u16(*status).is_err()
+        {
+            return Err(XepakError::Cfg(format!(
+                "Invalid static response status {status} for URI: {}",
+                ep.uri
+            )));
+        }
+
+        if let ResourceSpecs::Composite { parts } = &ep.resource {
+            validate_composite_parts(parts, &ep.uri)?;
+        }
+
+        // Other resources don't run a single write to count affected rows of
+        if ep.no_content
+            && !matches!(ep.on_no_rows, NoRowsPolicy::NoContent)
+            && !matches!(
+                ep.resource,
+                ResourceSpecs::Query { .. }
+                    | ResourceSpecs::QueryScript { .. }
+                    | ResourceSpecs::QuerySwitch { .. }
+                    | ResourceSpecs::PartialUpdate { .. }
+                    | ResourceSpecs::Insert { .. }
+            )
+        {
+            return Err(XepakError::Cfg(format!(
+                "on_no_rows is not supported by the resource type for URI: {}",
+                ep.uri
+            )));
+        }
+
+        // Without key columns the generated UPDATE would change every row
+        if let ResourceSpecs::PartialUpdate { key, .. } = &ep.resource
+            && key.is_empty()
+        {
+            return Err(XepakError::Cfg(format!(
+                "Partial update key must not be empty for URI: {}",
+                ep.uri
+            )));
+        }
+
+        // Row filter and If-Match queries are changed/executed per request the usual way
+        let prepared_query = match &ep.resource {
+            ResourceSpecs::Query { query, .. }
+                if ep.row_filter.is_none() && !ep.if_match && !ep.no_content =>
+            {
+                PreparedQuery::new(query)
+            }
+            _ => None,
+        };
+
+        let row_filter_unless = ep
+            .row_filter
+            .as_ref()
+            .and_then(|rf| CheckAuthConf::any_of(&rf.unless));
+
+        let idempotency = ep
+            .idempotency_ttl_secs
+            .map(|ttl| IdempotencyStore::new(Duration::from_secs(ttl)));
+
+        let arg_precedence = ep
+            .arg_precedence
+            .clone()
+            .unwrap_or_else(|| DEFAULT_ARG_PRECEDENCE.to_vec());
+
+        if ep.max_concurrency == Some(0) {
+            return Err(XepakError::Cfg(format!(
+                "Max concurrency must be positive for URI: {}",
+                ep.uri
+            )));
+        }
+        let concurrency = ep.max_concurrency.map(|n| Arc::new(Semaphore::new(n)));
+
+        if ep.max_db_conns == Some(0) {
+            return Err(XepakError::Cfg(format!(
+                "Max DB connections must be positive for URI: {}",
+                ep.uri
+            )));
+        }
+        let db_conns = ep.max_db_conns.map(|n| Arc::new(Semaphore::new(n)));
+
+        let input_only_fields: Vec<String> = ep
+            .schema
+            .iter()
+            .filter(|(_, s)| matches!(s.scope, ArgSchemaScope::Input))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let cors = ep
+            .cors
+            .clone()
+            .map(Arc::new)
+            .or_else(|| app.get_cors().cloned());
+
         Ok(Self {
+            uri: Arc::from(ep.uri.first()),
             ep: Arc::new(ep),
             rhai_engine: Arc::new(rhai_engine),
             handler_script: Arc::new(handler_script),
+            prepared_query: Arc::new(prepared_query),
             // processor_scrips: Arc::new(Default::default()),
             processors: Arc::new(processors),
+            row_filter_unless: Arc::new(row_filter_unless),
+            sort_keys: app.is_sort_keys(),
+            allow_pretty: app.is_allow_pretty(),
+            format_arg: app.get_format_arg().clone(),
+            cors,
+            catch_panics: app.is_catch_panics(),
+            expose_db_errors: app.is_expose_db_errors(),
+            pagination_headers: Arc::new(app.get_pagination_headers().clone()),
+            idempotency: Arc::new(idempotency),
+            arg_precedence: Arc::new(arg_precedence),
+            concurrency,
+            db_conns,
+            input_only_fields: Arc::new(input_only_fields),
         })
     }
 
@@ -96,30 +313,370 @@ impl EndpointHandler {
         &self,
         req: HttpRequest,
         state: Data<XepakAppData>,
-        body: Bytes,
+        payload: web::Payload,
+    ) -> HttpResponse {
+        let Some(cors) = self.cors.as_ref() else {
+            return self.handle_request(req, state, payload).await;
+        };
+
+        if CorsConf::is_preflight(&req) {
+            return cors
+                .preflight_response(&req)
+                .unwrap_or_else(|err| self.error_response(&req, err));
+        }
+        let mut resp = self.handle_request(req.clone(), state, payload).await;
+        cors.append_headers(&req, &mut resp);
+        resp
+    }
+
+    async fn handle_request(
+        &self,
+        req: HttpRequest,
+        state: Data<XepakAppData>,
+        payload: web::Payload,
     ) -> HttpResponse {
         tracing::debug!("Handler called for {:?}", self.ep);
 
-        let mut ri = match self.pre_process_request(&req, &state, &body).await {
+        if let Err(err) = self.requested_format(&req) {
+            return self.error_response(&req, err);
+        }
+
+        let body = match body::read_body(&req, payload, state.get_body_limits()).await {
+            Ok(body) => body,
+            Err(err) => return self.error_response(&req, err),
+        };
+
+        let ri = match self.pre_process_request(&req, &state, &body).await {
             Ok(result) => result,
             Err(err) => {
-                let (status_code, data) = to_error_object(err);
-                return self.data_to_response(&req, None, status_code, &data);
+                return self.error_response(&req, err);
+            }
+        };
+
+        let identity = ri.get_auth_identity();
+        if let Some(identity) = &identity {
+            tracing::debug!("Request to {} authenticated as {identity}", self.uri);
+        }
+
+        let mut resp = if !self.ep.debug_io {
+            self.respond_once(&req, ri, &state).await
+        } else {
+            debug_io::log_input(&self.uri, &ri, self.ep.debug_io_max_length);
+            let resp = self.respond_once(&req, ri, &state).await;
+            debug_io::log_response(&self.uri, resp, self.ep.debug_io_max_length)
+        };
+
+        if let Some(identity) = identity {
+            if state.is_auth_id_header() {
+                identity.append_headers(&mut resp);
             }
+            resp.extensions_mut().insert(identity);
+        }
+        resp
+    }
+
+    /// Respond with stored response for repeated idempotency key (if enabled).
+    async fn respond_once(
+        &self,
+        req: &HttpRequest,
+        ri: RequestInput,
+        state: &Data<XepakAppData>,
+    ) -> HttpResponse {
+        let Some(store) = self.idempotency.as_ref() else {
+            return self.respond(req, ri, state).await;
+        };
+        let Some(key) = IdempotencyStore::request_key(req) else {
+            return self.respond(req, ri, state).await;
+        };
+        // Same key from another caller must not get someone else's response
+        let key = match ri.get_auth() {
+            Some((id, _)) => format!("{key} {}", id.as_string()),
+            None => key,
         };
+
+        match store.claim(key) {
+            Ok(Claim::Replay(resp)) => {
+                tracing::debug!("Replaying stored response for idempotency key");
+                resp
+            }
+            Ok(Claim::Reserved(reservation)) => {
+                let resp = self.respond(req, ri, state).await;
+                reservation.complete(resp)
+            }
+            Err(err) => self.error_response(req, err),
+        }
+    }
+
+    async fn respond(
+        &self,
+        req: &HttpRequest,
+        mut ri: RequestInput,
+        state: &Data<XepakAppData>,
+    ) -> HttpResponse {
         // Maybe it should be in processors
         ri.parse_offset_limit(&self.ep.offset_arg, &self.ep.limit_arg, self.ep.fetch_limit);
+        if let Some(keyset) = &self.ep.keyset
+            && let Err(err) = ri.parse_keyset(keyset)
+        {
+            return self.error_response(req, err);
+        }
+        if let ResourceSpecs::Static { body, status } = &self.ep.resource {
+            let status_code = status
+                .and_then(|s| StatusCode::from_u16(s).ok())
+                .unwrap_or(StatusCode::OK);
+            let meta = ResponseMeta::default();
+            return self.data_to_response(req, &meta, status_code, &JsonOutput(body.clone()));
+        }
+        if let ResourceSpecs::Redirect { to, permanent } = &self.ep.resource {
+            return match redirect_location(to, &ri) {
+                Ok(location) => redirect_response(&location, *permanent),
+                Err(err) => self.error_response(req, err),
+            };
+        }
+        if self.ep.if_match
+            && let Some(version) = req.headers().get(IF_MATCH).and_then(|v| v.to_str().ok())
+        {
+            ri.set_if_match(version);
+        }
+
+        let slot = match self.acquire_slot().await {
+            Ok(slot) => slot,
+            Err(err) => return self.error_response(req, err),
+        };
+
+        let db_conns = match self.acquire_db_conns().await {
+            Ok(permit) => permit,
+            Err(err) => return self.error_response(req, err),
+        };
+
+        // Streams keep the slot and connections permit until they are finished
+        if let ResourceSpecs::SseQuery {
+            data_source,
+            query,
+            poll_ms,
+        } = &self.ep.resource
+        {
+            let resp = self.stream_sse(req, ri, state, data_source, query, *poll_ms);
+            return hold_permit(hold_permit(resp, db_conns), slot);
+        }
+
+        if self.ep.stream_csv
+            && let ResourceSpecs::Query { data_source, query } = &self.ep.resource
+            && (csv::accepts_csv(req) || self.response_format(req) == ResponseFormat::Csv)
+        {
+            let resp = self.stream_csv(req, ri, state, data_source, query).await;
+            return hold_permit(hold_permit(resp, db_conns), slot);
+        }
 
         // TODO rethink this with new storage api for query/query_one
-        let data = match self.handle_resource(&ri, &state).await {
+        let data = match self.handle_resource(&ri, state).await {
             Ok(d) => d,
             Err(err) => {
-                let (status_code, data) = to_error_object(err);
-                return self.data_to_response(&req, None, status_code, &data);
+                return self.error_response(req, err);
+            }
+        };
+
+        if self.ep.no_content {
+            return HttpResponse::NoContent().finish();
+        }
+
+        self.build_response(req, &ri, data)
+    }
+
+    /// Respond with CSV rows written as they are fetched from the storage.
+    async fn stream_csv(
+        &self,
+        req: &HttpRequest,
+        input: RequestInput,
+        state: &Data<XepakAppData>,
+        data_source: &str,
+        query: &str,
+    ) -> HttpResponse {
+        let started = self
+            .filter_rows(query, &input)
+            .map(Cow::into_owned)
+            .and_then(|query| {
+                let ds = self.route_data_source(state, data_source, &query)?;
+                ds.query_stream(query, input)
+            });
+        let mut rows = match started {
+            Ok(rows) => rows,
+            Err(err) => return self.error_response(req, err),
+        };
+
+        // Status could be changed only until the first row
+        match rows.recv().await {
+            Some(Err(err)) => self.error_response(req, err),
+            first => csv::stream_response(first.and_then(Result::ok), rows, &self.ep.columns),
+        }
+    }
+
+    /// Take one of `max_concurrency` slots, it is released when the permit is dropped.
+    async fn acquire_slot(&self) -> Result<Option<OwnedSemaphorePermit>, XepakError> {
+        let Some(slots) = self.concurrency.as_ref() else {
+            return Ok(None);
+        };
+
+        let permit = match self.ep.concurrency_wait_ms {
+            Some(wait) => {
+                tokio::time::timeout(Duration::from_millis(wait), slots.clone().acquire_owned())
+                    .await
+                    .ok()
+                    .and_then(Result::ok)
+            }
+            None => slots.clone().try_acquire_owned().ok(),
+        };
+
+        permit.map(Some).ok_or_else(|| {
+            XepakError::Unavailable(format!("Too many concurrent requests at URI: {}", self.uri))
+        })
+    }
+
+    /// Wait for `max_db_conns` permits for connections the resource uses at the same time
+    /// (composite parts run concurrently), they are released when the permit is dropped.
+    async fn acquire_db_conns(&self) -> Result<Option<OwnedSemaphorePermit>, XepakError> {
+        let Some(conns) = self.db_conns.as_ref() else {
+            return Ok(None);
+        };
+
+        let needed = match &self.ep.resource {
+            ResourceSpecs::Composite { parts } if parts.iter().all(|p| p.expose.is_empty()) => {
+                parts.len().clamp(1, self.ep.max_db_conns.unwrap_or(1))
             }
+            _ => 1,
+        };
+
+        conns
+            .clone()
+            .acquire_many_owned(needed as u32)
+            .await
+            .map(Some)
+            .map_err(|e| XepakError::WeScrewed(format!("DB connections cap is closed: {e}")))
+    }
+
+    /// Run WebSocket query after the usual pre-processing (e.g. auth) of the handshake request.
+    async fn handle_websocket(
+        &self,
+        req: HttpRequest,
+        state: Data<XepakAppData>,
+        payload: web::Payload,
+    ) -> HttpResponse {
+        let ResourceSpecs::WebSocketQuery {
+            data_source,
+            query,
+            poll_ms,
+        } = &self.ep.resource
+        else {
+            return self.error_response(
+                &req,
+                XepakError::Unexpected("WebSocket route for non WebSocket resource".to_string()),
+            );
+        };
+
+        let input = match self.pre_process_request(&req, &state, &Bytes::new()).await {
+            Ok(input) => input,
+            Err(err) => return self.error_response(&req, err),
+        };
+        let (ds, query) = match self.polled_query(&state, &input, data_source, query) {
+            Ok(prepared) => prepared,
+            Err(err) => return self.error_response(&req, err),
+        };
+        // Socket keeps the permit until it is closed
+        let db_conns = match self.acquire_db_conns().await {
+            Ok(permit) => permit,
+            Err(err) => return self.error_response(&req, err),
+        };
+
+        let cbor = accepts_cbor(&req);
+        let sort_keys = self.sort_keys;
+        let interval = Duration::from_millis(*poll_ms);
+        let resp =
+            websocket::stream_polled(&req, payload, interval, self.expose_db_errors, move || {
+                let (ds, query, input) = (ds.clone(), query.clone(), input.clone());
+                async move {
+                    let records = ds.query(ResourceRequest::new(&query, &input)).await?;
+                    let output = ResourceOutput::Records(records);
+                    if sort_keys {
+                        websocket::encode_frame(&SortedKeys(&output), cbor)
+                    } else {
+                        websocket::encode_frame(&output, cbor)
+                    }
+                }
+            });
+        hold_permit(resp, db_conns)
+    }
+
+    /// Respond with Server-Sent Events of the query results.
+    fn stream_sse(
+        &self,
+        req: &HttpRequest,
+        input: RequestInput,
+        state: &Data<XepakAppData>,
+        data_source: &str,
+        query: &str,
+        poll_ms: u64,
+    ) -> HttpResponse {
+        let (ds, query) = match self.polled_query(state, &input, data_source, query) {
+            Ok(prepared) => prepared,
+            Err(err) => return self.error_response(req, err),
         };
 
-        self.build_response(&req, &ri, data)
+        let sort_keys = self.sort_keys;
+        let interval = Duration::from_millis(poll_ms);
+        sse::stream_polled(interval, self.expose_db_errors, move || {
+            let (ds, query, input) = (ds.clone(), query.clone(), input.clone());
+            async move {
+                let records = ds.query(ResourceRequest::new(&query, &input)).await?;
+                let output = ResourceOutput::Records(records);
+                let data = if sort_keys {
+                    serde_json::to_string(&SortedKeys(&output))
+                } else {
+                    serde_json::to_string(&output)
+                };
+                data.map_err(|e| XepakError::WeScrewed(e.to_string()))
+            }
+        })
+    }
+
+    /// Data source and query with row filter for the resources polled by the clients.
+    fn polled_query(
+        &self,
+        state: &XepakAppData,
+        input: &RequestInput,
+        data_source: &str,
+        query: &str,
+    ) -> Result<(Storage, String), XepakError> {
+        let query = self.filter_rows(query, input)?.into_owned();
+        let ds = self.route_data_source(state, data_source, &query)?.clone();
+        Ok((ds, query))
+    }
+
+    /// Execute write of the `no_content` endpoint applying `on_no_rows` policy.
+    async fn execute_no_content(
+        &self,
+        ds: &Storage,
+        rr: ResourceRequest<'_, RequestInput>,
+    ) -> Result<ResourceOutput, XepakError> {
+        let affected = ds.execute(rr).await?;
+        if affected == 0 {
+            self.no_rows_affected()?;
+        }
+        Ok(ResourceOutput::Record(None))
+    }
+
+    /// Apply `on_no_rows` policy to the write without affected rows.
+    fn no_rows_affected(&self) -> Result<(), XepakError> {
+        match self.ep.on_no_rows {
+            NoRowsPolicy::NoContent => Ok(()),
+            NoRowsPolicy::NotFound => Err(XepakError::NotFound(format!(
+                "No rows affected at URI: {}",
+                self.uri
+            ))),
+            NoRowsPolicy::Conflict => Err(XepakError::Conflict(format!(
+                "No rows affected at URI: {}",
+                self.uri
+            ))),
+        }
     }
 
     async fn pre_process_request(
@@ -131,9 +688,13 @@ impl EndpointHandler {
         let mut input = RequestInput::new(
             self.ep.schema.clone(),
             self.ep.strict_schema,
-            &self.ep.uri,
+            &self.uri,
             req.path(),
         );
+        input.set_http_request(req);
+        input.set_arg_precedence(self.arg_precedence.clone());
+        input.set_arg_limits(state.get_arg_limits());
+        input.convert_path_args()?;
 
         for p in self.processors.as_ref() {
             p.handle(req, state, body, &mut input)?;
@@ -146,28 +707,44 @@ impl EndpointHandler {
         &self,
         input: &RequestInput,
         state: &Data<XepakAppData>,
-    ) -> Result<Vec<HashMap<String, XepakValue>>, XepakError> {
+    ) -> Result<ResourceOutput, XepakError> {
         match &self.ep.resource {
             ResourceSpecs::Query { data_source, query } => {
-                let Some(ds) = state.get_data_source(data_source) else {
-                    return Err(XepakError::Cfg(format!(
-                        "Data source does not exists \"{data_source}\""
-                    )));
-                };
+                if let Some(prepared) = self.prepared_query.as_ref() {
+                    let ds = self.route_data_source(state, data_source, &prepared.sql)?;
+                    return ds
+                        .query_prepared(prepared, input)
+                        .await
+                        .map(ResourceOutput::Records);
+                }
+
+                let query = self.filter_rows(query, input)?;
+                let ds = self.route_data_source(state, data_source, &query)?;
+                let rr = ResourceRequest::new(&query, input);
+
+                if self.ep.no_content && !self.ep.if_match {
+                    return self.execute_no_content(ds, rr).await;
+                }
+
+                if self.ep.if_match {
+                    let updated = ds.execute(rr).await?;
+                    if updated == 0 {
+                        return Err(XepakError::PreconditionFailed(
+                            "Record was changed or does not exist".to_string(),
+                        ));
+                    }
+                    return Ok(ResourceOutput::Record(Some(Record::from([(
+                        "updated".to_string(),
+                        XepakValue::Integer(updated as i128),
+                    )]))));
+                }
 
-                let rr = ResourceRequest::new(query, input);
-                ds.query(rr).await
+                ds.query(rr).await.map(ResourceOutput::Records)
             }
             ResourceSpecs::QueryScript { data_source, .. } => {
-                let Some(ds) = state.get_data_source(data_source) else {
-                    return Err(XepakError::Cfg(format!(
-                        "Data source does not exists \"{data_source}\""
-                    )));
-                };
-
                 let result = execute_script_blocking(
                     state.clone(),
-                    self.ep.uri.clone(),
+                    self.uri.to_string(),
                     self.rhai_engine.clone(),
                     self.handler_script.clone(),
                     input.clone(),
@@ -183,96 +760,527 @@ impl EndpointHandler {
                     )));
                 };
 
+                let query = self.filter_rows(&query, input)?;
+                let ds = self.route_data_source(state, data_source, &query)?;
+                let rr = ResourceRequest::new(&query, input);
+                if self.ep.no_content {
+                    return self.execute_no_content(ds, rr).await;
+                }
+                ds.query(rr).await.map(ResourceOutput::Records)
+            }
+            ResourceSpecs::QuerySwitch {
+                data_source,
+                on_arg,
+                cases,
+                default,
+            } => {
+                let case = match input.get_arg_value(on_arg) {
+                    Some(v) if !v.is_null() => v.as_string(),
+                    _ => default.clone().ok_or_else(|| {
+                        XepakError::Input(format!("Argument \"{on_arg}\" is required"))
+                    })?,
+                };
+
+                let Some(query) = cases.get(&case) else {
+                    return Err(XepakError::Input(format!(
+                        "Unsupported value \"{case}\" for argument \"{on_arg}\""
+                    )));
+                };
+
+                let query = self.filter_rows(query, input)?;
+                let ds = self.route_data_source(state, data_source, &query)?;
+                let rr = ResourceRequest::new(&query, input);
+                if self.ep.no_content {
+                    return self.execute_no_content(ds, rr).await;
+                }
+                ds.query(rr).await.map(ResourceOutput::Records)
+            }
+            ResourceSpecs::PartialUpdate {
+                data_source,
+                table,
+                columns,
+                key,
+                returning,
+            } => {
+                let query = build_partial_update(table, columns, key, returning, input)?;
+                let ds = self.route_data_source(state, data_source, &query)?;
+                let rr = ResourceRequest::new(&query, input);
+                if self.ep.no_content {
+                    return self.execute_no_content(ds, rr).await;
+                }
+                ds.query(rr).await.map(ResourceOutput::Records)
+            }
+            ResourceSpecs::Insert {
+                data_source,
+                table,
+                columns,
+                return_row,
+            } => {
+                let query = build_insert(table, columns, *return_row, input);
+                let ds = self.route_data_source(state, data_source, &query)?;
                 let rr = ResourceRequest::new(&query, input);
-                ds.query(rr).await
+
+                if self.ep.no_content {
+                    return self.execute_no_content(ds, rr).await;
+                }
+                if *return_row {
+                    return ds.query_one(rr).await.map(ResourceOutput::Record);
+                }
+
+                let inserted = ds.execute(rr).await?;
+                Ok(ResourceOutput::Record(Some(Record::from([(
+                    "inserted".to_string(),
+                    XepakValue::Integer(inserted as i128),
+                )]))))
+            }
+            ResourceSpecs::BulkInsert {
+                data_source,
+                table,
+                columns,
+            } => {
+                if input.get_batch().is_empty() {
+                    return Err(XepakError::Input(
+                        "Nothing to insert, expected JSON array of objects".to_string(),
+                    ));
+                }
+
+                let values: Vec<String> = columns.iter().map(|c| format!("{{{{{c}}}}}")).collect();
+                let query = format!(
+                    "INSERT INTO {table} ({}) VALUES ({})",
+                    columns.join(", "),
+                    values.join(", ")
+                );
+                let ds = self.route_data_source(state, data_source, &query)?;
+
+                let batch: Vec<RequestInput> = input
+                    .get_batch()
+                    .iter()
+                    .map(|row| input.with_args(row.clone()))
+                    .collect();
+                let inserted = ds.execute_batch(&query, &batch).await?;
+
+                Ok(ResourceOutput::Record(Some(Record::from([(
+                    "inserted".to_string(),
+                    XepakValue::Integer(inserted as i128),
+                )]))))
+            }
+            ResourceSpecs::Composite { parts } => self.handle_composite(parts, input, state).await,
+            ResourceSpecs::Static { .. } | ResourceSpecs::Redirect { .. } => {
+                Err(XepakError::Unexpected(
+                    "Static and redirect resources must be handled before storage".to_string(),
+                ))
+            }
+            ResourceSpecs::WebSocketQuery { .. } | ResourceSpecs::SseQuery { .. } => {
+                Err(XepakError::Unexpected(
+                    "Polled resources must be streamed instead of handled once".to_string(),
+                ))
             }
         }
     }
 
-    fn data_to_response<R>(
+    /// Insert row filter condition into the query (if endpoint has row filter).
+    /// Callers passing the filter checks get always true condition instead.
+    fn filter_rows<'q>(
         &self,
-        req: &HttpRequest,
-        input: Option<&RequestInput>,
-        status_code: StatusCode,
-        data: &R,
-    ) -> HttpResponse
-    where
-        R: Serialize + minicbor::Encode<()>,
-    {
-        let cbor_response = if let Some(accept) = req.headers().get(ACCEPT)
-            && accept.eq(CONTENT_TYPE_CBOR)
-        {
-            true
-        } else {
-            false
+        query: &'q str,
+        input: &RequestInput,
+    ) -> Result<Cow<'q, str>, XepakError> {
+        let Some(row_filter) = &self.ep.row_filter else {
+            return Ok(Cow::Borrowed(query));
         };
 
-        // TODO should normalize headers output instead providing limit/offset
-        let (limit, offset) = match input {
-            Some(inp) => (inp.get_limit(), inp.get_offset()),
-            None => (0, 0),
+        if !query.contains(ROW_FILTER_PLACEHOLDER) {
+            return Err(XepakError::Cfg(format!(
+                "Query must contain {ROW_FILTER_PLACEHOLDER} for row filter at URI: {}",
+                self.ep.uri
+            )));
+        }
+
+        let unfiltered = match (self.row_filter_unless.as_ref(), input.get_auth()) {
+            (Some(check), Some((id, roles))) => check.is_allowed(&id.as_string(), roles),
+            _ => false,
         };
 
-        if cbor_response {
-            to_cbor_response(status_code, data, limit, offset)
+        let condition = if unfiltered {
+            "1 = 1".to_string()
         } else {
-            to_json_response(status_code, data, limit, offset)
-        }
+            format!("({})", row_filter.condition)
+        };
+
+        Ok(Cow::Owned(
+            query.replace(ROW_FILTER_PLACEHOLDER, &condition),
+        ))
     }
-    fn build_response(
+
+    /// Pick data source for the query: endpoint read/write data sources take precedence
+    /// (according to the query intent) over the resource data source.
+    fn route_data_source<'s>(
         &self,
-        req: &HttpRequest,
-        input: &RequestInput,
-        data: Vec<HashMap<String, XepakValue>>,
-    ) -> HttpResponse {
-        if self.ep.single_record_response {
-            if data.len() > 1 {
-                tracing::warn!("More than one record returned for URI:{}", req.uri());
-            }
+        state: &'s XepakAppData,
+        data_source: &str,
+        query: &str,
+    ) -> Result<&'s Storage, XepakError> {
+        let routed = if is_read_query(query) {
+            self.ep.read_data_source.as_deref()
+        } else {
+            self.ep.write_data_source.as_deref()
+        };
+        let data_source = routed.unwrap_or(data_source);
 
-            let Some(one_row_data) = data.first() else {
-                let (status_code, err_data) = to_error_object(XepakError::NotFound(format!(
-                    "Record not found at URI: {}",
-                    req.uri()
-                )));
+        state.get_data_source(data_source).ok_or_else(|| {
+            XepakError::Cfg(format!("Data source does not exists \"{data_source}\""))
+        })
+    }
 
-                return self.data_to_response(req, Some(input), status_code, &err_data);
-            };
+    /// Run all parts concurrently and combine results in the parts order.
+    async fn handle_composite(
+        &self,
+        parts: &[QueryPart],
+        input: &RequestInput,
+        state: &Data<XepakAppData>,
+    ) -> Result<ResourceOutput, XepakError> {
+        if parts.iter().any(|p| !p.expose.is_empty()) {
+            return self.handle_composite_sequential(parts, input, state).await;
+        }
 
-            self.data_to_response(req, Some(input), StatusCode::OK, &one_row_data)
-        } else {
-            self.data_to_response(req, Some(input), StatusCode::OK, &data)
+        let mut tasks = Vec::with_capacity(parts.len());
+        for part in parts {
+            let ds = composite_data_source(state, part)?.clone();
+            let query = self.filter_rows(&part.query, input)?.into_owned();
+            let part = part.clone();
+            let input = input.clone();
+            tasks.push(tokio::spawn(async move {
+                let output = query_part(&ds, &part, &query, &input).await?;
+                Ok::<_, XepakError>((part.name, output))
+            }));
         }
-    }
-}
 
-impl Handler<EndpointHandlerArgs> for EndpointHandler {
-    type Output = HttpResponse;
-    type Future = Pin<Box<dyn Future<Output = Self::Output> + 'static>>;
+        let mut result = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            result.push(task.await.map_err(XepakError::other)??);
+        }
 
-    fn call(&self, (req, state, body): EndpointHandlerArgs) -> Self::Future {
-        tracing::debug!("Handler CALL called for {:?}", self.ep);
-        let this = self.clone();
-        Box::pin(async move { this.handle(req, state, body).await })
+        Ok(ResourceOutput::Object(result))
     }
-}
 
-impl HttpServiceFactory for EndpointHandler {
-    fn register(self, config: &mut actix_web::dev::AppService) {
-        let name = format!("Entrypoint: {}", self.ep.uri);
-        tracing::debug!("Registering [{:?}]: {name}", std::thread::current().id());
-
-        web::resource(self.ep.uri.clone())
-            .route(web::route().to(self))
-            // .route(web::route().to(move |req, state, body| {
-            //     let h = self.clone();
-            //     async move { h.handle(req, state, body).await }
-            // }))
-            // .route(web::route().to(self))
-            .register(config);
+    /// Execute parts one by one, so exposed values are available to the next parts.
+    async fn handle_composite_sequential(
+        &self,
+        parts: &[QueryPart],
+        input: &RequestInput,
+        state: &Data<XepakAppData>,
+    ) -> Result<ResourceOutput, XepakError> {
+        let mut input = input.clone();
+        let mut result = Vec::with_capacity(parts.len());
+        for part in parts {
+            let ds = composite_data_source(state, part)?;
+            let query = self.filter_rows(&part.query, &input)?;
+            let output = query_part(ds, part, &query, &input).await?;
 
-        // web::resource("/user/list")
-        //     // .route(web::route().to(self))
+            if !part.expose.is_empty() {
+                let first = match &output {
+                    ResourceOutput::Record(record) => record.as_ref(),
+                    ResourceOutput::Records(records) => records.first(),
+                    _ => None,
+                };
+                let exposed: Vec<(String, XepakValue)> = part
+                    .expose
+                    .iter()
+                    .map(|column| {
+                        let value = first
+                            .and_then(|r| r.get(column))
+                            .cloned()
+                            .unwrap_or(XepakValue::Null);
+                        (exposed_arg_name(&part.name, column), value)
+                    })
+                    .collect();
+                input = input.with_extra_args(exposed);
+            }
+
+            result.push((part.name.clone(), output));
+        }
+
+        Ok(ResourceOutput::Object(result))
+    }
+
+    /// Format from the query parameter, unknown format is an input error.
+    fn requested_format(&self, req: &HttpRequest) -> Result<Option<ResponseFormat>, XepakError> {
+        let Some(name) = query_param(req, &self.format_arg) else {
+            return Ok(None);
+        };
+        ResponseFormat::from_name(&name).map(Some).ok_or_else(|| {
+            XepakError::Input(format!(
+                "Unknown response format \"{name}\", expected json, cbor, text or csv"
+            ))
+        })
+    }
+
+    /// Forced content type, then the one from the query parameter and `Accept` header.
+    fn response_format(&self, req: &HttpRequest) -> ResponseFormat {
+        self.ep
+            .force_content_type
+            .or_else(|| self.requested_format(req).ok().flatten())
+            .unwrap_or_else(|| negotiate_format(req))
+    }
+
+    fn data_to_response<R>(
+        &self,
+        req: &HttpRequest,
+        meta: &ResponseMeta,
+        status_code: StatusCode,
+        data: &R,
+    ) -> HttpResponse
+    where
+        R: Serialize + minicbor::Encode<()>,
+    {
+        let pretty = self.ep.pretty || (self.allow_pretty && pretty_requested(req));
+        let format = self.response_format(req);
+        if format == ResponseFormat::Csv {
+            return match csv::records_to_csv(data, &self.ep.columns) {
+                Ok(body) => to_csv_response(status_code, body, meta, &self.pagination_headers),
+                Err(err) => {
+                    let (status_code, data) = to_error_object(err);
+                    to_json_response(status_code, &data, meta, &self.pagination_headers, pretty)
+                }
+            };
+        }
+        if format == ResponseFormat::Text && !self.ep.envelope {
+            return to_text_response(status_code, data, meta, &self.pagination_headers, Some(req));
+        }
+        if self.ep.envelope {
+            let no_meta = ResponseMeta::default();
+            return if status_code.is_success() {
+                encode_response(
+                    format,
+                    status_code,
+                    &Envelope { data, meta },
+                    &no_meta,
+                    &self.pagination_headers,
+                    pretty,
+                )
+            } else {
+                encode_response(
+                    format,
+                    status_code,
+                    &ErrorEnvelope(data),
+                    &no_meta,
+                    &self.pagination_headers,
+                    pretty,
+                )
+            };
+        }
+
+        encode_response(
+            format,
+            status_code,
+            data,
+            meta,
+            &self.pagination_headers,
+            pretty,
+        )
+    }
+
+    fn response_meta(&self, input: &RequestInput, data: &ResourceOutput) -> ResponseMeta {
+        let mut meta = ResponseMeta {
+            limit: input.get_limit(),
+            offset: input.get_offset(),
+            next_cursor: None,
+        };
+
+        // Full page means there could be more records after the last one
+        if let Some(keyset) = &self.ep.keyset
+            && let ResourceOutput::Records(records) = data
+            && meta.limit > 0
+            && records.len() >= meta.limit
+            && let Some(last) = records.last().and_then(|r| r.get(&keyset.column))
+        {
+            match encode_cursor(last) {
+                Ok(cursor) => meta.next_cursor = Some(cursor),
+                Err(e) => tracing::error!("Can't encode next page cursor: {e}"),
+            }
+        }
+
+        meta
+    }
+
+    fn build_response(
+        &self,
+        req: &HttpRequest,
+        input: &RequestInput,
+        mut data: ResourceOutput,
+    ) -> HttpResponse {
+        let meta = self.response_meta(input, &data);
+
+        if !self.input_only_fields.is_empty() {
+            data.remove_fields(&self.input_only_fields);
+        }
+
+        if self.ep.allow_sparse_fields
+            && let Some(fields) = input.get_arg_value(FIELDS_ARG)
+            && !fields.is_null()
+        {
+            let fields = fields.as_string();
+            let fields: Vec<&str> = fields
+                .split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .collect();
+            data.retain_fields(&fields);
+        }
+
+        if let Some(digits) = self.ep.float_precision {
+            data.round_floats(digits);
+        }
+
+        if let Some(max) = self.ep.json_big_int_as_string
+            && self.response_format(req) != ResponseFormat::Cbor
+        {
+            data.big_ints_to_text(max);
+        }
+
+        if let Some(spec) = &self.ep.json_pointer {
+            return match extract_json(spec, &data) {
+                Ok(value) => self.data_to_response(req, &meta, StatusCode::OK, &JsonOutput(value)),
+                Err(err) => self.error_response(req, err),
+            };
+        }
+
+        if let Some(column) = &self.ep.key_by {
+            let keep_last = self.ep.on_duplicate_key == DuplicateKeyPolicy::LastWins;
+            data = match data.key_by(column, keep_last) {
+                Ok(data) => data,
+                Err(err) => return self.error_response(req, err),
+            };
+        }
+
+        if self.ep.single_record_response
+            && let ResourceOutput::Records(data) = data
+        {
+            if data.len() > 1 {
+                tracing::warn!("More than one record returned for URI:{}", req.uri());
+            }
+
+            let Some(one_row_data) = data.first() else {
+                let (status_code, err_data) = to_error_object(XepakError::NotFound(format!(
+                    "Record not found at URI: {}",
+                    req.uri()
+                )));
+
+                return self.record_response(req, &meta, status_code, &err_data);
+            };
+
+            self.record_response(req, &meta, StatusCode::OK, one_row_data)
+        } else if self.sort_keys {
+            self.data_to_response(req, &meta, StatusCode::OK, &SortedKeys(&data))
+        } else {
+            self.data_to_response(req, &meta, StatusCode::OK, &data)
+        }
+    }
+
+    fn record_response(
+        &self,
+        req: &HttpRequest,
+        meta: &ResponseMeta,
+        status_code: StatusCode,
+        data: &Record,
+    ) -> HttpResponse {
+        if self.sort_keys {
+            self.data_to_response(req, meta, status_code, &SortedKeys(data))
+        } else {
+            self.data_to_response(req, meta, status_code, &RecordOutput(data))
+        }
+    }
+
+    fn error_response(&self, req: &HttpRequest, err: XepakError) -> HttpResponse {
+        if let XepakError::InvalidInput(errors) = &err {
+            let errors = serde_json::to_value(errors).unwrap_or_default();
+            let (status_code, data) = to_error_object(err);
+            let mut data = serde_json::to_value(data).unwrap_or_default();
+            data["errors"] = errors;
+            return self.data_to_response(
+                req,
+                &ResponseMeta::default(),
+                status_code,
+                &JsonOutput(data),
+            );
+        }
+
+        let (status_code, data) = client_error_object(err, self.expose_db_errors);
+        self.record_response(req, &ResponseMeta::default(), status_code, &data)
+    }
+}
+
+impl Handler<EndpointHandlerArgs> for EndpointHandler {
+    type Output = HttpResponse;
+    type Future = Pin<Box<dyn Future<Output = Self::Output> + 'static>>;
+
+    fn call(&self, (req, state, body): EndpointHandlerArgs) -> Self::Future {
+        tracing::debug!("Handler CALL called for {:?}", self.ep);
+        let this = self.clone();
+        if !self.catch_panics {
+            return Box::pin(async move { this.handle(req, state, body).await });
+        }
+
+        Box::pin(async move {
+            let uri = req.uri().clone();
+            let accepts_cbor = accepts_cbor(&req);
+            match AssertUnwindSafe(this.handle(req, state, body))
+                .catch_unwind()
+                .await
+            {
+                Ok(resp) => resp,
+                Err(panic) => {
+                    let request_id = uuid::Uuid::new_v4();
+                    let reason = panic
+                        .downcast_ref::<&str>()
+                        .copied()
+                        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                        .unwrap_or("unknown");
+                    tracing::error!(
+                        "Handler panicked, request id {request_id}, URI {uri}: {reason}"
+                    );
+
+                    let (status_code, data) = to_error_object(XepakError::WeScrewed(format!(
+                        "Internal server error, request id: {request_id}"
+                    )));
+                    let (meta, headers) = (ResponseMeta::default(), PaginationHeaders::default());
+                    if accepts_cbor {
+                        to_cbor_response(status_code, &RecordOutput(&data), &meta, &headers)
+                    } else {
+                        to_json_response(status_code, &data, &meta, &headers, false)
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl HttpServiceFactory for EndpointHandler {
+    fn register(self, config: &mut actix_web::dev::AppService) {
+        for uri in self.ep.uri.iter() {
+            let name = format!("Entrypoint: {uri}");
+            tracing::debug!("Registering [{:?}]: {name}", std::thread::current().id());
+
+            let handler = Self {
+                uri: Arc::from(uri.as_str()),
+                ..self.clone()
+            };
+
+            let route = if let ResourceSpecs::WebSocketQuery { .. } = handler.ep.resource {
+                // Handshake body is the connection stream, so it must not be read as bytes
+                web::get().to(move |req, state, payload| {
+                    let handler = handler.clone();
+                    async move { handler.handle_websocket(req, state, payload).await }
+                })
+            } else {
+                web::route().to(handler)
+            };
+
+            web::resource(uri.clone()).route(route).register(config);
+        }
+
+        // web::resource("/user/list")
+        //     // .route(web::route().to(self))
         //     // .route(web::route().to(move |req, state, body| {
         //     //     let h = self.clone();
         //     //     async move { h.handle(req, state, body).await }
@@ -282,22 +1290,310 @@ impl HttpServiceFactory for EndpointHandler {
     }
 }
 
+fn composite_data_source<'a>(
+    state: &'a XepakAppData,
+    part: &QueryPart,
+) -> Result<&'a Storage, XepakError> {
+    state.get_data_source(&part.data_source).ok_or_else(|| {
+        XepakError::Cfg(format!(
+            "Data source does not exists \"{}\"",
+            part.data_source
+        ))
+    })
+}
+
+async fn query_part(
+    ds: &Storage,
+    part: &QueryPart,
+    query: &str,
+    input: &RequestInput,
+) -> Result<ResourceOutput, XepakError> {
+    let rr = ResourceRequest::new(query, input);
+    if part.single_record {
+        Ok(ResourceOutput::Record(ds.query_one(rr).await?))
+    } else {
+        Ok(ResourceOutput::Records(ds.query(rr).await?))
+    }
+}
+
+/// Argument name for the column exposed by the composite part, e.g. `@user.id`.
+fn exposed_arg_name(part: &str, column: &str) -> String {
+    format!("@{part}.{column}")
+}
+
+/// Exposed values must be referenced only after the part that exposes them.
+fn validate_composite_parts(parts: &[QueryPart], uri: &EndpointUri) -> Result<(), XepakError> {
+    let mut exposed = HashSet::new();
+    for part in parts {
+        for (arg, _) in SqlLexer::new(&part.query) {
+            if arg.starts_with('@') && !exposed.contains(arg) {
+                return Err(XepakError::Cfg(format!(
+                    "Part \"{}\" uses {arg} that is not exposed by previous parts for URI: {uri}",
+                    part.name
+                )));
+            }
+        }
+        exposed.extend(part.expose.iter().map(|c| exposed_arg_name(&part.name, c)));
+    }
+    Ok(())
+}
+
+/// Everything except unreserved characters (RFC 3986) is encoded in a path segment.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Replace `{arg}` in the redirect target with path args.
+/// Path args keep the request encoding, so they are decoded and re-encoded as a path segment.
+fn redirect_location(to: &str, input: &RequestInput) -> Result<String, XepakError> {
+    let mut location = String::with_capacity(to.len());
+    let mut rest = to;
+
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + len];
+        let Some(value) = input.path_args.get(name) else {
+            return Err(XepakError::Cfg(format!(
+                "Redirect path argument \"{name}\" not found for target: {to}"
+            )));
+        };
+
+        // Value is taken from the raw request path, it is re-encoded as a single segment
+        let value = value.as_string();
+        let decoded = percent_decode_str(&value).decode_utf8_lossy();
+        location.push_str(&rest[..start]);
+        location.extend(utf8_percent_encode(&decoded, PATH_SEGMENT));
+        rest = &rest[start + len + 1..];
+    }
+    location.push_str(rest);
+
+    Ok(location)
+}
+
+fn redirect_response(location: &str, permanent: bool) -> HttpResponse {
+    let status_code = if permanent {
+        StatusCode::MOVED_PERMANENTLY
+    } else {
+        StatusCode::FOUND
+    };
+
+    HttpResponse::build(status_code)
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// Build `UPDATE` query with key args for the columns present in the input.
+/// Only names from the specs get into the query text, values are bound as usual.
+fn build_partial_update(
+    table: &str,
+    columns: &[String],
+    key: &[String],
+    returning: &[String],
+    input: &RequestInput,
+) -> Result<String, XepakError> {
+    let set: Vec<String> = columns
+        .iter()
+        .filter(|c| input.has_any_arg(c))
+        .map(|c| format!("{c} = {{{{{c}}}}}"))
+        .collect();
+
+    if set.is_empty() {
+        return Err(XepakError::Input(format!(
+            "Nothing to update, expected any of: {}",
+            columns.join(", ")
+        )));
+    }
+
+    if key.is_empty() {
+        return Err(XepakError::Cfg(
+            "Partial update key must not be empty".to_string(),
+        ));
+    }
+
+    let mut condition = Vec::with_capacity(key.len());
+    for k in key {
+        if !input.has_any_arg(k) {
+            return Err(XepakError::Input(format!("Argument \"{k}\" is required")));
+        }
+        condition.push(format!("{k} = {{{{{k}}}}}"));
+    }
+
+    let mut query = format!(
+        "UPDATE {table} SET {} WHERE {}",
+        set.join(", "),
+        condition.join(" AND ")
+    );
+    if !returning.is_empty() {
+        query.push_str(&format!(" RETURNING {}", returning.join(", ")));
+    }
+
+    Ok(query)
+}
+
+/// Build `INSERT` query for the columns present in the input, the rest get DB defaults.
+fn build_insert(table: &str, columns: &[String], return_row: bool, input: &RequestInput) -> String {
+    let provided: Vec<&String> = columns.iter().filter(|c| input.has_any_arg(c)).collect();
+
+    let mut query = if provided.is_empty() {
+        format!("INSERT INTO {table} DEFAULT VALUES")
+    } else {
+        let names: Vec<&str> = provided.iter().map(|c| c.as_str()).collect();
+        let values: Vec<String> = provided.iter().map(|c| format!("{{{{{c}}}}}")).collect();
+        format!(
+            "INSERT INTO {table} ({}) VALUES ({})",
+            names.join(", "),
+            values.join(", ")
+        )
+    };
+    if return_row {
+        query.push_str(" RETURNING *");
+    }
+
+    query
+}
+
+/// Parse JSON column of the first record and take a value by the pointer.
+fn extract_json(
+    spec: &JsonPointerSpecs,
+    data: &ResourceOutput,
+) -> Result<serde_json::Value, XepakError> {
+    let record = match data {
+        ResourceOutput::Records(records) => records.first(),
+        ResourceOutput::Record(record) => record.as_ref(),
+        ResourceOutput::Object(_) => None,
+    };
+
+    let column = spec.column.as_str();
+    let json = match record.and_then(|r| r.get(column)) {
+        Some(v) if !v.is_null() => v.as_string(),
+        _ => {
+            return Err(XepakError::NotFound(format!(
+                "No JSON value in column \"{column}\""
+            )));
+        }
+    };
+
+    let mut value: serde_json::Value = serde_json::from_str(&json)
+        .map_err(|e| XepakError::Decode(format!("Column \"{column}\" is not a JSON: {e}")))?;
+
+    match value.pointer_mut(&spec.pointer) {
+        Some(v) => Ok(v.take()),
+        None => Err(XepakError::NotFound(format!(
+            "JSON value not found at \"{}\"",
+            spec.pointer
+        ))),
+    }
+}
+
+/// Default service for requests that matched no endpoint, responds with the `not_found` error.
+pub fn not_found_route(message: String) -> actix_web::Route {
+    web::to(move |req: HttpRequest| {
+        let (status_code, data) = to_error_object(XepakError::NotFound(message.clone()));
+        let resp = encode_response(
+            negotiate_format(&req),
+            status_code,
+            &RecordOutput(&data),
+            &ResponseMeta::default(),
+            &PaginationHeaders::default(),
+            false,
+        );
+        async move { resp }
+    })
+}
+
+/// Encode data in the format, metadata goes to headers.
+fn encode_response<R>(
+    format: ResponseFormat,
+    status_code: StatusCode,
+    data: &R,
+    meta: &ResponseMeta,
+    headers: &PaginationHeaders,
+    pretty: bool,
+) -> HttpResponse
+where
+    R: Serialize + minicbor::Encode<()>,
+{
+    match format {
+        ResponseFormat::Json => to_json_response(status_code, data, meta, headers, pretty),
+        ResponseFormat::Cbor => to_cbor_response(status_code, data, meta, headers),
+        ResponseFormat::Text => to_text_response(status_code, data, meta, headers, None),
+        ResponseFormat::Csv => match csv::records_to_csv(data, &[]) {
+            Ok(body) => to_csv_response(status_code, body, meta, headers),
+            Err(err) => {
+                let (status_code, data) = to_error_object(err);
+                to_json_response(status_code, &data, meta, headers, pretty)
+            }
+        },
+    }
+}
+
+/// Format requested with the `Accept` header, JSON by default.
+fn negotiate_format(req: &HttpRequest) -> ResponseFormat {
+    if accepts_cbor(req) {
+        ResponseFormat::Cbor
+    } else {
+        ResponseFormat::Json
+    }
+}
+
+/// Query parameters accepted with `reject_unknown_query`.
+fn known_query_args(ep: &EndpointSpecs, app: &XepakAppData) -> HashSet<String> {
+    let mut known: HashSet<String> = ep.args.iter().chain(ep.schema.keys()).cloned().collect();
+    known.insert(app.get_format_arg().to_string());
+    known.insert(ep.limit_arg.clone());
+    known.insert(ep.offset_arg.clone());
+    if let Some(keyset) = &ep.keyset {
+        known.insert(keyset.cursor_arg.clone());
+    }
+    if let ResourceSpecs::QuerySwitch { on_arg, .. } = &ep.resource {
+        known.insert(on_arg.clone());
+    }
+    if app.is_allow_pretty() {
+        known.insert(PRETTY_ARG.to_string());
+    }
+    known
+}
+
+/// Query string has `pretty=1` or `pretty=true`.
+fn pretty_requested(req: &HttpRequest) -> bool {
+    query_param(req, PRETTY_ARG).is_some_and(|value| value == "1" || value == "true")
+}
+
+/// First value of the query string parameter.
+fn query_param(req: &HttpRequest, name: &str) -> Option<String> {
+    serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|(n, value)| (n == name).then_some(value))
+}
+
+fn accepts_cbor(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(ACCEPT)
+        .is_some_and(|accept| accept.eq(CONTENT_TYPE_CBOR))
+}
+
 fn to_json_response<T: Serialize>(
     code: StatusCode,
     data: &T,
-    limit: usize,
-    offset: usize,
+    meta: &ResponseMeta,
+    headers: &PaginationHeaders,
+    pretty: bool,
 ) -> HttpResponse<BoxBody> {
-    match serde_json::to_string(data) {
+    let body = if pretty {
+        serde_json::to_string_pretty(data)
+    } else {
+        serde_json::to_string(data)
+    };
+    match body {
         Ok(body) => {
             let mut resp = HttpResponseBuilder::new(code);
             resp.append_header((CONTENT_TYPE, CONTENT_TYPE_JSON));
-            if limit > 0 {
-                resp.append_header((LIMIT_HEADER, limit.to_string()));
-            }
-            if offset > 0 {
-                resp.append_header((OFFSET_HEADER, offset.to_string()));
-            }
+            meta.append_headers(&mut resp, headers);
 
             resp.body(body)
         }
@@ -308,22 +1604,55 @@ fn to_json_response<T: Serialize>(
     }
 }
 
+fn to_csv_response(
+    code: StatusCode,
+    body: String,
+    meta: &ResponseMeta,
+    headers: &PaginationHeaders,
+) -> HttpResponse<BoxBody> {
+    let mut resp = HttpResponseBuilder::new(code);
+    resp.append_header((CONTENT_TYPE, csv::CONTENT_TYPE_CSV));
+    meta.append_headers(&mut resp, headers);
+    resp.body(body)
+}
+
+/// Successful text response honors the `Range` header of the request (if any).
+fn to_text_response<T: Serialize>(
+    code: StatusCode,
+    data: &T,
+    meta: &ResponseMeta,
+    headers: &PaginationHeaders,
+    req: Option<&HttpRequest>,
+) -> HttpResponse<BoxBody> {
+    let body = match serde_json::to_value(data) {
+        Ok(serde_json::Value::String(text)) => text,
+        Ok(value) => value.to_string(),
+        Err(e) => {
+            tracing::error!("Can't serialize response: {e}");
+            return HttpResponse::InternalServerError().body(format!("{e}"));
+        }
+    };
+
+    let mut resp = HttpResponseBuilder::new(code);
+    resp.append_header((CONTENT_TYPE, CONTENT_TYPE_TEXT));
+    meta.append_headers(&mut resp, headers);
+    match req {
+        Some(req) if code == StatusCode::OK => range::ranged_body(req, resp, body),
+        _ => resp.body(body),
+    }
+}
+
 fn to_cbor_response<T: minicbor::Encode<()>>(
     code: StatusCode,
     data: &T,
-    limit: usize,
-    offset: usize,
+    meta: &ResponseMeta,
+    headers: &PaginationHeaders,
 ) -> HttpResponse<BoxBody> {
     match minicbor::to_vec(data) {
         Ok(body) => {
             let mut resp = HttpResponseBuilder::new(code);
             resp.append_header((CONTENT_TYPE, CONTENT_TYPE_CBOR));
-            if limit > 0 {
-                resp.append_header((LIMIT_HEADER, limit.to_string()));
-            }
-            if offset > 0 {
-                resp.append_header((OFFSET_HEADER, offset.to_string()));
-            }
+            meta.append_headers(&mut resp, headers);
 
             resp.body(body)
         }
@@ -333,3 +1662,1702 @@ fn to_cbor_response<T: minicbor::Encode<()>>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::{App, test};
+
+    use super::*;
+    use crate::testing::{TestEnv, exec, exec_all, sqlite_app_data, test_service};
+
+    #[actix_web::test]
+    async fn composite_from_two_sources() {
+        let env = TestEnv {
+            app_data: sqlite_app_data("composite", &["users", "orders"], "").await,
+        };
+        exec_all(
+            &env.app_data,
+            "users",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+            INSERT INTO users VALUES (1, 'alice')",
+        )
+        .await;
+        exec_all(
+            &env.app_data,
+            "orders",
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER);
+            INSERT INTO orders VALUES (10, 1), (11, 1)",
+        )
+        .await;
+
+        let app = env
+            .service(&[r#"
+            uri = "/user/{user_id}/summary"
+
+            [resource]
+            type = "composite"
+
+            [[resource.parts]]
+            name = "user"
+            data_source = "users"
+            query = "SELECT name FROM users WHERE id = {{user_id}}"
+            single_record = true
+
+            [[resource.parts]]
+            name = "orders"
+            data_source = "orders"
+            query = "SELECT id FROM orders WHERE user_id = {{user_id}} ORDER BY id"
+            "#])
+            .await;
+
+        let req = test::TestRequest::get().uri("/user/1/summary").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "user": {"name": "alice"},
+                "orders": [{"id": 10}, {"id": 11}],
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn composite_part_uses_exposed_value() {
+        let env = TestEnv::new(
+            "composite-exposed",
+            "",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+            CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER);
+            INSERT INTO users VALUES (1, 'alice'), (2, 'bob');
+            INSERT INTO orders VALUES (10, 1), (11, 2), (12, 2)",
+        )
+        .await;
+
+        let unknown = env.handler(
+            r#"
+            uri = "/broken"
+
+            [resource]
+            type = "composite"
+
+            [[resource.parts]]
+            name = "orders"
+            query = "SELECT id FROM orders WHERE user_id = {{@user.id}}"
+            "#,
+        );
+        assert!(matches!(unknown, Err(XepakError::Cfg(_))));
+
+        let app = env
+            .service(&[r#"
+            uri = "/users/{name}/orders"
+
+            [resource]
+            type = "composite"
+
+            [[resource.parts]]
+            name = "user"
+            query = "SELECT id, name FROM users WHERE name = {{name}}"
+            single_record = true
+            expose = ["id"]
+
+            [[resource.parts]]
+            name = "orders"
+            query = "SELECT id FROM orders WHERE user_id = {{@user.id}} ORDER BY id"
+            "#])
+            .await;
+
+        let req = test::TestRequest::get()
+            .uri("/users/bob/orders")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "user": {"id": 2, "name": "bob"},
+                "orders": [{"id": 11}, {"id": 12}],
+            })
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/users/carol/orders")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"user": null, "orders": []}));
+    }
+
+    #[actix_web::test]
+    async fn reads_and_writes_routed() {
+        let env = TestEnv {
+            app_data: sqlite_app_data("rw-routing", &["primary", "replica"], "").await,
+        };
+        for (ds, name) in [("primary", "primary"), ("replica", "replica")] {
+            exec_all(
+                &env.app_data,
+                ds,
+                &format!("CREATE TABLE items (name TEXT); INSERT INTO items VALUES ('{name}')"),
+            )
+            .await;
+        }
+
+        let app = env
+            .service(&[
+                r#"
+                uri = "/items"
+                read_data_source = "replica"
+                write_data_source = "primary"
+
+                [resource]
+                type = "query"
+                query = "SELECT name FROM items"
+                "#,
+                r#"
+                uri = "/items/add"
+                read_data_source = "replica"
+                write_data_source = "primary"
+
+                [resource]
+                type = "query"
+                query = "INSERT INTO items VALUES ({{name}}) RETURNING name"
+                "#,
+            ])
+            .await;
+
+        let req = test::TestRequest::get().uri("/items").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"name": "replica"}]));
+
+        let req = test::TestRequest::get()
+            .uri("/items/add?name=new")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let primary = env.app_data.get_data_source("primary").unwrap();
+        let rows = exec(primary, "SELECT name FROM items ORDER BY name")
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["name"].as_string(), "new");
+    }
+
+    #[actix_web::test]
+    async fn multiple_uris_share_handler() {
+        let app = test_service(
+            "multi-uri",
+            "",
+            &[r#"
+            uri = ["/legacy/item/{id}", "/v2/items/{id}"]
+            args = ["id"]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{id}} AS id"
+            "#],
+        )
+        .await;
+
+        for uri in ["/legacy/item/7", "/v2/items/7"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body, serde_json::json!([{"id": "7"}]));
+        }
+    }
+
+    #[actix_web::test]
+    async fn permanent_redirect_with_path_arg() {
+        let app = test_service(
+            "redirect",
+            "",
+            &[r#"
+            uri = "/old/users/{id}"
+
+            [resource]
+            type = "redirect"
+            to = "/v2/users/{id}/profile"
+            permanent = true
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/old/users/a%20b")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            resp.headers().get(LOCATION).unwrap(),
+            "/v2/users/a%20b/profile"
+        );
+
+        // Reserved characters can't change the redirect target
+        let req = test::TestRequest::get()
+            .uri("/old/users/..%2Fadmin%3Fx=1%23top%25")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(LOCATION).unwrap(),
+            "/v2/users/..%2Fadmin%3Fx%3D1%23top%25/profile"
+        );
+    }
+
+    #[actix_web::test]
+    async fn static_body_as_json_and_cbor() {
+        let app = test_service(
+            "static-body",
+            "",
+            &[r#"
+            uri = "/flags"
+
+            [resource]
+            type = "static"
+            status = 203
+            body = { dark_mode = true, beta = "off" }
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/flags").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NON_AUTHORITATIVE_INFORMATION);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body, serde_json::json!({"dark_mode": true, "beta": "off"}));
+
+        let req = test::TestRequest::get()
+            .uri("/flags")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_CBOR);
+
+        let body = test::read_body(resp).await;
+        let mut d = minicbor::Decoder::new(&body);
+        assert_eq!(d.map().unwrap(), Some(2));
+        let mut decoded = HashMap::new();
+        for _ in 0..2 {
+            let key = d.str().unwrap().to_string();
+            let value = match d.datatype().unwrap() {
+                minicbor::data::Type::Bool => d.bool().unwrap().to_string(),
+                _ => d.str().unwrap().to_string(),
+            };
+            decoded.insert(key, value);
+        }
+        assert_eq!(decoded["dark_mode"], "true");
+        assert_eq!(decoded["beta"], "off");
+    }
+
+    #[actix_web::test]
+    async fn delete_responds_no_content() {
+        let env = TestEnv::new(
+            "no-content",
+            "",
+            "CREATE TABLE items (id INTEGER); INSERT INTO items VALUES (1), (2)",
+        )
+        .await;
+        let app = env
+            .service(&[
+                r#"
+                uri = "/items/{id}"
+                no_content = true
+                on_no_rows = "not_found"
+
+                [schema.id]
+                type = "int"
+
+                [resource]
+                type = "query"
+                query = "DELETE FROM items WHERE id = {{id}}"
+                "#,
+                r#"
+                uri = "/items/{id}/script"
+                no_content = true
+                on_no_rows = "not_found"
+
+                [resource]
+                type = "query_script"
+                script = '"DELETE FROM items WHERE id = {{id}}"'
+                "#,
+                r#"
+                uri = "/items/{id}/patch"
+                no_content = true
+                on_no_rows = "conflict"
+                processor = [{ type = "parse_body_args" }]
+
+                [resource]
+                type = "partial_update"
+                table = "items"
+                columns = ["id"]
+                key = ["id"]
+                "#,
+            ])
+            .await;
+
+        let req = test::TestRequest::delete().uri("/items/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(test::read_body(resp).await.is_empty());
+
+        let rows = exec(env.storage(), "SELECT id FROM items").await.unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let req = test::TestRequest::delete().uri("/items/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Policy applies to other write resources too
+        let req = test::TestRequest::delete()
+            .uri("/items/1/script")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::patch()
+            .uri("/items/1/patch")
+            .set_json(serde_json::json!({"id": 1}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let req = test::TestRequest::patch()
+            .uri("/items/2/patch")
+            .set_json(serde_json::json!({"id": 2}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let req = test::TestRequest::delete()
+            .uri("/items/2/script")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        // Resources without a single write can't count affected rows
+        let bulk = env.handler(
+            r#"
+            uri = "/items/bulk"
+            no_content = true
+            on_no_rows = "not_found"
+            resource = { type = "bulk_insert", table = "items", columns = ["id"] }
+            "#,
+        );
+        assert!(matches!(bulk.err(), Some(XepakError::Cfg(_))));
+    }
+
+    #[actix_web::test]
+    async fn insert_returns_generated_columns() {
+        let app = test_service(
+            "insert-return-row",
+            "CREATE TABLE notes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                text TEXT,
+                created_at TEXT DEFAULT CURRENT_TIMESTAMP
+            )",
+            &[r#"
+            uri = "/notes"
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "insert"
+            table = "notes"
+            columns = ["text"]
+            return_row = true
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/notes")
+            .set_json(serde_json::json!({"text": "hello"}))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["id"], 1);
+        assert_eq!(body["text"], "hello");
+        assert!(
+            body["created_at"].as_str().is_some_and(|s| !s.is_empty()),
+            "{body}"
+        );
+    }
+
+    #[actix_web::test]
+    async fn max_concurrency_rejects_or_queues() {
+        let slow_query = "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 300000) SELECT count(*) AS n FROM c";
+        let app = test_service(
+            "max-concurrency",
+            "",
+            &[
+                &format!(
+                    r#"
+                    uri = "/heavy"
+                    max_concurrency = 1
+
+                    [resource]
+                    type = "query"
+                    query = "{slow_query}"
+                    "#
+                ),
+                &format!(
+                    r#"
+                    uri = "/queued"
+                    max_concurrency = 1
+                    concurrency_wait_ms = 10000
+
+                    [resource]
+                    type = "query"
+                    query = "{slow_query}"
+                    "#
+                ),
+            ],
+        )
+        .await;
+
+        let statuses = |uri: &'static str| {
+            futures_util::future::join_all((0..3).map(|_| {
+                let req = test::TestRequest::get().uri(uri).to_request();
+                test::call_service(&app, req)
+            }))
+        };
+
+        let mut codes: Vec<u16> = statuses("/heavy")
+            .await
+            .iter()
+            .map(|r| r.status().as_u16())
+            .collect();
+        codes.sort();
+        assert_eq!(codes, vec![200, 503, 503]);
+
+        // Slot is released after the response
+        let req = test::TestRequest::get().uri("/heavy").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let codes: Vec<u16> = statuses("/queued")
+            .await
+            .iter()
+            .map(|r| r.status().as_u16())
+            .collect();
+        assert_eq!(codes, vec![200, 200, 200]);
+    }
+
+    #[actix_web::test]
+    async fn max_concurrency_covers_streams() {
+        let app = test_service(
+            "max-concurrency-streams",
+            "",
+            &[
+                r#"
+                uri = "/export"
+                stream_csv = true
+                max_concurrency = 1
+
+                [resource]
+                type = "query"
+                query = "SELECT 1 AS id"
+                "#,
+                r#"
+                uri = "/events"
+                max_concurrency = 1
+
+                [resource]
+                type = "sse_query"
+                query = "SELECT 1 AS id"
+                poll_ms = 60000
+                "#,
+            ],
+        )
+        .await;
+
+        let get = |uri: &'static str| {
+            test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(uri)
+                    .insert_header((ACCEPT, "text/csv"))
+                    .to_request(),
+            )
+        };
+
+        for uri in ["/export", "/events"] {
+            // Body is not consumed yet, so the stream still holds the slot
+            let streaming = get(uri).await;
+            assert_eq!(streaming.status(), StatusCode::OK);
+            assert_eq!(get(uri).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+            drop(streaming);
+            assert_eq!(get(uri).await.status(), StatusCode::OK);
+        }
+    }
+
+    #[actix_web::test]
+    async fn max_db_conns_covers_streams() {
+        let app = test_service(
+            "max-db-conns-streams",
+            "",
+            &[
+                r#"
+                uri = "/export"
+                stream_csv = true
+                max_db_conns = 1
+
+                [resource]
+                type = "query"
+                query = "SELECT 1 AS id"
+                "#,
+                r#"
+                uri = "/events"
+                max_db_conns = 1
+
+                [resource]
+                type = "sse_query"
+                query = "SELECT 1 AS id"
+                poll_ms = 60000
+                "#,
+            ],
+        )
+        .await;
+
+        let get = |uri: &'static str| {
+            test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(uri)
+                    .insert_header((ACCEPT, "text/csv"))
+                    .to_request(),
+            )
+        };
+
+        for uri in ["/export", "/events"] {
+            // Body is not consumed yet, so the next request waits for the permit
+            let streaming = get(uri).await;
+            assert_eq!(streaming.status(), StatusCode::OK);
+            let waiting = tokio::time::timeout(Duration::from_millis(200), get(uri)).await;
+            assert!(waiting.is_err(), "{uri} went past max_db_conns");
+
+            drop(streaming);
+            assert_eq!(get(uri).await.status(), StatusCode::OK);
+        }
+    }
+
+    #[actix_web::test]
+    async fn max_db_conns_leaves_pool_for_others() {
+        use crate::{cfg::XepakConf, server::init_app_data};
+
+        sqlx::any::install_default_drivers();
+        let db_file = crate::testing::temp_db_file("max-db-conns");
+        let conf: XepakConf = toml::from_str(&format!(
+            "[[storage]]\ntype = \"sqlite\"\nfile = {:?}\nmax_connections = 2\nacquire_timeout_ms = 100",
+            db_file.to_string_lossy()
+        ))
+        .unwrap();
+        let env = TestEnv {
+            app_data: init_app_data(std::path::Path::new("."), &conf)
+                .await
+                .unwrap(),
+        };
+
+        let slow_query = "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 2000000) SELECT count(*) AS n FROM c";
+        let app = env
+            .service(&[
+                &format!(
+                    r#"
+                    uri = "/report"
+                    max_db_conns = 1
+
+                    [resource]
+                    type = "query"
+                    query = "{slow_query}"
+                    "#
+                ),
+                r#"
+                uri = "/lookup"
+                resource = { type = "query", query = "SELECT 1 AS n" }
+                "#,
+            ])
+            .await;
+
+        // Reports wait for each other, so the lookup still gets the second connection
+        let uris = ["/report", "/report", "/report", "/lookup"];
+        let codes: Vec<u16> = futures_util::future::join_all(uris.map(|uri| {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            test::call_service(&app, req)
+        }))
+        .await
+        .iter()
+        .map(|r| r.status().as_u16())
+        .collect();
+        assert_eq!(codes, vec![200, 200, 200, 200]);
+
+        let zero = env.handler(
+            r#"
+            uri = "/zero"
+            max_db_conns = 0
+            resource = { type = "query", query = "SELECT 1" }
+            "#,
+        );
+        assert!(matches!(zero.err(), Some(XepakError::Cfg(_))));
+    }
+
+    #[actix_web::test]
+    async fn records_keyed_by_column() {
+        let app = test_service(
+            "key-by",
+            "CREATE TABLE items (id INTEGER, name TEXT);
+            INSERT INTO items VALUES (1, 'a'), (2, 'b'), (1, 'c')",
+            &[
+                r#"
+                uri = "/items"
+                key_by = "id"
+
+                [resource]
+                type = "query"
+                query = "SELECT id, name FROM items WHERE id = 2 OR name = 'a'"
+                "#,
+                r#"
+                uri = "/duplicates"
+                key_by = "id"
+
+                [resource]
+                type = "query"
+                query = "SELECT id, name FROM items ORDER BY name"
+                "#,
+                r#"
+                uri = "/strict"
+                key_by = "id"
+                on_duplicate_key = "error"
+
+                [resource]
+                type = "query"
+                query = "SELECT id, name FROM items"
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/items").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "1": {"id": 1, "name": "a"},
+                "2": {"id": 2, "name": "b"},
+            })
+        );
+
+        let req = test::TestRequest::get().uri("/duplicates").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["1"], serde_json::json!({"id": 1, "name": "c"}));
+
+        let req = test::TestRequest::get().uri("/strict").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_web::test]
+    async fn output_scope_arg_set_by_client_rejected() {
+        let app = test_service(
+            "arg-scope",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, pin TEXT)",
+            &[
+                r#"
+                uri = "/users"
+                single_record_response = true
+                processor = [{ type = "parse_body_args" }]
+
+                [schema]
+                id = { type = "int", scope = "output" }
+                name = { type = "text" }
+                pin = { type = "text", scope = "input" }
+
+                [resource]
+                type = "query"
+                query = "INSERT INTO users (name, pin) VALUES ({{name}}, {{pin}}) RETURNING id, name, pin"
+                "#,
+                r#"
+                uri = "/users/{id}"
+                single_record_response = true
+                schema = { id = { type = "int", scope = "output" } }
+
+                [resource]
+                type = "query"
+                query = "SELECT id, name FROM users WHERE id = {{id}}"
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(serde_json::json!({"id": 7, "name": "alice", "pin": "1234"}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("output only"));
+
+        // Input only arg is not returned back
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(serde_json::json!({"name": "alice", "pin": "1234"}))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"id": 1, "name": "alice"}));
+
+        // Path args come from the route, the output scope does not apply to them
+        let req = test::TestRequest::get().uri("/users/1").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"id": 1, "name": "alice"}));
+    }
+
+    #[actix_web::test]
+    async fn pretty_json_on_request() {
+        let app = TestEnv::new("pretty", "allow_pretty = true", "")
+            .await
+            .service(&[
+                r#"
+                uri = "/ping"
+
+                [resource]
+                type = "static"
+                body = { pong = true }
+                "#,
+                r#"
+                uri = "/pretty"
+                pretty = true
+
+                [resource]
+                type = "static"
+                body = { pong = true }
+                "#,
+            ])
+            .await;
+
+        let req = test::TestRequest::get().uri("/ping").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(&body[..], b"{\"pong\":true}".as_slice());
+
+        let req = test::TestRequest::get().uri("/ping?pretty=1").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(&body[..], b"{\n  \"pong\": true\n}".as_slice());
+
+        let req = test::TestRequest::get().uri("/pretty").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(&body[..], b"{\n  \"pong\": true\n}".as_slice());
+
+        // Query param is ignored unless allowed in the config
+        let app = test_service(
+            "pretty-off",
+            "",
+            &[r#"
+            uri = "/ping"
+
+            [resource]
+            type = "static"
+            body = { pong = true }
+            "#],
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/ping?pretty=1").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(&body[..], b"{\"pong\":true}".as_slice());
+    }
+
+    #[actix_web::test]
+    async fn unknown_path_structured_not_found() {
+        let app = test::init_service(
+            App::new().default_service(not_found_route("Nothing here".to_string())),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/missing").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_JSON);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body,
+            serde_json::json!({"code": "not_found", "message": "Nothing here"})
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/missing")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_CBOR);
+    }
+
+    #[actix_web::test]
+    async fn forced_content_type_ignores_accept() {
+        let app = test_service(
+            "forced-content-type",
+            "",
+            &[
+                r#"
+                uri = "/forced/json"
+                force_content_type = "application/json"
+                resource = { type = "static", body = { ok = true } }
+                "#,
+                r#"
+                uri = "/forced/text"
+                force_content_type = "text/plain"
+                resource = { type = "static", body = "healthy" }
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/forced/json")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_JSON);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body, serde_json::json!({"ok": true}));
+
+        let req = test::TestRequest::get().uri("/forced/text").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_TEXT);
+        assert_eq!(test::read_body(resp).await, "healthy");
+    }
+
+    #[actix_web::test]
+    async fn format_query_param_overrides_accept() {
+        let app = TestEnv::new("format-param", "format_arg = \"fmt\"", "")
+            .await
+            .service(&[r#"
+            uri = "/formats"
+            resource = { type = "static", body = [{ id = 1, name = "a,b" }] }
+            "#])
+            .await;
+
+        let req = test::TestRequest::get()
+            .uri("/formats?fmt=json")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_JSON);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body, serde_json::json!([{"id": 1, "name": "a,b"}]));
+
+        let req = test::TestRequest::get()
+            .uri("/formats?fmt=cbor")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_CBOR);
+        assert!(!test::read_body(resp).await.is_empty());
+
+        let req = test::TestRequest::get()
+            .uri("/formats?fmt=csv")
+            .insert_header((ACCEPT, CONTENT_TYPE_JSON))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(CONTENT_TYPE).unwrap(),
+            csv::CONTENT_TYPE_CSV
+        );
+        assert_eq!(test::read_body(resp).await, "id,name\r\n1,\"a,b\"\r\n");
+
+        let req = test::TestRequest::get()
+            .uri("/formats?fmt=xml")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn pagination_header_names_configured() {
+        let app = TestEnv::new(
+            "pagination-headers",
+            "limit_header = \"X-Pagination-Limit\"\noffset_header = \"X-Pagination-Offset\"",
+            "",
+        )
+        .await
+        .service(&[r#"
+            uri = "/numbers"
+            fetch_limit = 10
+
+            [resource]
+            type = "query"
+            query = "SELECT 1 AS n LIMIT {{-limit-}} OFFSET {{-offset-}}"
+            "#])
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/numbers?limit=5&offset=2")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let headers = resp.headers();
+        assert_eq!(headers.get("X-Pagination-Limit").unwrap(), "5");
+        assert_eq!(headers.get("X-Pagination-Offset").unwrap(), "2");
+        assert!(headers.get("X-Limit").is_none());
+        assert!(headers.get("X-Offset").is_none());
+    }
+
+    #[actix_web::test]
+    async fn db_errors_exposed_only_when_enabled() {
+        for (conf, exposed) in [("", false), ("expose_db_errors = true", true)] {
+            let app = TestEnv::new("expose-db-errors", conf, "")
+                .await
+                .service(&[r#"
+                uri = "/broken"
+
+                [resource]
+                type = "query"
+                query = "SELECT secret FROM missing_table"
+                "#])
+                .await;
+
+            let req = test::TestRequest::get().uri("/broken").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(!resp.status().is_success());
+
+            let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+            assert_eq!(body.contains("missing_table"), exposed, "{body}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn query_switch_by_arg() {
+        let app = test_service(
+            "query-switch",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+            INSERT INTO users VALUES (1, 'bob'), (2, 'alice')",
+            &[r#"
+            uri = "/users"
+
+            [resource]
+            type = "query_switch"
+            on_arg = "sort"
+            default = "id"
+            cases = { id = "SELECT name FROM users ORDER BY id", name = "SELECT name FROM users ORDER BY name" }
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/users").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"name": "bob"}, {"name": "alice"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/users?sort=name")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"name": "alice"}, {"name": "bob"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/users?sort=name;DROP")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn identifier_arg_from_allowlist() {
+        let app = test_service(
+            "identifier-arg",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+            INSERT INTO users VALUES (1, 'bob'), (2, 'alice')",
+            &[r#"
+            uri = "/users"
+            schema = { sort = { identifiers = ["id", "name"] } }
+
+            [resource]
+            type = "query"
+            query = "SELECT id FROM users ORDER BY {{!sort}}"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/users?sort=name")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"id": 2}, {"id": 1}]));
+
+        let req = test::TestRequest::get()
+            .uri("/users?sort=password")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn quoted_identifier_arg() {
+        let app = test_service(
+            "quoted-identifier-arg",
+            "CREATE TABLE tasks (id INTEGER PRIMARY KEY, \"order\" INTEGER);
+            INSERT INTO tasks VALUES (1, 2), (2, 1)",
+            &[r#"
+            uri = "/tasks"
+            schema = { sort = { identifiers = ["id", "order"], quote_identifier = true } }
+
+            [resource]
+            type = "query"
+            query = "SELECT id FROM tasks ORDER BY {{!sort}}"
+            "#],
+        )
+        .await;
+
+        // Keyword column works only when quoted
+        let req = test::TestRequest::get()
+            .uri("/tasks?sort=order")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"id": 2}, {"id": 1}]));
+    }
+
+    #[actix_web::test]
+    async fn big_int_as_string_in_json() {
+        let app = test_service(
+            "big_int_as_string_in_json",
+            "CREATE TABLE ids (id INTEGER PRIMARY KEY);
+            INSERT INTO ids (id) VALUES (42), (9007199254740993)",
+            &[r#"
+            uri = "/ids"
+            json_big_int_as_string = 9007199254740991
+
+            [resource]
+            type = "query"
+            query = "SELECT id FROM ids ORDER BY id"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/ids").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"id": 42}, {"id": "9007199254740993"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/ids")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let mut decoder = minicbor::Decoder::new(&body);
+        decoder.array().unwrap();
+        decoder.map().unwrap();
+        assert_eq!(decoder.str().unwrap(), "id");
+        decoder.skip().unwrap();
+        decoder.map().unwrap();
+        assert_eq!(decoder.str().unwrap(), "id");
+        assert_eq!(decoder.u64().unwrap(), 9007199254740993);
+    }
+
+    /// Walk CBOR item and fail on any indefinite-length container.
+    fn assert_definite_cbor(d: &mut minicbor::Decoder) {
+        use minicbor::data::Type;
+
+        match d.datatype().unwrap() {
+            Type::Array => {
+                let len = d.array().unwrap().expect("Array must have definite length");
+                for _ in 0..len {
+                    assert_definite_cbor(d);
+                }
+            }
+            Type::Map => {
+                let len = d.map().unwrap().expect("Map must have definite length");
+                for _ in 0..len * 2 {
+                    assert_definite_cbor(d);
+                }
+            }
+            Type::ArrayIndef | Type::MapIndef | Type::BytesIndef | Type::StringIndef => {
+                panic!("Indefinite length item at {}", d.position())
+            }
+            _ => d.skip().unwrap(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn cbor_definite_lengths() {
+        let app = test_service(
+            "cbor_definite_lengths",
+            "CREATE TABLE items (id INTEGER, name TEXT, price REAL);
+            INSERT INTO items VALUES (1, 'one', 1.5), (2, 'two', NULL), (3, 'three', 3.0)",
+            &[r#"
+            uri = "/items"
+
+            [resource]
+            type = "query"
+            query = "SELECT id, name, price FROM items ORDER BY id"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/items")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+
+        let mut decoder = minicbor::Decoder::new(&body);
+        assert_eq!(decoder.probe().array().unwrap(), Some(3));
+        assert_definite_cbor(&mut decoder);
+        assert_eq!(decoder.position(), body.len());
+    }
+
+    #[actix_web::test]
+    async fn cbor_missing_single_record() {
+        let app = test_service(
+            "cbor-missing-single",
+            "",
+            &[r#"
+            uri = "/item/{id}"
+            single_record_response = true
+
+            [resource]
+            type = "query"
+            query = "SELECT 1 AS id WHERE 1 = {{id}}"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/item/2")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_CBOR);
+
+        let body = test::read_body(resp).await;
+        let error: HashMap<String, String> = minicbor::decode(&body).unwrap();
+        assert_eq!(error["code"], "not_found");
+        assert!(error["message"].contains("/item/2"), "{error:?}");
+    }
+
+    #[actix_web::test]
+    async fn cbor_records_decoded() {
+        let app = test_service(
+            "cbor-records-decoded",
+            "CREATE TABLE items (id INTEGER, score INTEGER);
+            INSERT INTO items VALUES (1, 10), (2, 20)",
+            &[
+                r#"
+                uri = "/items"
+
+                [resource]
+                type = "query"
+                query = "SELECT id, score FROM items ORDER BY id"
+                "#,
+                r#"
+                uri = "/items/{id}"
+                single_record_response = true
+
+                [resource]
+                type = "query"
+                query = "SELECT id, score FROM items WHERE id = {{id}}"
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/items")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let records: Vec<HashMap<String, i64>> = minicbor::decode(&body).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                HashMap::from([("id".to_string(), 1), ("score".to_string(), 10)]),
+                HashMap::from([("id".to_string(), 2), ("score".to_string(), 20)]),
+            ]
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/items/2")
+            .insert_header((ACCEPT, CONTENT_TYPE_CBOR))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let record: HashMap<String, i64> = minicbor::decode(&body).unwrap();
+        assert_eq!(
+            record,
+            HashMap::from([("id".to_string(), 2), ("score".to_string(), 20)])
+        );
+    }
+
+    #[actix_web::test]
+    async fn sorted_keys_output() {
+        let app = TestEnv::new(
+            "sorted_keys_output",
+            "sort_keys = true",
+            "CREATE TABLE wide (d INTEGER, b INTEGER, a INTEGER, c INTEGER, e INTEGER);
+            INSERT INTO wide VALUES (4, 2, 1, 3, 5), (40, 20, 10, 30, 50)",
+        )
+        .await
+        .service(&[r#"
+            uri = "/wide"
+
+            [resource]
+            type = "query"
+            query = "SELECT * FROM wide"
+            "#])
+        .await;
+
+        let mut bodies = Vec::new();
+        for _ in 0..5 {
+            let req = test::TestRequest::get().uri("/wide").to_request();
+            bodies.push(test::call_and_read_body(&app, req).await);
+        }
+
+        let expected = r#"[{"a":1,"b":2,"c":3,"d":4,"e":5},{"a":10,"b":20,"c":30,"d":40,"e":50}]"#;
+        for body in bodies {
+            assert_eq!(std::str::from_utf8(&body).unwrap(), expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn sparse_fields_requested() {
+        let app = test_service(
+            "sparse_fields_requested",
+            "CREATE TABLE users (id INTEGER, name TEXT, email TEXT, age INTEGER);
+            INSERT INTO users VALUES (1, 'neo', 'neo@matrix', 37)",
+            &[
+                r#"
+                uri = "/users"
+                allow_sparse_fields = true
+
+                [resource]
+                type = "query"
+                query = "SELECT id, name, email, age FROM users"
+                "#,
+                r#"
+                uri = "/all-fields"
+
+                [resource]
+                type = "query"
+                query = "SELECT id, name, email, age FROM users"
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/users?fields=name,%20email,password")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"name": "neo", "email": "neo@matrix"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/all-fields?fields=name")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body[0].as_object().unwrap().len(), 4);
+    }
+
+    #[actix_web::test]
+    async fn json_pointer_extracts_nested_value() {
+        let app = test_service(
+            "json_pointer_extracts_nested_value",
+            r#"CREATE TABLE profiles (id INTEGER, data TEXT);
+            INSERT INTO profiles VALUES (1, '{"address": {"city": "Kyiv", "zip": "01001"}}');
+            INSERT INTO profiles VALUES (2, '{"phone": "123"}')"#,
+            &[r#"
+            uri = "/profiles/{id}/city"
+            json_pointer = { column = "data", pointer = "/address/city" }
+
+            [resource]
+            type = "query"
+            query = "SELECT data FROM profiles WHERE id = {{id}}"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/profiles/1/city")
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, r#""Kyiv""#);
+
+        for uri in ["/profiles/2/city", "/profiles/3/city"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn boolean_columns_by_schema() {
+        // Any driver can't decode SQLite BOOLEAN declared columns as is, hence the cast.
+        // Only the column declared in the schema is mapped, the other stays integer.
+        let app = test_service(
+            "boolean-columns",
+            "CREATE TABLE flags (id INTEGER, v BOOLEAN);
+            INSERT INTO flags VALUES (1, TRUE), (2, FALSE)",
+            &[r#"
+            uri = "/flags"
+            schema = { v = { type = "boolean" } }
+
+            [resource]
+            type = "query"
+            query = "SELECT id, CAST(v AS BOOLEAN) AS v, CAST(v AS BOOLEAN) AS raw FROM flags ORDER BY id"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/flags").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {"id": 1, "v": true, "raw": 1},
+                {"id": 2, "v": false, "raw": 0}
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn uuid_args_validated() {
+        let app = test_service(
+            "uuid_args_validated",
+            "CREATE TABLE devices (id TEXT PRIMARY KEY, name TEXT);
+            INSERT INTO devices VALUES ('67e55044-10b1-426f-9247-bb680e5fe0c8', 'sensor')",
+            &[r#"
+            uri = "/devices"
+            schema = { id = { type = "uuid" } }
+
+            [resource]
+            type = "query"
+            query = "SELECT {{id}} AS id, name FROM devices WHERE id = {{id}}"
+            "#],
+        )
+        .await;
+
+        // Normalized to the canonical form before binding
+        let req = test::TestRequest::get()
+            .uri("/devices?id=67E55044-10B1-426F-9247-BB680E5FE0C8")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"id": "67e55044-10b1-426f-9247-bb680e5fe0c8", "name": "sensor"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/devices?id=67e55044-nope")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn default_schema_applied_to_endpoints() {
+        use crate::cfg::XepakSpecs;
+
+        let env = TestEnv::new("default_schema_applied_to_endpoints", "", "").await;
+
+        let mut specs: XepakSpecs = toml::from_str(
+            r#"
+            default_schema = { tenant = { type = "uuid", required = true } }
+
+            [[endpoint]]
+            uri = "/orders"
+            resource = { type = "query", query = "SELECT {{tenant}} AS tenant" }
+
+            [[endpoint]]
+            uri = "/notes"
+            schema = { tenant = { type = "text" } }
+            resource = { type = "query", query = "SELECT {{tenant}} AS tenant" }
+            "#,
+        )
+        .unwrap();
+        specs.apply_default_schema();
+
+        let mut app = App::new().app_data(Data::new(env.app_data.clone()));
+        for ep in specs.endpoint {
+            app = app.service(EndpointHandler::new(ep, &env.app_data).unwrap());
+        }
+        let app = test::init_service(app).await;
+
+        let call = |uri: &'static str| {
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request())
+        };
+
+        // Endpoint did not declare the arg, default entry validates it
+        assert_eq!(
+            call("/orders?tenant=acme").await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(call("/orders").await.status(), StatusCode::BAD_REQUEST);
+        let req = test::TestRequest::get()
+            .uri("/orders?tenant=67E55044-10B1-426F-9247-BB680E5FE0C8")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"tenant": "67e55044-10b1-426f-9247-bb680e5fe0c8"}])
+        );
+
+        // Endpoint entry overrides the default one
+        assert_eq!(call("/notes?tenant=acme").await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn enveloped_responses() {
+        let app = test_service(
+            "enveloped_responses",
+            "CREATE TABLE items (id INTEGER PRIMARY KEY);
+            INSERT INTO items (id) VALUES (1), (2), (3)",
+            &[
+                r#"
+                uri = "/items"
+                envelope = true
+                fetch_limit = 2
+
+                [resource]
+                type = "query"
+                query = "SELECT id FROM items ORDER BY id LIMIT {{-limit-}} OFFSET {{-offset-}}"
+                "#,
+                r#"
+                uri = "/items/{id}"
+                envelope = true
+                single_record_response = true
+
+                [resource]
+                type = "query"
+                query = "SELECT id FROM items WHERE id = {{id}}"
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/items?offset=1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get("X-Limit").is_none());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "data": [{"id": 2}, {"id": 3}],
+                "meta": {"limit": 2, "offset": 1}
+            })
+        );
+
+        let req = test::TestRequest::get().uri("/items/3").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!({"data": {"id": 3}, "meta": {"limit": 0, "offset": 0}})
+        );
+    }
+
+    #[actix_web::test]
+    async fn enveloped_errors() {
+        let app = test_service(
+            "enveloped_errors",
+            "",
+            &[r#"
+            uri = "/numbers"
+            envelope = true
+            schema = { n = { type = "int" } }
+
+            [resource]
+            type = "query"
+            query = "SELECT {{n}} AS n"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/numbers?n=abc").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_object().unwrap().len(), 1);
+        assert_eq!(body["error"]["code"], "bad_request");
+        assert!(body["error"]["message"].as_str().unwrap().contains("\"n\""));
+    }
+
+    #[actix_web::test]
+    async fn partial_update_patch() {
+        let env = TestEnv::new("partial_update_patch", "", "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT, age INTEGER, role TEXT);
+            INSERT INTO users VALUES (1, 'neo', 'neo@matrix', 37, 'user')").await;
+        let app = env
+            .service(&[r#"
+            uri = "/users/{id}"
+            processor = [{ type = "parse_body_args" }]
+            single_record_response = true
+
+            [resource]
+            type = "partial_update"
+            table = "users"
+            columns = ["name", "email", "age"]
+            key = ["id"]
+            returning = ["id", "name", "email", "age", "role"]
+            "#])
+            .await;
+
+        let req = test::TestRequest::patch()
+            .uri("/users/1")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"{"email": "thomas@anderson", "age": 38, "role": "admin"}"#)
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "id": 1, "name": "neo", "email": "thomas@anderson", "age": 38, "role": "user"
+            })
+        );
+
+        let req = test::TestRequest::patch()
+            .uri("/users/1")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"{"role": "admin"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::patch()
+            .uri("/users/2")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"{"name": "smith"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Update without WHERE clause is never built
+        let unkeyed = env.handler(
+            r#"
+            uri = "/users"
+            resource = { type = "partial_update", table = "users", columns = ["name"], key = [] }
+            "#,
+        );
+        assert!(matches!(unkeyed.err(), Some(XepakError::Cfg(_))));
+    }
+
+    #[actix_web::test]
+    async fn bulk_insert_rows() {
+        let app = test_service(
+            "bulk_insert_rows",
+            "CREATE TABLE metrics (sensor TEXT NOT NULL, value REAL)",
+            &[
+                r#"
+                uri = "/metrics/count"
+
+                [resource]
+                type = "query"
+                query = "SELECT COUNT(*) AS total FROM metrics"
+                "#,
+                r#"
+                uri = "/metrics"
+                processor = [{ type = "parse_body_args" }]
+                schema = { value = { type = "float" } }
+
+                [resource]
+                type = "bulk_insert"
+                table = "metrics"
+                columns = ["sensor", "value"]
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/metrics")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(
+                r#"[{"sensor": "a", "value": 1}, {"sensor": "b", "value": "2.5"}, {"sensor": "c", "value": null}]"#,
+            )
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"inserted": 3}));
+
+        // Second row violates NOT NULL, so the first one must be rolled back
+        let req = test::TestRequest::post()
+            .uri("/metrics")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"[{"sensor": "d", "value": 4}, {"sensor": null, "value": 5}]"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.status().is_success());
+
+        let req = test::TestRequest::get().uri("/metrics/count").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"total": 3}]));
+    }
+
+    #[actix_web::test]
+    async fn if_match_stale_version() {
+        let app = test_service(
+            "if_match_stale_version",
+            "CREATE TABLE docs (id INTEGER PRIMARY KEY, body TEXT, version INTEGER);
+            INSERT INTO docs VALUES (1, 'draft', 1)",
+            &[r#"
+            uri = "/docs/{id}"
+            processor = [{ type = "parse_body_args" }]
+            if_match = true
+
+            [resource]
+            type = "query"
+            query = """UPDATE docs SET body = {{body}}, version = version + 1
+                WHERE id = {{id}} AND version = {{-if-match-}}"""
+            "#],
+        )
+        .await;
+
+        let update = |version: &str| {
+            test::TestRequest::put()
+                .uri("/docs/1")
+                .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+                .insert_header((IF_MATCH, version.to_string()))
+                .set_payload(r#"{"body": "final"}"#)
+                .to_request()
+        };
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, update("\"1\"")).await;
+        assert_eq!(body, serde_json::json!({"updated": 1}));
+
+        // Another client still has the first version
+        let resp = test::call_service(&app, update("\"1\"")).await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        let req = test::TestRequest::put()
+            .uri("/docs/1")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"{"body": "final"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn panic_becomes_500() {
+        // Invalid UTF-8 blob can't be mapped to a value and the row mapping panics
+        let app = test_service(
+            "panic_becomes_500",
+            "",
+            &[r#"
+            uri = "/blob"
+
+            [resource]
+            type = "query"
+            query = "SELECT x'FFFE' AS data"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/blob").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("request id"));
+    }
+}
@@ -0,0 +1,155 @@
+use actix_web::{
+    HttpResponse, Resource,
+    web::{self, Data},
+};
+use futures_util::future::join_all;
+use serde::Deserialize;
+
+use crate::{
+    XepakError,
+    server::{XepakAppData, to_error_object},
+};
+
+/// Built-in probes: liveness responds while the process runs,
+/// readiness only when every storage responds.
+#[derive(Clone, Debug, Deserialize)]
+pub struct HealthEndpointsConf {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_livez_path")]
+    pub livez_path: String,
+
+    #[serde(default = "default_readyz_path")]
+    pub readyz_path: String,
+}
+
+impl Default for HealthEndpointsConf {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            livez_path: default_livez_path(),
+            readyz_path: default_readyz_path(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_livez_path() -> String {
+    "/livez".to_string()
+}
+
+fn default_readyz_path() -> String {
+    "/readyz".to_string()
+}
+
+pub fn livez_resource(conf: &HealthEndpointsConf) -> Resource {
+    web::resource(conf.livez_path.clone()).route(
+        web::get().to(|| async { HttpResponse::Ok().json(serde_json::json!({"status": "ok"})) }),
+    )
+}
+
+/// Specs are loaded before the server starts, so only storages are checked here.
+pub fn readyz_resource(conf: &HealthEndpointsConf) -> Resource {
+    web::resource(conf.readyz_path.clone()).route(web::get().to(
+        |state: Data<XepakAppData>| async move {
+            let storages = state.get_data_sources();
+            let pings = join_all(storages.iter().map(|(_, storage)| storage.ping())).await;
+
+            let failed: Vec<&str> = storages
+                .iter()
+                .zip(pings)
+                .filter_map(|((id, _), ping)| {
+                    let err = ping.err()?;
+                    tracing::warn!("Readiness check failed for storage \"{id}\": {err}");
+                    Some(id.as_str())
+                })
+                .collect();
+
+            if failed.is_empty() {
+                return HttpResponse::Ok().json(serde_json::json!({"status": "ready"}));
+            }
+            let (status_code, data) = to_error_object(XepakError::Unavailable(format!(
+                "Storages not ready: {}",
+                failed.join(", ")
+            )));
+            HttpResponse::build(status_code).json(data)
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, http::StatusCode, test};
+
+    use super::*;
+    use crate::testing::sqlite_app_data;
+
+    #[actix_web::test]
+    async fn livez_and_readyz_ok() {
+        let app_data = sqlite_app_data("health-ok", &["", "other"], "").await;
+        let conf = HealthEndpointsConf::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(app_data))
+                .service(livez_resource(&conf))
+                .service(readyz_resource(&conf)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/livez").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn readyz_fails_on_unreachable_storage() {
+        let app_data = sqlite_app_data(
+            "health-unreachable",
+            &[""],
+            r#"
+            [[storage]]
+            type = "sqlite"
+            id = "broken"
+            file = "/nonexistent/dir/xepak.db"
+            acquire_timeout_ms = 500
+            "#,
+        )
+        .await;
+        let conf: HealthEndpointsConf = toml::from_str(
+            r#"
+            livez_path = "/health/live"
+            readyz_path = "/health/ready"
+            "#,
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(app_data))
+                .service(livez_resource(&conf))
+                .service(readyz_resource(&conf)),
+        )
+        .await;
+
+        // Liveness does not depend on storages
+        let req = test::TestRequest::get().uri("/health/live").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/health/ready").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(
+            body["message"].as_str().unwrap().contains("broken"),
+            "{body}"
+        );
+    }
+}
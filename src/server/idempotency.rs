@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    body::{BoxBody, MessageBody},
+    http::{Method, StatusCode, header::HeaderMap},
+    web::Bytes,
+};
+
+use crate::XepakError;
+
+/// Request header with the client generated key for write requests.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Response replayed for a repeated idempotency key.
+#[derive(Clone)]
+struct StoredResponse {
+    stored_at: Instant,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+enum Entry {
+    /// Request with the key is still running
+    InFlight,
+    Done(StoredResponse),
+}
+
+/// Outcome of [`IdempotencyStore::claim`].
+pub enum Claim<'a> {
+    /// Key was already used, respond with the stored response
+    Replay(HttpResponse),
+    /// Key is reserved for this request, complete the reservation with its response
+    Reserved(Reservation<'a>),
+}
+
+/// In-memory responses of write requests by the idempotency key (with the method and path).
+/// Key is reserved before the request runs, so a concurrent duplicate gets 409
+/// instead of executing the query twice.
+pub struct IdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Key for the write request with [`IDEMPOTENCY_KEY_HEADER`], safe methods are never stored.
+    pub fn request_key(req: &HttpRequest) -> Option<String> {
+        if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+            return None;
+        }
+
+        let key = req.headers().get(IDEMPOTENCY_KEY_HEADER)?.to_str().ok()?;
+        Some(format!("{} {} {key}", req.method(), req.path()))
+    }
+
+    /// Replay stored response for the key or reserve the key for the current request.
+    pub fn claim(&self, key: String) -> Result<Claim<'_>, XepakError> {
+        let mut entries = self.lock_entries();
+        entries.retain(|_, e| match e {
+            Entry::InFlight => true,
+            Entry::Done(r) => r.stored_at.elapsed() <= self.ttl,
+        });
+
+        match entries.get(&key) {
+            Some(Entry::InFlight) => Err(XepakError::Conflict(
+                "Request with the same idempotency key is in progress".to_string(),
+            )),
+            Some(Entry::Done(stored)) => Ok(Claim::Replay(stored.to_response())),
+            None => {
+                entries.insert(key.clone(), Entry::InFlight);
+                Ok(Claim::Reserved(Reservation {
+                    store: self,
+                    key: Some(key),
+                }))
+            }
+        }
+    }
+
+    fn lock_entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl StoredResponse {
+    fn to_response(&self) -> HttpResponse {
+        let mut resp = HttpResponse::build(self.status).body(self.body.clone());
+        for (name, value) in &self.headers {
+            resp.headers_mut().insert(name.clone(), value.clone());
+        }
+        resp
+    }
+}
+
+/// Idempotency key held by the running request.
+/// Released without storing anything if dropped (e.g. request was cancelled).
+pub struct Reservation<'a> {
+    store: &'a IdempotencyStore,
+    key: Option<String>,
+}
+
+impl Reservation<'_> {
+    /// Keep response for the key (server errors are not stored, so the request could be retried).
+    pub fn complete(mut self, resp: HttpResponse) -> HttpResponse {
+        if resp.status().is_server_error() {
+            return resp;
+        }
+
+        let (resp, body) = resp.into_parts();
+        let body = match body.try_into_bytes() {
+            Ok(body) => body,
+            Err(body) => return resp.set_body(body),
+        };
+
+        let stored = StoredResponse {
+            stored_at: Instant::now(),
+            status: resp.status(),
+            headers: resp.headers().clone(),
+            body: body.clone(),
+        };
+        if let Some(key) = self.key.take() {
+            self.store.lock_entries().insert(key, Entry::Done(stored));
+        }
+
+        resp.set_body(BoxBody::new(body))
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.lock_entries().remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header::CONTENT_TYPE, test};
+
+    use super::*;
+    use crate::{
+        server::CONTENT_TYPE_JSON,
+        testing::{TestEnv, exec},
+    };
+
+    #[actix_web::test]
+    async fn idempotency_key_replays_response() {
+        let env = TestEnv::new(
+            "idempotency",
+            "",
+            "CREATE TABLE items (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT)",
+        )
+        .await;
+        let app = env
+            .service(&[r#"
+            uri = "/items/add"
+            idempotency_ttl_secs = 60
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "query"
+            query = "INSERT INTO items (name) VALUES ({{name}}) RETURNING id, name"
+            "#])
+            .await;
+
+        let mut bodies = Vec::new();
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/items/add")
+                .insert_header(("Idempotency-Key", "abc"))
+                .set_json(serde_json::json!({"name": "one"}))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_JSON);
+            bodies.push(test::read_body(resp).await);
+        }
+        assert_eq!(bodies[0], bodies[1]);
+
+        let rows = exec(env.storage(), "SELECT id FROM items").await.unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let req = test::TestRequest::post()
+            .uri("/items/add")
+            .insert_header(("Idempotency-Key", "def"))
+            .set_json(serde_json::json!({"name": "two"}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let rows = exec(env.storage(), "SELECT id FROM items").await.unwrap();
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn idempotency_key_reserved_while_running() {
+        let env = TestEnv::new(
+            "idempotency-concurrent",
+            "",
+            "CREATE TABLE items (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT)",
+        )
+        .await;
+        let app = env
+            .service(&[r#"
+            uri = "/items/add"
+            idempotency_ttl_secs = 60
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "query"
+            query = """
+            INSERT INTO items (name)
+            SELECT {{name}} FROM (
+                WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 1000000)
+                SELECT count(*) FROM c
+            )
+            RETURNING id, name
+            """
+            "#])
+            .await;
+
+        let add = || {
+            test::TestRequest::post()
+                .uri("/items/add")
+                .insert_header(("Idempotency-Key", "retry"))
+                .set_json(serde_json::json!({"name": "one"}))
+                .to_request()
+        };
+
+        // Retry arrives while the first request is still running
+        let (first, retry) = futures_util::future::join(
+            test::call_service(&app, add()),
+            test::call_service(&app, add()),
+        )
+        .await;
+        let mut codes = [first.status(), retry.status()];
+        codes.sort();
+        assert_eq!(codes, [StatusCode::OK, StatusCode::CONFLICT]);
+
+        let rows = exec(env.storage(), "SELECT id FROM items").await.unwrap();
+        assert_eq!(rows.len(), 1);
+
+        // Once finished, the stored response is replayed
+        let resp = test::call_service(&app, add()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let rows = exec(env.storage(), "SELECT id FROM items").await.unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+}
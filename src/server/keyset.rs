@@ -0,0 +1,121 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::Deserialize;
+
+use crate::{XepakError, types::XepakValue};
+
+/// Keyset (cursor) pagination settings.
+///
+/// Query must contain `{{-keyset-}}` placeholder in WHERE clause and be ordered by the `column`
+/// in ascending order. Placeholder is replaced with `column > ?` when cursor is provided.
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeysetSpecs {
+    /// Unique sortable column that is present in the output
+    pub column: String,
+
+    /// Argument with a cursor value returned by a previous page
+    #[serde(default = "default_cursor_arg")]
+    pub cursor_arg: String,
+}
+
+/// Encode page key value to the opaque cursor string.
+pub fn encode_cursor(value: &XepakValue) -> Result<String, XepakError> {
+    let json = serde_json::to_vec(value).map_err(XepakError::other)?;
+    Ok(URL_SAFE_NO_PAD.encode(json))
+}
+
+/// Decode page key value from the cursor string.
+pub fn decode_cursor(cursor: &str) -> Result<XepakValue, XepakError> {
+    let bad_cursor = |e: String| XepakError::Input(format!("Wrong cursor value: {e}"));
+
+    let json = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|e| bad_cursor(e.to_string()))?;
+    let value: serde_json::Value =
+        serde_json::from_slice(&json).map_err(|e| bad_cursor(e.to_string()))?;
+
+    XepakValue::try_from(&value).map_err(|e| bad_cursor(e.to_string()))
+}
+
+fn default_cursor_arg() -> String {
+    "cursor".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{TestRequest, call_service, read_body_json},
+    };
+
+    use super::*;
+    use crate::testing::test_service;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let cursor = encode_cursor(&XepakValue::Integer(42)).unwrap();
+        assert_eq!(decode_cursor(&cursor).unwrap().as_int().unwrap(), 42);
+
+        let cursor = encode_cursor(&XepakValue::from("2024-01-01 a/b")).unwrap();
+        assert_eq!(
+            decode_cursor(&cursor).unwrap().as_string(),
+            "2024-01-01 a/b"
+        );
+
+        assert!(matches!(
+            decode_cursor("not a cursor"),
+            Err(XepakError::Input(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn keyset_pagination_pages() {
+        let app = test_service(
+            "keyset_pagination_pages",
+            "CREATE TABLE items (id INTEGER PRIMARY KEY);
+            INSERT INTO items (id) VALUES (1), (2), (3), (4), (5)",
+            &[r#"
+            uri = "/items"
+            fetch_limit = 2
+            keyset = { column = "id" }
+
+            [resource]
+            type = "query"
+            query = "SELECT id FROM items WHERE {{-keyset-}} ORDER BY id LIMIT {{-limit-}}"
+            "#],
+        )
+        .await;
+
+        let mut pages = Vec::new();
+        let mut uri = "/items".to_string();
+        loop {
+            let req = TestRequest::get().uri(&uri).to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let cursor = resp
+                .headers()
+                .get("X-Next-Cursor")
+                .map(|v| v.to_str().unwrap().to_string());
+            let body: serde_json::Value = read_body_json(resp).await;
+            pages.push(body);
+
+            match cursor {
+                Some(cursor) => uri = format!("/items?cursor={cursor}"),
+                None => break,
+            }
+        }
+
+        assert_eq!(
+            pages,
+            vec![
+                serde_json::json!([{"id": 1}, {"id": 2}]),
+                serde_json::json!([{"id": 3}, {"id": 4}]),
+                serde_json::json!([{"id": 5}]),
+            ]
+        );
+
+        let req = TestRequest::get().uri("/items?cursor=bad").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
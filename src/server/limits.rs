@@ -0,0 +1,104 @@
+use actix_web::{
+    Error, HttpResponse,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    middleware::Next,
+};
+
+use crate::{XepakError, server::to_error_object};
+
+/// Request URI length limits, `None` means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UriLimits {
+    /// Max length of path with query string
+    pub max_uri: Option<usize>,
+    /// Max length of query string (without `?`)
+    pub max_query: Option<usize>,
+}
+
+impl UriLimits {
+    pub fn is_enabled(&self) -> bool {
+        self.max_uri.is_some() || self.max_query.is_some()
+    }
+
+    fn check(&self, req: &ServiceRequest) -> Result<(), XepakError> {
+        let uri = req.uri();
+        let uri_len = uri.path_and_query().map_or(0, |pq| pq.as_str().len());
+        if let Some(max) = self.max_uri
+            && uri_len > max
+        {
+            return Err(XepakError::UriTooLong(format!(
+                "URI length {uri_len} exceeds max {max}"
+            )));
+        }
+
+        let query_len = uri.query().map_or(0, str::len);
+        if let Some(max) = self.max_query
+            && query_len > max
+        {
+            return Err(XepakError::UriTooLong(format!(
+                "Query string length {query_len} exceeds max {max}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Respond with 414 before any processing if request URI exceeds the `limits`.
+pub async fn reject_long_uri<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    limits: UriLimits,
+) -> Result<ServiceResponse<B>, Error> {
+    if let Err(err) = limits.check(&req) {
+        tracing::debug!("Rejected {}: {err}", req.path());
+
+        let (status_code, data) = to_error_object(err.clone());
+        let resp = HttpResponse::build(status_code).json(data);
+        return Err(InternalError::from_response(err, resp).into());
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::StatusCode,
+        middleware::from_fn,
+        test::{self, TestRequest},
+        web,
+    };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn long_uri_rejected() {
+        let limits = UriLimits {
+            max_uri: Some(40),
+            max_query: Some(10),
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(move |req, next| reject_long_uri(req, next, limits)))
+                .default_service(web::to(|| async { "ok" })),
+        )
+        .await;
+
+        let resp = test::call_service(&app, TestRequest::get().uri("/a?x=1").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let long_path = format!("/{}", "a".repeat(40));
+        let long_query = format!("/a?x={}", "1".repeat(10));
+        for uri in [long_path, long_query] {
+            let err = test::try_call_service(&app, TestRequest::get().uri(&uri).to_request())
+                .await
+                .unwrap_err();
+            let resp = err.error_response();
+            assert_eq!(resp.status(), StatusCode::URI_TOO_LONG, "{uri}");
+        }
+    }
+}
@@ -1,35 +1,88 @@
+pub mod body;
+pub mod client_ip;
+pub mod compress;
+pub mod cors;
+pub mod csv;
+pub mod debug_io;
 pub mod handler;
+pub mod health;
+pub mod idempotency;
+pub mod keyset;
+pub mod limits;
 pub mod processor;
+pub mod range;
+pub mod sse;
+pub mod timeout;
+pub mod tls;
+pub mod version;
+pub mod websocket;
 
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use actix_web::App;
+use actix_http::HttpService;
+use actix_service::map_config;
 use actix_web::dev::Server;
+use actix_web::dev::{AppConfig, Service};
+use actix_web::http::KeepAlive;
 use actix_web::http::StatusCode;
-use actix_web::middleware::Logger;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::{Compress, Condition, Logger, from_fn};
 use actix_web::web::ServiceConfig;
+use actix_web::{App, HttpResponseBuilder};
+use actix_web::{HttpRequest, HttpResponse};
 use actix_web::{HttpServer, web::Data};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize};
 
 use crate::XepakError;
-use crate::auth::{SimpleAuthRegistry, auth_specs_to_registry};
+use crate::auth::{AuthMethod, SimpleAuthRegistry, auth_specs_to_registry};
 use crate::cfg::{XepakConf, XepakSpecs};
-use crate::schema::{Schema, convert_with_schema};
-use crate::server::handler::EndpointHandler;
+use crate::schema::{
+    ArgSchemaScope, Schema, convert_with_schema, reject_output_arg, validate_with_schema,
+};
+use crate::server::body::BodyLimits;
+use crate::server::client_ip::IpCidr;
+use crate::server::compress::filter_accept_encoding;
+use crate::server::cors::CorsConf;
+use crate::server::handler::{EndpointHandler, not_found_route};
+use crate::server::health::{livez_resource, readyz_resource};
+use crate::server::keyset::{KeysetSpecs, decode_cursor};
+use crate::server::limits::{UriLimits, reject_long_uri};
+use crate::server::timeout::request_timeout;
+use crate::server::tls::server_config;
+use crate::server::version::version_resource;
 use crate::storage::{SqlxRequestArgs, Storage, StorageRequestArgs, init_storage_connectors};
-use crate::types::XepakValue;
+use crate::types::{Record, XepakType, XepakValue};
 
 const OFFSET_HEADER: &str = "X-Offset";
 const LIMIT_HEADER: &str = "X-Limit";
+const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+/// Lowercase, so it could be used with `HeaderName::from_static`
+const AUTH_ID_HEADER: &str = "x-auth-id";
+const AUTH_METHOD_HEADER: &str = "x-auth-method";
 const CONTENT_TYPE_CBOR: &str = "application/cbor";
 const CONTENT_TYPE_JSON: &str = "application/json";
+const CONTENT_TYPE_TEXT: &str = "text/plain; charset=utf-8";
 
 #[derive(Clone)]
 pub struct XepakAppData {
     simple_auth_registry: SimpleAuthRegistry,
     storage_links: HashMap<String, Storage>,
+    sort_keys: bool,
+    allow_pretty: bool,
+    format_arg: Arc<str>,
+    auth_id_header: bool,
+    catch_panics: bool,
+    expose_db_errors: bool,
+    pagination_headers: PaginationHeaders,
+    arg_limits: ArgLimits,
+    body_limits: BodyLimits,
+    cors: Option<Arc<CorsConf>>,
+    trusted_proxies: Arc<Vec<IpCidr>>,
 }
 
 impl XepakAppData {
@@ -37,36 +90,136 @@ impl XepakAppData {
         self.storage_links.get(key)
     }
 
+    /// All storages sorted by id
+    pub fn get_data_sources(&self) -> Vec<(&String, &Storage)> {
+        let mut storages: Vec<_> = self.storage_links.iter().collect();
+        storages.sort_by_key(|(id, _)| *id);
+        storages
+    }
+
     pub fn get_auth_data(&self, api_key: &str) -> Option<&(String, HashSet<String>)> {
         self.simple_auth_registry.get(api_key)
     }
+
+    /// Response record keys must be serialized in sorted order
+    pub fn is_sort_keys(&self) -> bool {
+        self.sort_keys
+    }
+
+    /// Clients may request indented JSON
+    pub fn is_allow_pretty(&self) -> bool {
+        self.allow_pretty
+    }
+
+    /// Query parameter selecting the response format
+    pub fn get_format_arg(&self) -> &Arc<str> {
+        &self.format_arg
+    }
+
+    /// Responses of authenticated requests must have the auth id headers
+    pub fn is_auth_id_header(&self) -> bool {
+        self.auth_id_header
+    }
+
+    /// Database error text must be returned to clients
+    pub fn is_expose_db_errors(&self) -> bool {
+        self.expose_db_errors
+    }
+
+    pub fn get_pagination_headers(&self) -> &PaginationHeaders {
+        &self.pagination_headers
+    }
+
+    pub fn get_arg_limits(&self) -> ArgLimits {
+        self.arg_limits
+    }
+
+    pub fn get_body_limits(&self) -> BodyLimits {
+        self.body_limits
+    }
+
+    /// Global CORS policy (endpoints may override it)
+    pub fn get_cors(&self) -> Option<&Arc<CorsConf>> {
+        self.cors.as_ref()
+    }
+
+    /// Client IP resolved with respect to the trusted proxies, see [`client_ip::client_ip`].
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        client_ip::client_ip(req, &self.trusted_proxies)
+    }
+
+    /// Panics in handlers must be turned into 500 responses
+    pub fn is_catch_panics(&self) -> bool {
+        self.catch_panics
+    }
 }
 
-fn init_app_data() -> XepakAppData {
-    unimplemented!()
+/// Initialize storage connectors and auth registry shared by all endpoints.
+pub async fn init_app_data(
+    conf_dir: &Path,
+    config: &XepakConf,
+) -> Result<XepakAppData, XepakError> {
+    // Required to use with sqlx::Any connector
+    sqlx::any::install_default_drivers();
+
+    let storage_links = init_storage_connectors(conf_dir, config).await?;
+
+    let simple_auth_registry = auth_specs_to_registry(&config.simple_auth)?;
+
+    Ok(XepakAppData {
+        storage_links,
+        simple_auth_registry,
+        sort_keys: config.sort_keys,
+        allow_pretty: config.allow_pretty,
+        format_arg: config.format_arg.as_str().into(),
+        auth_id_header: config.auth_id_header,
+        catch_panics: config.catch_panics,
+        expose_db_errors: config.expose_db_errors,
+        pagination_headers: PaginationHeaders {
+            limit: config
+                .limit_header
+                .clone()
+                .unwrap_or_else(|| LIMIT_HEADER.to_string()),
+            offset: config
+                .offset_header
+                .clone()
+                .unwrap_or_else(|| OFFSET_HEADER.to_string()),
+        },
+        arg_limits: ArgLimits {
+            max_args: config.max_args,
+            max_arg_length: config.max_arg_length,
+        },
+        body_limits: BodyLimits {
+            max_size: config.max_body_size,
+            max_decompressed_size: config.max_decompressed_size,
+            max_decompression_ratio: config.max_decompression_ratio,
+        },
+        cors: config.cors.clone().map(Arc::new),
+        trusted_proxies: Arc::new(config.trusted_proxies.clone()),
+    })
 }
 
 pub async fn init_server(
     conf_dir: PathBuf,
     config: XepakConf,
     specs: XepakSpecs,
+) -> Result<Server, XepakError> {
+    let app_data = init_app_data(&conf_dir, &config).await?;
+    init_server_with_data(conf_dir, config, specs, app_data)
+}
+
+/// Same as [`init_server`] with app data initialized by the caller,
+/// so it could be passed to [`shutdown`] after the server stops.
+pub fn init_server_with_data(
+    conf_dir: PathBuf,
+    config: XepakConf,
+    mut specs: XepakSpecs,
+    app_data: XepakAppData,
 ) -> Result<Server, XepakError> {
     // if config.specs.deceit.is_empty() {
     //     log::warn!("Starting server without deceits in specs");
     // }
-    // let port = config.port;
-    let port = 8080;
-
-    // Required to use with sqlx::Any connector
-    sqlx::any::install_default_drivers();
-
-    let storage_links = init_storage_connectors(&conf_dir, &config.storage).await;
-
-    let simple_auth_registry = auth_specs_to_registry(&config.simple_auth)?;
-    let app_data = XepakAppData {
-        storage_links,
-        simple_auth_registry,
-    };
+    let port = config.port;
     // let data: Data<ApateState> = Data::new(config.into_state());
 
     // let mut app = App::new()
@@ -79,35 +232,186 @@ pub async fn init_server(
     // }
     // app.default_service(web::to(handlers::apate_server_handler));
 
+    // Unix socket is served as plain HTTP, TLS would be silently dropped
+    if config.unix_socket.is_some() && config.tls.is_some() {
+        return Err(XepakError::Cfg(
+            "Both unix_socket and tls are configured, TLS is not supported on unix socket"
+                .to_string(),
+        ));
+    }
+
+    check_endpoint_count(&config, &specs)?;
+    specs.apply_default_schema();
+
     let mut endpoints = Vec::new();
     for espec in specs.endpoint {
         endpoints.push(EndpointHandler::new(espec, &app_data)?);
     }
 
-    let server = HttpServer::new(move || {
+    let compression = config.compression.clone();
+    let timeout = config.request_timeout_ms.map(Duration::from_millis);
+    let uri_limits = UriLimits {
+        max_uri: config.max_uri_length,
+        max_query: config.max_query_length,
+    };
+
+    let version = config.version_endpoint.clone();
+    let health = config.health_endpoints.clone();
+    let not_found_message = config.not_found_message.clone();
+    let started = Instant::now();
+
+    let app_factory = move || {
         let ep_config = endpoints.clone();
-        let mut app = App::new()
+        let compression = compression.clone();
+        App::new()
             .app_data(Data::new(app_data.clone()))
             // .service(web::scope("/") ...
             .configure(|cfg: &mut ServiceConfig| {
+                if version.enabled {
+                    cfg.service(version_resource(&version, started));
+                }
+                if health.enabled {
+                    cfg.service(livez_resource(&health));
+                    cfg.service(readyz_resource(&health));
+                }
                 for eh in ep_config {
                     cfg.service(eh);
                 }
             })
-            .wrap(Logger::default());
+            .default_service(not_found_route(not_found_message.clone()))
+            .wrap(Condition::new(
+                timeout.is_some(),
+                from_fn(move |req, next| request_timeout(req, next, timeout.unwrap_or_default())),
+            ))
+            .wrap(Condition::new(!compression.is_empty(), Compress::default()))
+            .wrap_fn(move |mut req, srv| {
+                if !compression.is_empty() {
+                    filter_accept_encoding(&mut req, &compression);
+                }
+                srv.call(req)
+            })
+            .wrap(Condition::new(
+                uri_limits.is_enabled(),
+                from_fn(move |req, next| reject_long_uri(req, next, uri_limits)),
+            ))
+            .wrap(access_logger())
         // let endpoint = web::scope("some/endpoint").configure(cfg_fn)
         // web::sc
         // app.service()
-        app
-    })
-    .bind((Ipv4Addr::UNSPECIFIED, port))
-    .map_err(Arc::new)?
-    .keep_alive(actix_web::http::KeepAlive::Disabled)
-    .run();
+    };
+
+    if let Some(tls) = &config.tls {
+        let tls_config = server_config(&conf_dir, tls)?;
+        tracing::info!("Listening on HTTPS port {port} (HTTP/2 {})", tls.http2);
+        if tls.http2 {
+            let server = HttpServer::new(app_factory)
+                .keep_alive(KeepAlive::Disabled)
+                .bind_rustls((Ipv4Addr::UNSPECIFIED, port), tls_config)
+                .map_err(Arc::new)?;
+            return Ok(server.run());
+        }
+
+        // HttpServer adds h2 to ALPN protocols, so HTTP/1.1 only service is built directly
+        let server = Server::build()
+            .bind("xepak-https", (Ipv4Addr::UNSPECIFIED, port), move || {
+                HttpService::build()
+                    .keep_alive(KeepAlive::Disabled)
+                    .h1(map_config(app_factory(), |_| AppConfig::default()))
+                    .rustls(tls_config.clone())
+            })
+            .map_err(Arc::new)?;
+        return Ok(server.run());
+    }
+
+    let server = HttpServer::new(app_factory).keep_alive(KeepAlive::Disabled);
+
+    let server = match unix_socket_path(&conf_dir, &config) {
+        #[cfg(unix)]
+        Some(path) => {
+            remove_unix_socket(&path);
+            tracing::info!("Listening on unix socket {path:?}");
+            server.bind_uds(&path).map_err(Arc::new)?
+        }
+        #[cfg(not(unix))]
+        Some(path) => {
+            return Err(XepakError::Cfg(format!(
+                "Unix socket {path:?} is not supported on this platform"
+            )));
+        }
+        None => server
+            .bind((Ipv4Addr::UNSPECIFIED, port))
+            .map_err(Arc::new)?,
+    };
+
+    Ok(server.run())
+}
+
+/// Shutdown hooks, must run after the server stopped and in-flight requests are done.
+/// Storage pools are closed, so databases could flush their state (e.g. SQLite WAL checkpoint).
+pub async fn shutdown(app_data: &XepakAppData) {
+    tracing::info!("Server stopped, running shutdown hooks");
+    for (id, storage) in app_data.get_data_sources() {
+        storage.close().await;
+        tracing::debug!("Closed storage \"{id}\"");
+    }
+}
+
+/// Log number of endpoints and check it against `max_endpoints`,
+/// handlers are cloned into every worker, so memory grows with endpoints × workers.
+pub fn check_endpoint_count(config: &XepakConf, specs: &XepakSpecs) -> Result<(), XepakError> {
+    let count = specs.endpoint.len();
+    let uris: usize = specs.endpoint.iter().map(|ep| ep.uri.iter().count()).sum();
+    tracing::info!("Registering {count} endpoints ({uris} URIs)");
+
+    let Some(max) = config.max_endpoints else {
+        return Ok(());
+    };
+    if count <= max {
+        return Ok(());
+    }
 
-    Ok(server)
+    let message = format!(
+        "Number of endpoints {count} exceeds max_endpoints {max}, every worker keeps a copy of each"
+    );
+    if config.max_endpoints_strict {
+        return Err(XepakError::Cfg(message));
+    }
+    tracing::warn!("{message}");
+    Ok(())
+}
+
+/// Unix socket path from the config (relative path is resolved against the config dir).
+pub fn unix_socket_path(conf_dir: &Path, config: &XepakConf) -> Option<PathBuf> {
+    let path = config.unix_socket.as_ref()?;
+    if path.is_absolute() {
+        Some(path.clone())
+    } else {
+        Some(conf_dir.join(path))
+    }
+}
+
+/// Remove socket file left by the previous run (or after shutdown).
+pub fn remove_unix_socket(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(_) => tracing::debug!("Removed unix socket file {path:?}"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Can't remove unix socket file {path:?}: {e}"),
+    }
 }
 
+/// Request part the argument value is taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgSource {
+    Path,
+    Body,
+    Query,
+}
+
+/// Default argument precedence: path args override body args that override query args.
+pub const DEFAULT_ARG_PRECEDENCE: [ArgSource; 3] =
+    [ArgSource::Path, ArgSource::Body, ArgSource::Query];
+
 /// Contains aggregated/formatted input from request that will be used to querying resource.
 /// Input is updated/extended during processors execution.
 /// Also it could be updated from resource script before executing output query.
@@ -124,11 +428,43 @@ pub struct RequestInput {
     /// Final input args storage with schema applied
     pub(crate) args: Arc<HashMap<String, XepakValue>>,
 
+    /// Source of every value in `args`
+    arg_sources: Arc<HashMap<String, ArgSource>>,
+
+    /// Sources by precedence, value from the earlier one wins
+    arg_precedence: Arc<Vec<ArgSource>>,
+
     auth: Arc<Option<(XepakValue, HashSet<String>)>>,
 
+    /// How the request was authenticated
+    auth_method: Option<AuthMethod>,
+
     limit: usize,
 
     offset: usize,
+
+    /// Keyset pagination column and a page key value from the cursor (if any)
+    keyset: Option<(String, Option<XepakValue>)>,
+
+    /// Rows from the request body array (bulk requests)
+    batch: Arc<Vec<HashMap<String, XepakValue>>>,
+
+    /// Expected row version from `If-Match` header
+    if_match: Option<String>,
+
+    /// Concrete request path (empty for script inputs)
+    path: String,
+
+    /// Endpoint URI pattern matched by the request (empty for script inputs)
+    route: String,
+
+    /// HTTP method of the request (empty for script inputs)
+    method: String,
+
+    /// Request headers with lowercase names, multiple values are joined with `", "`
+    headers: Arc<HashMap<String, String>>,
+
+    arg_limits: ArgLimits,
 }
 
 impl RequestInput {
@@ -149,24 +485,102 @@ impl RequestInput {
             schema,
             strict_schema,
             auth: Arc::new(None),
+            auth_method: None,
             path_args: Arc::new(path_args),
             args: Arc::new(Default::default()),
+            arg_sources: Arc::new(Default::default()),
+            arg_precedence: Arc::new(DEFAULT_ARG_PRECEDENCE.to_vec()),
             limit: 0,
             offset: 0,
+            keyset: None,
+            batch: Arc::new(Vec::new()),
+            if_match: None,
+            path: req_path.to_string(),
+            route: uri_pattern.to_string(),
+            method: String::new(),
+            headers: Arc::new(Default::default()),
+            arg_limits: ArgLimits::default(),
+        }
+    }
+
+    /// Convert path args to their schema types (e.g. `int` for `/users/{id}`).
+    /// Path args are part of the route, so strict schema does not reject them.
+    pub fn convert_path_args(&mut self) -> Result<(), XepakError> {
+        if self.path_args.is_empty() || self.schema.is_empty() {
+            return Ok(());
+        }
+
+        let mut converted = HashMap::with_capacity(self.path_args.len());
+        for (name, value) in self.path_args.iter() {
+            let value = convert_with_schema(&self.schema, name, value.clone(), false)?;
+            converted.insert(name.clone(), value);
         }
+        self.path_args = Arc::new(converted);
+
+        Ok(())
     }
 
     /// Used when script is building input for nested queries
     pub fn new_in_script(args: HashMap<String, XepakValue>, limit: usize, offset: usize) -> Self {
         RequestInput {
             auth: Arc::new(None),
+            auth_method: None,
             schema: Schema::default(),
             strict_schema: false,
             path_args: Arc::new(Default::default()),
             args: Arc::new(args),
+            arg_sources: Arc::new(Default::default()),
+            arg_precedence: Arc::new(DEFAULT_ARG_PRECEDENCE.to_vec()),
             limit,
             offset,
+            keyset: None,
+            batch: Arc::new(Vec::new()),
+            if_match: None,
+            path: String::new(),
+            route: String::new(),
+            method: String::new(),
+            headers: Arc::new(Default::default()),
+            arg_limits: ArgLimits::default(),
+        }
+    }
+
+    /// Keep request method and headers (non UTF-8 header values are skipped).
+    pub fn set_http_request(&mut self, req: &HttpRequest) {
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for (name, value) in req.headers() {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            headers
+                .entry(name.as_str().to_string())
+                .and_modify(|v| {
+                    v.push_str(", ");
+                    v.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
         }
+
+        self.method = req.method().to_string();
+        self.headers = Arc::new(headers);
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_route(&self) -> &str {
+        &self.route
+    }
+
+    pub fn get_method(&self) -> &str {
+        &self.method
+    }
+
+    /// Header value by case-insensitive name
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
     }
 
     pub fn has_any_arg(&self, arg_name: &str) -> bool {
@@ -178,10 +592,42 @@ impl RequestInput {
 
     pub fn get_arg_value(&self, argument: &str) -> Option<&XepakValue> {
         let path_arg = self.path_args.get(argument);
-        if path_arg.is_none() {
-            self.args.get(argument)
-        } else {
-            path_arg
+        let arg = self.args.get(argument);
+        match (path_arg, arg) {
+            (Some(path_arg), Some(arg)) => {
+                let source = self.arg_sources.get(argument).copied();
+                if source
+                    .is_some_and(|s| self.precedence_of(s) < self.precedence_of(ArgSource::Path))
+                {
+                    Some(arg)
+                } else {
+                    Some(path_arg)
+                }
+            }
+            (Some(path_arg), None) => Some(path_arg),
+            (None, arg) => arg,
+        }
+    }
+
+    /// Limits checked for every argument set with [`RequestInput::set_arg_with_schema`].
+    pub fn set_arg_limits(&mut self, limits: ArgLimits) {
+        self.arg_limits = limits;
+    }
+
+    /// Set sources order for arguments present in more than one of them.
+    pub fn set_arg_precedence(&mut self, precedence: Arc<Vec<ArgSource>>) {
+        self.arg_precedence = precedence;
+    }
+
+    /// Position of the source in precedence list (lower wins), unlisted sources go last
+    /// in the default order.
+    fn precedence_of(&self, source: ArgSource) -> usize {
+        match self.arg_precedence.iter().position(|s| *s == source) {
+            Some(position) => position,
+            None => {
+                let default = DEFAULT_ARG_PRECEDENCE.iter().position(|s| *s == source);
+                self.arg_precedence.len() + default.unwrap_or_default()
+            }
         }
     }
 
@@ -204,6 +650,16 @@ impl RequestInput {
         }
     }
 
+    /// Enable keyset pagination and decode page key from the cursor argument if provided.
+    pub fn parse_keyset(&mut self, specs: &KeysetSpecs) -> Result<(), XepakError> {
+        let after = match self.get_arg_value(&specs.cursor_arg) {
+            Some(v) if !v.is_null() => Some(decode_cursor(&v.as_string())?),
+            _ => None,
+        };
+        self.keyset = Some((specs.column.clone(), after));
+        Ok(())
+    }
+
     fn parse_usize_from(&self, arg_name: &str) -> Option<usize> {
         let value = self.get_arg_value(arg_name)?;
 
@@ -224,21 +680,36 @@ impl RequestInput {
     }
 
     /// Set argument value and apply schema conversion to it if any defined.
-    /// Strict [`Schema`] rules will apply only if `enforce_schema = true`,
-    /// this is needed to avoid schema.
+    /// Strict [`Schema`] rules (unknown argument is an input error) will apply only if
+    /// `enforce_schema = true` and the endpoint has strict schema enabled.
+    /// Path arguments are captured on [`RequestInput::new`] and never checked this way.
+    /// Value already set from the source with higher precedence is kept.
     pub fn set_arg_with_schema(
         &mut self,
+        source: ArgSource,
         name: String,
         value: XepakValue,
         enforce_schema: bool,
     ) -> Result<(), XepakError> {
+        if let Some(current) = self.arg_sources.get(&name)
+            && self.precedence_of(*current) < self.precedence_of(source)
+        {
+            return Ok(());
+        }
+
         // TODO skip enforce_schema because we build new RequestInput for sript calls.
-        let Some(args) = Arc::get_mut(&mut self.args) else {
+        let (Some(args), Some(arg_sources)) = (
+            Arc::get_mut(&mut self.args),
+            Arc::get_mut(&mut self.arg_sources),
+        ) else {
             return Err(XepakError::Unexpected(
                 "Must acquire args &mut reference here by design".to_string(),
             ));
         };
 
+        self.arg_limits.check(args, &name, &value)?;
+        reject_output_arg(&self.schema, &name)?;
+
         let value = convert_with_schema(
             &self.schema,
             name.as_str(),
@@ -246,13 +717,28 @@ impl RequestInput {
             self.strict_schema && enforce_schema,
         )?;
 
+        arg_sources.insert(name.clone(), source);
         args.insert(name, value);
 
         Ok(())
     }
 
-    pub fn set_auth(&mut self, id: String, roles: HashSet<String>) {
-        self.auth = Arc::new(Some((XepakValue::Text(id), roles)))
+    pub fn set_auth(&mut self, id: String, roles: HashSet<String>, method: AuthMethod) {
+        self.auth = Arc::new(Some((XepakValue::Text(id), roles)));
+        self.auth_method = Some(method);
+    }
+
+    pub fn get_auth_method(&self) -> Option<AuthMethod> {
+        self.auth_method
+    }
+
+    /// Authenticated id and method without roles, safe to be logged.
+    pub fn get_auth_identity(&self) -> Option<AuthIdentity> {
+        let (id, _) = self.get_auth()?;
+        Some(AuthIdentity {
+            id: id.as_string(),
+            method: self.auth_method,
+        })
     }
 
     pub fn is_authenticated(&self) -> bool {
@@ -262,6 +748,65 @@ impl RequestInput {
     pub fn get_auth(&self) -> Option<&(XepakValue, HashSet<String>)> {
         self.auth.as_ref().as_ref()
     }
+
+    /// Set expected version from `If-Match` header value (quotes and weak prefix are dropped).
+    pub fn set_if_match(&mut self, header_value: &str) {
+        let value = header_value.trim();
+        let value = value.strip_prefix("W/").unwrap_or(value);
+        self.if_match = Some(value.trim_matches('"').to_string());
+    }
+
+    /// Convert and validate row from the request body with the schema and add it to the batch.
+    pub fn push_batch_row(
+        &mut self,
+        row: HashMap<String, XepakValue>,
+        enforce_schema: bool,
+    ) -> Result<(), XepakError> {
+        let mut converted = HashMap::with_capacity(row.len());
+        for (name, value) in row {
+            reject_output_arg(&self.schema, &name)?;
+            let value = convert_with_schema(
+                &self.schema,
+                &name,
+                value,
+                self.strict_schema && enforce_schema,
+            )?;
+            converted.insert(name, value);
+        }
+        validate_with_schema(&self.schema, &converted)?;
+
+        let Some(batch) = Arc::get_mut(&mut self.batch) else {
+            return Err(XepakError::Unexpected(
+                "Must acquire batch &mut reference here by design".to_string(),
+            ));
+        };
+        batch.push(converted);
+        Ok(())
+    }
+
+    pub fn get_batch(&self) -> &[HashMap<String, XepakValue>] {
+        &self.batch
+    }
+
+    /// Input with additional args (e.g. values exposed by composite parts), other args are kept.
+    pub fn with_extra_args(&self, extra: Vec<(String, XepakValue)>) -> RequestInput {
+        let mut args = self.args.as_ref().clone();
+        args.extend(extra);
+        RequestInput {
+            args: Arc::new(args),
+            ..self.clone()
+        }
+    }
+
+    /// Input for a single batch row, it keeps path args and auth of the request.
+    pub fn with_args(&self, args: HashMap<String, XepakValue>) -> RequestInput {
+        RequestInput {
+            args: Arc::new(args),
+            arg_sources: Arc::new(Default::default()),
+            batch: Arc::new(Vec::new()),
+            ..self.clone()
+        }
+    }
 }
 
 impl StorageRequestArgs for RequestInput {
@@ -272,6 +817,57 @@ impl StorageRequestArgs for RequestInput {
     fn get_rows_offset(&self) -> usize {
         self.get_offset()
     }
+
+    fn get_keyset(&self) -> Option<(&str, Option<&XepakValue>)> {
+        self.keyset
+            .as_ref()
+            .map(|(column, after)| (column.as_str(), after.as_ref()))
+    }
+
+    fn get_auth(&self) -> Option<(&XepakValue, &HashSet<String>)> {
+        RequestInput::get_auth(self).map(|(id, roles)| (id, roles))
+    }
+
+    fn get_if_match(&self) -> Option<&str> {
+        self.if_match.as_deref()
+    }
+
+    fn get_identifier(&self, arg_name: &str) -> Result<&str, XepakError> {
+        let Some(value) = self.get_arg_value(arg_name) else {
+            return Err(XepakError::Input(format!(
+                "Can't use identifier '{arg_name}' - does not exists in request."
+            )));
+        };
+
+        let value = value.as_string();
+        let allowed = self
+            .schema
+            .get(arg_name)
+            .map(|s| s.identifiers.as_slice())
+            .unwrap_or_default();
+
+        allowed
+            .iter()
+            .find(|v| **v == value)
+            .map(|v| v.as_str())
+            .ok_or_else(|| {
+                XepakError::Input(format!(
+                    "Value \"{value}\" is not allowed for argument \"{arg_name}\""
+                ))
+            })
+    }
+
+    fn is_quoted_identifier(&self, arg_name: &str) -> bool {
+        self.schema
+            .get(arg_name)
+            .is_some_and(|s| s.quote_identifier)
+    }
+
+    fn is_boolean_column(&self, column: &str) -> bool {
+        self.schema.get(column).is_some_and(|s| {
+            !matches!(s.scope, ArgSchemaScope::Input) && matches!(s.ty, XepakType::Boolean)
+        })
+    }
 }
 
 impl SqlxRequestArgs for RequestInput {
@@ -287,12 +883,219 @@ impl SqlxRequestArgs for RequestInput {
         };
 
         // TODO should bind with respect to the schema
-        Ok(value.bind_sqlx(query))
+        value.bind_sqlx(query)
+    }
+}
+
+/// Response metadata returned along with the data.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ResponseMeta {
+    pub limit: usize,
+    pub offset: usize,
+    /// Cursor to request the next page with keyset pagination
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl minicbor::Encode<()> for ResponseMeta {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        _ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        e.map(if self.next_cursor.is_some() { 3 } else { 2 })?;
+        e.str("limit")?.u64(self.limit as u64)?;
+        e.str("offset")?.u64(self.offset as u64)?;
+        if let Some(cursor) = &self.next_cursor {
+            e.str("next_cursor")?.str(cursor)?;
+        }
+        Ok(())
+    }
+}
+
+/// Response data wrapped together with its metadata: `{"data": ..., "meta": {...}}`.
+pub struct Envelope<'a, T> {
+    pub data: &'a T,
+    pub meta: &'a ResponseMeta,
+}
+
+impl<T: Serialize> Serialize for Envelope<'_, T> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = ser.serialize_map(Some(2))?;
+        map.serialize_entry("data", self.data)?;
+        map.serialize_entry("meta", self.meta)?;
+        map.end()
     }
 }
 
-pub fn to_error_object(err: XepakError) -> (StatusCode, HashMap<String, XepakValue>) {
-    let mut result = HashMap::<String, XepakValue>::with_capacity(2);
+/// Error object wrapped for envelope mode: `{"error": {...}}`.
+pub struct ErrorEnvelope<'a, T>(pub &'a T);
+
+impl<T: Serialize> Serialize for ErrorEnvelope<'_, T> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = ser.serialize_map(Some(1))?;
+        map.serialize_entry("error", self.0)?;
+        map.end()
+    }
+}
+
+impl<T: minicbor::Encode<()>> minicbor::Encode<()> for ErrorEnvelope<'_, T> {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        e.map(1)?;
+        e.str("error")?;
+        self.0.encode(e, ctx)
+    }
+}
+
+impl<T: minicbor::Encode<()>> minicbor::Encode<()> for Envelope<'_, T> {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        e.map(2)?;
+        e.str("data")?;
+        self.data.encode(e, ctx)?;
+        e.str("meta")?;
+        self.meta.encode(e, ctx)?;
+        Ok(())
+    }
+}
+
+/// Request arguments limits, nothing is limited by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArgLimits {
+    pub max_args: Option<usize>,
+    pub max_arg_length: Option<usize>,
+}
+
+impl ArgLimits {
+    /// Check the argument before it is added to `args`, replaced argument is not counted twice.
+    fn check(
+        &self,
+        args: &HashMap<String, XepakValue>,
+        name: &str,
+        value: &XepakValue,
+    ) -> Result<(), XepakError> {
+        if let Some(max) = self.max_args
+            && !args.contains_key(name)
+            && args.len() >= max
+        {
+            return Err(XepakError::Input(format!(
+                "Too many arguments, max allowed is {max}"
+            )));
+        }
+        if let (Some(max), XepakValue::Text(text)) = (self.max_arg_length, value)
+            && text.len() > max
+        {
+            return Err(XepakError::Input(format!(
+                "Argument {name} is too long, max allowed length is {max}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Identity the request was authenticated as, stored in response extensions for the access log.
+#[derive(Debug, Clone)]
+pub struct AuthIdentity {
+    pub id: String,
+    pub method: Option<AuthMethod>,
+}
+
+impl AuthIdentity {
+    /// Add [`AUTH_ID_HEADER`] and [`AUTH_METHOD_HEADER`] to the response.
+    pub fn append_headers(&self, resp: &mut HttpResponse) {
+        let headers = resp.headers_mut();
+        if let Ok(id) = HeaderValue::from_str(&self.id) {
+            headers.insert(HeaderName::from_static(AUTH_ID_HEADER), id);
+        }
+        if let Some(method) = self.method
+            && let Ok(method) = HeaderValue::from_str(&method.to_string())
+        {
+            headers.insert(HeaderName::from_static(AUTH_METHOD_HEADER), method);
+        }
+    }
+}
+
+impl std::fmt::Display for AuthIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.method {
+            Some(method) => write!(f, "{} ({method})", self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+/// Default actix access log format with the authenticated identity appended.
+pub fn access_logger() -> Logger {
+    Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T auth=%{auth}xo"#)
+        .custom_response_replace("auth", |res| {
+            res.response()
+                .extensions()
+                .get::<AuthIdentity>()
+                .map(AuthIdentity::to_string)
+                .unwrap_or_else(|| "-".to_string())
+        })
+}
+
+/// Response header names for pagination details.
+#[derive(Debug, Clone)]
+pub struct PaginationHeaders {
+    pub limit: String,
+    pub offset: String,
+}
+
+impl Default for PaginationHeaders {
+    fn default() -> Self {
+        Self {
+            limit: LIMIT_HEADER.to_string(),
+            offset: OFFSET_HEADER.to_string(),
+        }
+    }
+}
+
+impl ResponseMeta {
+    pub fn append_headers(&self, resp: &mut HttpResponseBuilder, names: &PaginationHeaders) {
+        if self.limit > 0 {
+            resp.append_header((names.limit.as_str(), self.limit.to_string()));
+        }
+        if self.offset > 0 {
+            resp.append_header((names.offset.as_str(), self.offset.to_string()));
+        }
+        if let Some(cursor) = &self.next_cursor {
+            resp.append_header((NEXT_CURSOR_HEADER, cursor.as_str()));
+        }
+    }
+}
+
+/// Error object for the client, DB error text replaces the generic message only when
+/// `expose_db_errors` is enabled.
+pub fn client_error_object(err: XepakError, expose_db_errors: bool) -> (StatusCode, Record) {
+    let db_error = match &err {
+        XepakError::Storage(e) if expose_db_errors => Some(e.to_string()),
+        _ => None,
+    };
+
+    let (status_code, mut data) = to_error_object(err);
+    if let Some(message) = db_error {
+        data.insert("message".to_string(), message.into());
+    }
+    (status_code, data)
+}
+
+pub fn to_error_object(err: XepakError) -> (StatusCode, Record) {
+    let mut result = Record::with_capacity(2);
     let mut code = StatusCode::from_u16(520).expect("Must not fail (^_^)");
     match err {
         XepakError::NotFound(msg) => {
@@ -305,6 +1108,11 @@ pub fn to_error_object(err: XepakError) -> (StatusCode, HashMap<String, XepakVal
             result.insert("code".to_string(), "bad_request".into());
             result.insert("message".to_string(), msg.into());
         }
+        err @ XepakError::InvalidInput(_) => {
+            code = StatusCode::BAD_REQUEST;
+            result.insert("code".to_string(), "bad_request".into());
+            result.insert("message".to_string(), err.to_string().into());
+        }
         XepakError::Decode(msg) | XepakError::WeScrewed(msg) => {
             code = StatusCode::INTERNAL_SERVER_ERROR;
             result.insert("code".to_string(), "internal_error".into());
@@ -315,9 +1123,277 @@ pub fn to_error_object(err: XepakError) -> (StatusCode, HashMap<String, XepakVal
             result.insert("code".to_string(), "forbidden".into());
             result.insert("message".to_string(), msg.into());
         }
+        XepakError::Unavailable(msg) => {
+            code = StatusCode::SERVICE_UNAVAILABLE;
+            result.insert("code".to_string(), "unavailable".into());
+            result.insert("message".to_string(), msg.into());
+        }
+        XepakError::UriTooLong(msg) => {
+            code = StatusCode::URI_TOO_LONG;
+            result.insert("code".to_string(), "uri_too_long".into());
+            result.insert("message".to_string(), msg.into());
+        }
+        XepakError::Timeout(msg) => {
+            code = StatusCode::GATEWAY_TIMEOUT;
+            result.insert("code".to_string(), "timeout".into());
+            result.insert("message".to_string(), msg.into());
+        }
+        XepakError::PreconditionFailed(msg) => {
+            code = StatusCode::PRECONDITION_FAILED;
+            result.insert("code".to_string(), "precondition_failed".into());
+            result.insert("message".to_string(), msg.into());
+        }
+        XepakError::Conflict(msg) => {
+            code = StatusCode::CONFLICT;
+            result.insert("code".to_string(), "conflict".into());
+            result.insert("message".to_string(), msg.into());
+        }
+        XepakError::PayloadTooLarge(msg) => {
+            code = StatusCode::PAYLOAD_TOO_LARGE;
+            result.insert("code".to_string(), "payload_too_large".into());
+            result.insert("message".to_string(), msg.into());
+        }
+        XepakError::RangeNotSatisfiable(msg) => {
+            code = StatusCode::RANGE_NOT_SATISFIABLE;
+            result.insert("code".to_string(), "range_not_satisfiable".into());
+            result.insert("message".to_string(), msg.into());
+        }
+        XepakError::UnsupportedMediaType(msg) => {
+            code = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            result.insert("code".to_string(), "unsupported_media_type".into());
+            result.insert("message".to_string(), msg.into());
+        }
         _ => {
             result.insert("code".to_string(), "unknown_error".into());
         }
     }
     (code, result)
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{TestRequest, call_and_read_body_json, call_service, read_body_json};
+
+    use super::*;
+    use crate::testing::{LogCapture, TestEnv, test_service};
+
+    #[test]
+    fn endpoint_count_logged_and_checked() {
+        let specs: XepakSpecs = toml::from_str(
+            r#"
+            [[endpoint]]
+            uri = ["/a", "/b"]
+            resource = { type = "static", body = 1 }
+
+            [[endpoint]]
+            uri = "/c"
+            resource = { type = "static", body = 2 }
+            "#,
+        )
+        .unwrap();
+
+        let logs = LogCapture::start();
+        let config: XepakConf = toml::from_str("max_endpoints = 1").unwrap();
+        check_endpoint_count(&config, &specs).unwrap();
+        assert_eq!(
+            logs.messages(tracing::Level::INFO),
+            vec!["Registering 2 endpoints (3 URIs)"]
+        );
+        assert_eq!(logs.messages(tracing::Level::WARN).len(), 1);
+
+        let config: XepakConf =
+            toml::from_str("max_endpoints = 1\nmax_endpoints_strict = true").unwrap();
+        assert!(matches!(
+            check_endpoint_count(&config, &specs),
+            Err(XepakError::Cfg(_))
+        ));
+
+        let config: XepakConf = toml::from_str("max_endpoints = 2").unwrap();
+        check_endpoint_count(&config, &specs).unwrap();
+        assert_eq!(logs.messages(tracing::Level::WARN).len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn serves_over_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        sqlx::any::install_default_drivers();
+        let socket = std::env::temp_dir().join(format!("xepak-{}-uds.sock", std::process::id()));
+        let config: XepakConf =
+            toml::from_str(&format!("unix_socket = {:?}", socket.to_string_lossy())).unwrap();
+        let specs: XepakSpecs = toml::from_str(
+            r#"
+            [[endpoint]]
+            uri = "/ping"
+
+            [endpoint.resource]
+            type = "static"
+            body = { pong = true }
+            "#,
+        )
+        .unwrap();
+
+        let server = init_server(PathBuf::from("."), config, specs)
+            .await
+            .unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let mut stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("{\"pong\":true}"), "{response}");
+
+        handle.stop(true).await;
+        remove_unix_socket(&socket);
+        assert!(!socket.exists());
+
+        let config: XepakConf = toml::from_str(&format!(
+            "unix_socket = {:?}\ntls = {{ cert_file = \"cert.pem\", key_file = \"key.pem\" }}",
+            socket.to_string_lossy()
+        ))
+        .unwrap();
+        let result = init_server(PathBuf::from("."), config, XepakSpecs::default()).await;
+        assert!(matches!(result, Err(XepakError::Cfg(_))));
+    }
+
+    #[actix_web::test]
+    async fn shutdown_closes_storages() {
+        let app_data = crate::testing::sqlite_app_data("shutdown", &[""], "").await;
+        let specs: XepakSpecs = toml::from_str(
+            r#"
+            [[endpoint]]
+            uri = "/one"
+
+            [endpoint.resource]
+            type = "query"
+            query = "SELECT 1 AS one"
+            "#,
+        )
+        .unwrap();
+        let config: XepakConf = toml::from_str("port = 0").unwrap();
+
+        let server =
+            init_server_with_data(PathBuf::from("."), config, specs, app_data.clone()).unwrap();
+        let handle = server.handle();
+        let running = actix_web::rt::spawn(server);
+
+        handle.stop(true).await;
+        running.await.unwrap().unwrap();
+        shutdown(&app_data).await;
+
+        let storage = app_data.get_data_source("").unwrap();
+        assert!(storage.is_closed());
+        assert!(crate::testing::exec(storage, "SELECT 1").await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn path_args_converted_with_schema() {
+        let app = test_service(
+            "path-args-schema",
+            "",
+            &[r#"
+            uri = "/users/{id}/{slug}"
+            strict_schema = true
+
+            [schema.id]
+            type = "int"
+
+            [resource]
+            type = "query"
+            query = "SELECT typeof({{id}}) AS t, {{slug}} AS slug"
+            "#],
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/users/42/neo").to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"t": "integer", "slug": "neo"}]));
+
+        let req = TestRequest::get().uri("/users/abc/neo").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn arg_precedence_configured() {
+        let app = test_service(
+            "arg-precedence",
+            "",
+            &[r#"
+            uri = "/items/{id}/{tag}"
+            arg_precedence = ["query", "path", "body"]
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{id}} AS id, {{tag}} AS tag, {{name}} AS name"
+            "#],
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/items/1/path?id=2")
+            .set_json(serde_json::json!({"id": 3, "tag": "body", "name": "body"}))
+            .to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"id": "2", "tag": "path", "name": "body"}])
+        );
+    }
+
+    #[actix_web::test]
+    async fn request_args_limited() {
+        let app = TestEnv::new("args-limits", "max_args = 2\nmax_arg_length = 5", "")
+            .await
+            .service(&[r#"
+            uri = "/echo"
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{a}} AS a"
+            "#])
+            .await;
+
+        let rejected = |req| async {
+            let resp = call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = read_body_json(resp).await;
+            body["message"].as_str().unwrap().to_string()
+        };
+
+        let req = TestRequest::get().uri("/echo?a=12345&b=2").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = TestRequest::get().uri("/echo?a=1&b=2&c=3").to_request();
+        let message = rejected(req).await;
+        assert!(message.contains("max allowed is 2"), "{message}");
+
+        let req = TestRequest::get().uri("/echo?a=123456").to_request();
+        let message = rejected(req).await;
+        assert!(message.contains("max allowed length is 5"), "{message}");
+
+        let req = TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({"a": 1, "b": 2, "c": 3}))
+            .to_request();
+        let message = rejected(req).await;
+        assert!(message.contains("max allowed is 2"), "{message}");
+
+        let req = TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({"a": "too long"}))
+            .to_request();
+        let message = rejected(req).await;
+        assert!(message.contains("max allowed length is 5"), "{message}");
+    }
+}
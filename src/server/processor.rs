@@ -1,17 +1,18 @@
-use std::{collections::HashMap, default};
+use std::collections::{HashMap, HashSet};
 
 use actix_web::{
     HttpRequest,
     http::{Method, header::CONTENT_TYPE},
     web::{Bytes, Data},
 };
+use minicbor::data::Type;
 use serde::Deserialize;
 
 use crate::{
     XepakError,
-    auth::CheckAuthConf,
-    schema::validate_with_schema,
-    server::{CONTENT_TYPE_CBOR, RequestInput, XepakAppData},
+    auth::{AuthMethod, CheckAuthConf},
+    schema::{ArgError, ArgSchemaScope, collect_schema_errors, validate_with_schema},
+    server::{ArgSource, CONTENT_TYPE_CBOR, CONTENT_TYPE_JSON, RequestInput, XepakAppData},
     types::XepakValue,
 };
 
@@ -21,11 +22,21 @@ pub const PRIORITY_NORMAL: u16 = 30_000;
 
 pub const PRIORITY_LAST: u16 = 1000;
 
+/// Authentication must happen before anything else touches request data
+pub const PRIORITY_AUTHENTICATE: u16 = PRIORITY_FIRST + 2000;
+
+/// Authorization relies on authentication result
+pub const PRIORITY_AUTHORIZE: u16 = PRIORITY_FIRST + 1000;
+
 /// Define request processors variants.
 #[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum PreProcessor {
-    ParseBodyArgs,
+    ParseBodyArgs {
+        /// Respond with 415 to writes without JSON or CBOR `Content-Type`
+        #[serde(default = "default_require_content_type")]
+        require_content_type: bool,
+    },
     SimpleAuth {
         #[serde(default)]
         allow_no_auth: bool,
@@ -33,10 +44,23 @@ pub enum PreProcessor {
     Authorize {
         allow: Vec<CheckAuthConf>,
     },
+    /// Try auth methods in order, the first one that authenticates wins
+    AnyAuth {
+        methods: Vec<AuthMethod>,
+        #[serde(default)]
+        allow_no_auth: bool,
+    },
 }
 
 pub trait PreProcessorHandler {
-    /// Handler with higher priority will be processed first
+    /// Handler with higher priority will be processed first.
+    ///
+    /// Built-in processors are ordered as follows:
+    /// 1. authentication ([`PRIORITY_AUTHENTICATE`]) and authorization ([`PRIORITY_AUTHORIZE`]),
+    ///    so unauthorized requests are rejected before any input is parsed;
+    /// 2. body parsing ([`PRIORITY_FIRST`]);
+    /// 3. query string args ([`PRIORITY_NORMAL`]);
+    /// 4. input validation ([`PRIORITY_LAST`]) when all args are collected.
     fn priority(&self) -> u16 {
         PRIORITY_NORMAL
     }
@@ -51,7 +75,18 @@ pub trait PreProcessorHandler {
 }
 
 /// Execute validation logic for all input arguments according to schema.
-pub struct InputArgsValidator {}
+/// Runs last, when arguments from all sources are collected.
+pub struct InputArgsValidator {
+    /// Report all invalid arguments at once instead of failing on the first one
+    collect_errors: bool,
+}
+
+impl InputArgsValidator {
+    pub fn new_boxed(collect_errors: bool) -> Box<Self> {
+        Box::new(Self { collect_errors })
+    }
+}
+
 impl PreProcessorHandler for InputArgsValidator {
     fn priority(&self) -> u16 {
         PRIORITY_LAST
@@ -64,19 +99,88 @@ impl PreProcessorHandler for InputArgsValidator {
         _body: &Bytes,
         input: &mut RequestInput,
     ) -> Result<(), XepakError> {
+        let mut required: Vec<&String> = input
+            .schema
+            .iter()
+            .filter(|(_, s)| s.required && !matches!(s.scope, ArgSchemaScope::Output))
+            .map(|(name, _)| name)
+            .collect();
+        required.sort();
+
+        if self.collect_errors {
+            let mut errors: Vec<ArgError> = required
+                .into_iter()
+                .filter(|name| {
+                    !input.path_args.contains_key(*name) && !input.args.contains_key(*name)
+                })
+                .map(|name| ArgError {
+                    field: name.clone(),
+                    message: format!("Argument \"{name}\" is required"),
+                })
+                .collect();
+            collect_schema_errors(&input.schema, &input.path_args, &mut errors);
+            collect_schema_errors(&input.schema, &input.args, &mut errors);
+
+            return if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(XepakError::InvalidInput(errors))
+            };
+        }
+
+        for name in required {
+            if !input.path_args.contains_key(name) && !input.args.contains_key(name) {
+                return Err(XepakError::Input(format!(
+                    "Argument \"{name}\" is required"
+                )));
+            }
+        }
+
         validate_with_schema(&input.schema, &input.path_args)?;
         validate_with_schema(&input.schema, &input.args)?;
         Ok(())
     }
 }
 
+/// Request methods that pass arguments in the body
+fn has_body(method: &Method) -> bool {
+    method == Method::POST || method == Method::PUT || method == Method::PATCH
+}
+
 /// Handle arguments from query string arguments.
-/// Skip query string args POST/PUT requests (basically anything that have request body)
-pub struct QueryArgsProcessor {}
+/// Skip query string args POST/PUT/PATCH requests (basically anything that have request body)
+/// unless `with_body` is set.
+/// Parameters not listed in `known` (if set) are rejected even when query args are skipped.
+pub struct QueryArgsProcessor {
+    with_body: bool,
+    known: Option<HashSet<String>>,
+}
+
+impl QueryArgsProcessor {
+    pub fn new_boxed(with_body: bool, known: Option<HashSet<String>>) -> Box<Self> {
+        Box::new(Self { with_body, known })
+    }
+
+    fn check_known(&self, qstring: &str) -> Result<(), XepakError> {
+        let Some(known) = &self.known else {
+            return Ok(());
+        };
+        let params = serde_urlencoded::from_str::<Vec<(String, String)>>(qstring)
+            .map_err(|e| XepakError::Input(format!("Can't decode query string: {e}")))?;
+        for (name, _) in params {
+            if !known.contains(&name) {
+                return Err(XepakError::Input(format!(
+                    "Unknown query parameter \"{name}\""
+                )));
+            }
+        }
+        Ok(())
+    }
+}
 
 impl PreProcessorHandler for QueryArgsProcessor {
     fn priority(&self) -> u16 {
-        PRIORITY_FIRST + 1
+        PRIORITY_NORMAL
     }
 
     fn handle(
@@ -86,10 +190,12 @@ impl PreProcessorHandler for QueryArgsProcessor {
         _body: &Bytes,
         input: &mut RequestInput,
     ) -> Result<(), XepakError> {
-        if req.method() == Method::PUT || req.method() == Method::POST {
+        let qstring = req.uri().query().unwrap_or_default();
+        self.check_known(qstring)?;
+
+        if !self.with_body && has_body(req.method()) {
             return Ok(());
         }
-        let qstring = req.uri().query().unwrap_or_default();
         let query_args =
             if let Ok(qa) = serde_urlencoded::from_str::<HashMap<String, XepakValue>>(qstring) {
                 qa
@@ -99,17 +205,58 @@ impl PreProcessorHandler for QueryArgsProcessor {
             };
 
         for (k, v) in query_args {
-            input.set_arg_with_schema(k, v, true)?;
+            input.set_arg_with_schema(ArgSource::Query, k, v, true)?;
         }
 
         Ok(())
     }
 }
-pub struct BodyToArgsProcessor {}
+fn default_require_content_type() -> bool {
+    true
+}
+
+pub struct BodyToArgsProcessor {
+    /// Accept array of objects as a batch of rows
+    accept_array: bool,
+    /// Body without content type is rejected instead of parsed as JSON
+    require_content_type: bool,
+}
 
 impl BodyToArgsProcessor {
-    pub fn new_boxed() -> Box<Self> {
-        Box::new(Self {})
+    pub fn new_boxed(accept_array: bool, require_content_type: bool) -> Box<Self> {
+        Box::new(Self {
+            accept_array,
+            require_content_type,
+        })
+    }
+
+    /// Whether body is CBOR, content type parameters (e.g. `charset`) are ignored.
+    fn is_cbor_body(&self, req: &HttpRequest) -> Result<bool, XepakError> {
+        let Some(value) = req.headers().get(CONTENT_TYPE) else {
+            if self.require_content_type {
+                return Err(XepakError::UnsupportedMediaType(format!(
+                    "Content-Type is required, expected {CONTENT_TYPE_JSON} or {CONTENT_TYPE_CBOR}"
+                )));
+            }
+            return Ok(false);
+        };
+
+        let essence = value
+            .to_str()
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            CONTENT_TYPE_CBOR => Ok(true),
+            CONTENT_TYPE_JSON => Ok(false),
+            _ if self.require_content_type => Err(XepakError::UnsupportedMediaType(format!(
+                "Unsupported Content-Type \"{essence}\", expected {CONTENT_TYPE_JSON} or {CONTENT_TYPE_CBOR}"
+            ))),
+            _ => Ok(false),
+        }
     }
 }
 
@@ -119,7 +266,33 @@ impl BodyToArgsProcessor {
         body: &Bytes,
         input: &mut RequestInput,
     ) -> Result<(), XepakError> {
-        todo!("Implement CBOR parsing")
+        let mut d = minicbor::Decoder::new(body);
+        let datatype = d.datatype().map_err(wrong_cbor)?;
+
+        if self.accept_array && matches!(datatype, Type::Array | Type::ArrayIndef) {
+            let rows: Vec<HashMap<String, XepakValue>> = d.decode().map_err(|_| {
+                XepakError::Input(
+                    "CBOR request body array must contain only flat maps with text keys"
+                        .to_string(),
+                )
+            })?;
+            for row in rows {
+                input.push_batch_row(row, true)?;
+            }
+            return Ok(());
+        }
+
+        if !matches!(datatype, Type::Map | Type::MapIndef) {
+            return Err(XepakError::Input(
+                "CBOR request body only allowed to be a map".to_string(),
+            ));
+        }
+
+        let object: HashMap<String, XepakValue> = d.decode().map_err(wrong_cbor)?;
+        for (key, xvalue) in object {
+            input.set_arg_with_schema(ArgSource::Body, key, xvalue, true)?;
+        }
+        Ok(())
     }
 
     pub fn handle_json_body(
@@ -130,28 +303,57 @@ impl BodyToArgsProcessor {
         let json_request: serde_json::Value = serde_json::from_slice(body)
             .map_err(|e| XepakError::Input(format!("Wrong JSON format: {e}")))?;
 
+        if self.accept_array
+            && let Some(json_array) = json_request.as_array()
+        {
+            for item in json_array {
+                let Some(json_object) = item.as_object() else {
+                    return Err(XepakError::Input(
+                        "JSON request body array must contain only objects".to_string(),
+                    ));
+                };
+                input.push_batch_row(flat_json_object(json_object)?, true)?;
+            }
+            return Ok(());
+        }
+
         let Some(json_object) = json_request.as_object() else {
             return Err(XepakError::Input(
                 "JSON request body only allowed to be an object".to_string(),
             ));
         };
 
-        for (key, value) in json_object {
-            let xvalue = if value.is_array() || value.is_object() {
-                return Err(XepakError::Input(format!(
-                    "(๑•ᗝ•)૭ Root JSON must NOT have any nested arrays or objects. See \"{key}\" property."
-                )));
-            } else {
-                value.try_into()?
-            };
-
-            input.set_arg_with_schema(key.clone(), xvalue, true)?;
+        for (key, xvalue) in flat_json_object(json_object)? {
+            input.set_arg_with_schema(ArgSource::Body, key, xvalue, true)?;
         }
         Ok(())
     }
 }
 
+fn wrong_cbor(e: minicbor::decode::Error) -> XepakError {
+    XepakError::Input(format!("Wrong CBOR format: {e}"))
+}
+
+fn flat_json_object(
+    json_object: &serde_json::Map<String, serde_json::Value>,
+) -> Result<HashMap<String, XepakValue>, XepakError> {
+    let mut result = HashMap::with_capacity(json_object.len());
+    for (key, value) in json_object {
+        if value.is_array() || value.is_object() {
+            return Err(XepakError::Input(format!(
+                "Root JSON must not have any nested arrays or objects, see \"{key}\" property"
+            )));
+        }
+        result.insert(key.clone(), value.try_into()?);
+    }
+    Ok(result)
+}
+
 impl PreProcessorHandler for BodyToArgsProcessor {
+    fn priority(&self) -> u16 {
+        PRIORITY_FIRST
+    }
+
     fn handle(
         &self,
         req: &HttpRequest,
@@ -159,22 +361,374 @@ impl PreProcessorHandler for BodyToArgsProcessor {
         body: &Bytes,
         input: &mut RequestInput,
     ) -> Result<(), XepakError> {
-        if req.method() != Method::POST && req.method() != Method::PUT {
+        if !has_body(req.method()) {
             return Ok(());
         }
 
-        let cbor_body = if let Some(accept) = req.headers().get(CONTENT_TYPE)
-            && accept.eq(CONTENT_TYPE_CBOR)
-        {
-            true
-        } else {
-            false
-        };
-
-        if cbor_body {
+        if self.is_cbor_body(req)? {
             self.handle_cbor_body(body, input)
         } else {
             self.handle_json_body(body, input)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+
+    use super::*;
+    use crate::testing::{TestEnv, test_service};
+
+    #[actix_web::test]
+    async fn auth_runs_before_body_parsing() {
+        let app = TestEnv::new(
+            "auth_runs_before_body_parsing",
+            r#"
+            [[simple_auth]]
+            id = "admin"
+            key = "secret"
+            "#,
+            "",
+        )
+        .await
+        .service(&[r#"
+            uri = "/echo"
+            processor = [{ type = "parse_body_args" }, { type = "simple_auth" }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{name}} AS name"
+            "#])
+        .await;
+
+        // Malformed body must not be parsed for unauthenticated request
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload("{ not json")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .insert_header(("x-api-key", "secret"))
+            .set_payload("{ not json")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .insert_header(("x-api-key", "secret"))
+            .set_payload(r#"{"name": "xepak"}"#)
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"name": "xepak"}]));
+    }
+
+    #[actix_web::test]
+    async fn schema_validators_reject_input() {
+        let app = test_service(
+            "schema_validators_reject_input",
+            "",
+            &[r#"
+            uri = "/check"
+            processor = [{ type = "parse_body_args" }]
+
+            [schema.id]
+            type = "int"
+            required = true
+            validate = [{ kind = "not_null" }, { kind = "range", from = 1, to = 100 }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{id}} AS id"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/check?id=5").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"id": 5}]));
+
+        for uri in ["/check?id=500", "/check"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/check")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"{"id": null}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Error messages reaching clients are plain ASCII
+        let req = test::TestRequest::post()
+            .uri("/check")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"{"id": {"nested": 1}}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = test::read_body(resp).await;
+        assert!(body.is_ascii(), "{body:?}");
+        assert!(String::from_utf8_lossy(&body).contains("nested arrays or objects"));
+    }
+
+    #[actix_web::test]
+    async fn all_validation_errors_collected() {
+        let app = test_service(
+            "validation-errors-collected",
+            "",
+            &[r#"
+            uri = "/check"
+            collect_validation_errors = true
+
+            [schema.a]
+            type = "int"
+            validate = [{ kind = "range", from = 1, to = 10 }]
+
+            [schema.b]
+            type = "text"
+            validate = [{ kind = "range", from = 1, to = 3 }]
+
+            [schema.c]
+            type = "int"
+            required = true
+
+            [resource]
+            type = "query"
+            query = "SELECT {{c}} AS c"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/check?a=500&b=toolong")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "bad_request");
+        let fields: Vec<&str> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["c", "a", "b"]);
+
+        let req = test::TestRequest::get().uri("/check?c=1&a=5").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"c": 1}]));
+    }
+
+    #[actix_web::test]
+    async fn strict_schema_rejects_unknown_args() {
+        let app = test_service(
+            "strict_schema_rejects_unknown_args",
+            "",
+            &[r#"
+            uri = "/users/{id}"
+            strict_schema = true
+            processor = [{ type = "parse_body_args" }]
+            schema = { name = { type = "text" } }
+
+            [resource]
+            type = "query"
+            query = "SELECT {{id}} AS id, {{name}} AS name"
+            "#],
+        )
+        .await;
+
+        // Path arg "id" is not in the schema, but it is allowed
+        let req = test::TestRequest::get()
+            .uri("/users/1?name=neo")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"id": "1", "name": "neo"}]));
+
+        let req = test::TestRequest::get()
+            .uri("/users/1?name=neo&role=admin")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("\"role\""));
+
+        let req = test::TestRequest::post()
+            .uri("/users/1")
+            .insert_header((CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload(r#"{"name": "neo", "password": "x"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("\"password\""));
+    }
+
+    #[actix_web::test]
+    async fn unknown_query_param_rejected() {
+        let app = test_service(
+            "unknown-query-param",
+            "",
+            &[r#"
+            uri = "/pages"
+            args = ["page"]
+            reject_unknown_query = true
+
+            [resource]
+            type = "query"
+            query = "SELECT {{page}} AS page"
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/pages?page=2&limit=5")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/pages?pge=2").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("\"pge\""));
+    }
+
+    #[actix_web::test]
+    async fn write_without_content_type_rejected() {
+        let app = test_service(
+            "write-content-type",
+            "",
+            &[
+                r#"
+                uri = "/strict"
+                processor = [{ type = "parse_body_args" }]
+
+                [resource]
+                type = "query"
+                query = "SELECT {{name}} AS name"
+                "#,
+                r#"
+                uri = "/lenient"
+                processor = [{ type = "parse_body_args", require_content_type = false }]
+
+                [resource]
+                type = "query"
+                query = "SELECT {{name}} AS name"
+                "#,
+            ],
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/strict")
+            .set_payload(r#"{"name": "xepak"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "unsupported_media_type");
+
+        let req = test::TestRequest::post()
+            .uri("/strict")
+            .insert_header((CONTENT_TYPE, "text/plain"))
+            .set_payload(r#"{"name": "xepak"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let req = test::TestRequest::post()
+            .uri("/strict")
+            .insert_header((CONTENT_TYPE, "application/json; charset=utf-8"))
+            .set_payload(r#"{"name": "xepak"}"#)
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"name": "xepak"}]));
+
+        let req = test::TestRequest::get()
+            .uri("/strict?name=neo")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"name": "neo"}]));
+
+        let req = test::TestRequest::post()
+            .uri("/lenient")
+            .set_payload(r#"{"name": "xepak"}"#)
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"name": "xepak"}]));
+    }
+
+    #[actix_web::test]
+    async fn cbor_body_parsed_into_args() {
+        let app = test_service(
+            "cbor-body",
+            "",
+            &[r#"
+            uri = "/echo"
+            processor = [{ type = "parse_body_args" }]
+
+            [resource]
+            type = "query"
+            query = "SELECT {{name}} AS name, {{n}} AS n, {{ratio}} AS ratio"
+            "#],
+        )
+        .await;
+
+        let post = |body: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/echo")
+                .insert_header((CONTENT_TYPE, "application/cbor; charset=binary"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let mut body = Vec::new();
+        minicbor::Encoder::new(&mut body)
+            .map(3)
+            .unwrap()
+            .str("name")
+            .unwrap()
+            .str("xepak")
+            .unwrap()
+            .str("n")
+            .unwrap()
+            .i64(-7)
+            .unwrap()
+            .str("ratio")
+            .unwrap()
+            .f64(0.5)
+            .unwrap();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, post(body)).await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"name": "xepak", "n": -7, "ratio": 0.5}])
+        );
+
+        // Nested values are rejected like in JSON bodies
+        let mut nested = Vec::new();
+        minicbor::Encoder::new(&mut nested)
+            .map(1)
+            .unwrap()
+            .str("name")
+            .unwrap()
+            .array(0)
+            .unwrap();
+        let resp = test::call_service(&app, post(nested)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(&app, post(b"\xff garbage".to_vec())).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
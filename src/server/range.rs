@@ -0,0 +1,131 @@
+use std::ops::Range;
+
+use actix_web::{
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+    http::{
+        Method, StatusCode,
+        header::{ACCEPT_RANGES, CONTENT_RANGE, RANGE},
+    },
+};
+
+use crate::{XepakError, server::to_error_object};
+
+/// Byte range of the `len` bytes body requested by the `Range` header value
+/// (`bytes=0-99`, `bytes=100-` or `bytes=-100`). Multiple ranges are not supported.
+/// Returns `None` if the range is malformed or not satisfiable.
+pub fn parse_range(value: &str, len: usize) -> Option<Range<usize>> {
+    let spec = value.trim().strip_prefix("bytes=")?.trim();
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // Suffix range is the last `n` bytes
+        let suffix: usize = end.parse().ok()?;
+        len.saturating_sub(suffix)..len
+    } else {
+        let start: usize = start.parse().ok()?;
+        let end = if end.is_empty() {
+            len
+        } else {
+            let end: usize = end.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            end.saturating_add(1).min(len)
+        };
+        start..end
+    };
+
+    (range.start < range.end).then_some(range)
+}
+
+/// Body of the `GET` request with `Range` header is sliced with 206 Partial Content,
+/// invalid range is 416. Other requests get the whole body.
+pub fn ranged_body(req: &HttpRequest, mut resp: HttpResponseBuilder, body: String) -> HttpResponse {
+    resp.insert_header((ACCEPT_RANGES, "bytes"));
+    let range = match req.headers().get(RANGE) {
+        Some(range) if req.method() == Method::GET => range.to_str().unwrap_or_default(),
+        _ => return resp.body(body),
+    };
+
+    let len = body.len();
+    let Some(range) = parse_range(range, len) else {
+        let (status_code, data) = to_error_object(XepakError::RangeNotSatisfiable(format!(
+            "Range \"{range}\" is not satisfiable for {len} bytes"
+        )));
+        return HttpResponse::build(status_code)
+            .insert_header((CONTENT_RANGE, format!("bytes */{len}")))
+            .json(data);
+    };
+
+    resp.status(StatusCode::PARTIAL_CONTENT)
+        .insert_header((
+            CONTENT_RANGE,
+            format!("bytes {}-{}/{len}", range.start, range.end - 1),
+        ))
+        .body(body.into_bytes()[range].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing::test_service;
+
+    #[actix_web::test]
+    async fn text_value_range_requests() {
+        use actix_web::http::header::{ACCEPT_RANGES, CONTENT_RANGE, RANGE};
+
+        let app = test_service(
+            "text-value-range",
+            "",
+            &[r#"
+            uri = "/content"
+            json_pointer = { column = "content", pointer = "" }
+            force_content_type = "text/plain"
+
+            [resource]
+            type = "query"
+            query = "SELECT json_quote('hello world') AS content"
+            "#],
+        )
+        .await;
+
+        let get = |range: Option<&str>| {
+            let req = test::TestRequest::get().uri("/content");
+            match range {
+                Some(range) => req.insert_header((RANGE, range.to_string())),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let resp = test::call_service(&app, get(None)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(test::read_body(resp).await, "hello world");
+
+        let resp = test::call_service(&app, get(Some("bytes=0-4"))).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers().get(CONTENT_RANGE).unwrap(), "bytes 0-4/11");
+        assert_eq!(test::read_body(resp).await, "hello");
+
+        let resp = test::call_service(&app, get(Some("bytes=-5"))).await;
+        assert_eq!(resp.headers().get(CONTENT_RANGE).unwrap(), "bytes 6-10/11");
+        assert_eq!(test::read_body(resp).await, "world");
+
+        let resp = test::call_service(&app, get(Some("bytes=6-100"))).await;
+        assert_eq!(test::read_body(resp).await, "world");
+
+        for invalid in ["bytes=20-", "bytes=5-1", "items=0-1"] {
+            let resp = test::call_service(&app, get(Some(invalid))).await;
+            assert_eq!(
+                resp.status(),
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "{invalid}"
+            );
+            assert_eq!(resp.headers().get(CONTENT_RANGE).unwrap(), "bytes */11");
+        }
+    }
+}
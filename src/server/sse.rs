@@ -0,0 +1,162 @@
+use std::{future::Future, time::Duration};
+
+use actix_web::{
+    HttpResponse,
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    web::Bytes,
+};
+use futures_util::stream;
+use tokio::time::{Instant, Interval, interval_at};
+
+use crate::{XepakError, server::client_error_object};
+
+pub const CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+
+/// Event with the `data` split into lines, so line breaks don't end the event early.
+fn event(name: Option<&str>, data: &str) -> Bytes {
+    let mut out = String::with_capacity(data.len() + 16);
+    if let Some(name) = name {
+        out.push_str("event: ");
+        out.push_str(name);
+        out.push('\n');
+    }
+    for line in data.lines() {
+        out.push_str("data: ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('\n');
+    Bytes::from(out)
+}
+
+struct PollState<P> {
+    poll: P,
+    ticks: Interval,
+    first: bool,
+    done: bool,
+}
+
+/// Respond with `poll` result as the first event and then every `interval`.
+/// Polling stops when the client disconnects (the body is dropped),
+/// failed poll is sent as the `error` event with the error object and ends the stream.
+pub fn stream_polled<P, F>(interval: Duration, expose_db_errors: bool, poll: P) -> HttpResponse
+where
+    P: FnMut() -> F + 'static,
+    F: Future<Output = Result<String, XepakError>> + 'static,
+{
+    let state = PollState {
+        poll,
+        ticks: interval_at(Instant::now() + interval, interval),
+        first: true,
+        done: false,
+    };
+
+    let events = stream::unfold(state, move |mut state| async move {
+        if state.done {
+            return None;
+        }
+        if !state.first {
+            state.ticks.tick().await;
+        }
+        state.first = false;
+
+        let event = match (state.poll)().await {
+            Ok(data) => event(None, &data),
+            Err(e) => {
+                tracing::error!("SSE poll failed: {e}");
+                state.done = true;
+                let (_, data) = client_error_object(e, expose_db_errors);
+                event(
+                    Some("error"),
+                    &serde_json::to_string(&data).unwrap_or_default(),
+                )
+            }
+        };
+        Some((Ok::<_, XepakError>(event), state))
+    });
+
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, CONTENT_TYPE_EVENT_STREAM))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+
+    use actix_web::{body::MessageBody, http::StatusCode, test};
+
+    use super::*;
+    use crate::testing::{TestEnv, test_service};
+
+    #[actix_web::test]
+    async fn sse_sends_first_event() {
+        let app = test_service(
+            "sse",
+            "CREATE TABLE metrics (name TEXT, value INTEGER);
+            INSERT INTO metrics VALUES ('cpu', 42)",
+            &[r#"
+            uri = "/events/metrics"
+
+            [resource]
+            type = "sse_query"
+            query = "SELECT name, value FROM metrics"
+            poll_ms = 60000
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/events/metrics").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(CONTENT_TYPE).unwrap(),
+            CONTENT_TYPE_EVENT_STREAM
+        );
+
+        // Stream never ends by itself, so only the first event is read
+        let mut body = resp.into_body();
+        let event = std::future::poll_fn(|cx| Pin::new(&mut body).poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+        let data = event
+            .strip_prefix("data: ")
+            .unwrap()
+            .strip_suffix("\n\n")
+            .unwrap();
+        let data: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(data, serde_json::json!([{"name": "cpu", "value": 42}]));
+    }
+
+    #[actix_web::test]
+    async fn sse_error_event_redacted() {
+        for (conf, exposed) in [("", false), ("expose_db_errors = true", true)] {
+            let app = TestEnv::new("sse-error", conf, "")
+                .await
+                .service(&[r#"
+                uri = "/events/broken"
+
+                [resource]
+                type = "sse_query"
+                query = "SELECT secret FROM missing_table"
+                poll_ms = 60000
+                "#])
+                .await;
+
+            let req = test::TestRequest::get().uri("/events/broken").to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let event = std::str::from_utf8(&body).unwrap();
+            let data = event
+                .strip_prefix("event: error\ndata: ")
+                .unwrap()
+                .strip_suffix("\n\n")
+                .unwrap();
+            let data: serde_json::Value = serde_json::from_str(data).unwrap();
+            assert_eq!(data["code"], "unknown_error");
+            assert_eq!(event.contains("missing_table"), exposed, "{event}");
+        }
+    }
+}
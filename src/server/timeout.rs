@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    Error, HttpResponse,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    middleware::Next,
+};
+
+use crate::{XepakError, server::to_error_object};
+
+/// Respond with 504 if request is not handled (including serialization) within `timeout`.
+/// Handler future is dropped on timeout, so its work is cancelled at the next await point.
+pub async fn request_timeout<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    timeout: Duration,
+) -> Result<ServiceResponse<B>, Error> {
+    // Can't clone the request itself, it breaks routing
+    let endpoint = format!("{} {}", req.method(), req.uri());
+    let started = Instant::now();
+
+    match tokio::time::timeout(timeout, next.call(req)).await {
+        Ok(res) => res,
+        Err(_) => {
+            tracing::warn!(
+                "Request timed out after {}ms: {endpoint}",
+                started.elapsed().as_millis()
+            );
+
+            let err = XepakError::Timeout(format!(
+                "Request was not handled within {}ms",
+                timeout.as_millis()
+            ));
+            let (status_code, data) = to_error_object(err.clone());
+            let resp = HttpResponse::build(status_code).json(data);
+            Err(InternalError::from_response(err, resp).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App,
+        http::StatusCode,
+        middleware::from_fn,
+        test::{self, TestRequest},
+        web,
+    };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn slow_request_times_out() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(|req, next| {
+                    request_timeout(req, next, Duration::from_millis(50))
+                }))
+                .route("/fast", web::get().to(|| async { "ok" }))
+                .route(
+                    "/slow",
+                    web::get().to(|| async {
+                        tokio::time::sleep(Duration::from_millis(500)).await;
+                        "too late"
+                    }),
+                ),
+        )
+        .await;
+
+        let resp = test::call_service(&app, TestRequest::get().uri("/fast").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Server converts middleware error into the response
+        let err = test::try_call_service(&app, TestRequest::get().uri("/slow").to_request())
+            .await
+            .unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{sync::Arc, time::Instant};
+
+use actix_web::{
+    HttpRequest, HttpResponse, Resource,
+    web::{self, Bytes, Data},
+};
+use serde::Deserialize;
+
+use crate::{
+    XepakError,
+    auth::{AuthorizeProcessor, CheckAuthConf, SimpleAuthenticationProcessor},
+    server::{RequestInput, XepakAppData, processor::PreProcessorHandler, to_error_object},
+};
+
+/// Git commit the binary was built from (set by the build script if git is available).
+pub const GIT_COMMIT: Option<&str> = option_env!("XEPAK_GIT_COMMIT");
+
+/// Built-in endpoint that responds with the running build version and uptime.
+#[derive(Clone, Debug, Deserialize)]
+pub struct VersionEndpointConf {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_path")]
+    pub path: String,
+
+    /// Callers must be authenticated and pass any of these checks (empty allows everyone).
+    #[serde(default)]
+    pub allow: Vec<CheckAuthConf>,
+}
+
+impl Default for VersionEndpointConf {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            path: default_path(),
+            allow: Vec::new(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_path() -> String {
+    "/version".to_string()
+}
+
+/// Build resource for the version endpoint, `started` is used to calculate uptime.
+pub fn version_resource(conf: &VersionEndpointConf, started: Instant) -> Resource {
+    let processors: Arc<Vec<Box<dyn PreProcessorHandler + Send + Sync>>> =
+        Arc::new(if conf.allow.is_empty() {
+            Vec::new()
+        } else {
+            vec![
+                SimpleAuthenticationProcessor::new_boxed(false),
+                AuthorizeProcessor::new_boxed(&conf.allow),
+            ]
+        });
+    let path = conf.path.clone();
+
+    web::resource(conf.path.clone()).route(web::get().to(
+        move |req: HttpRequest, state: Data<XepakAppData>, body: Bytes| {
+            let processors = processors.clone();
+            let path = path.clone();
+            async move {
+                let mut input = RequestInput::new(Default::default(), false, &path, req.path());
+                for p in processors.iter() {
+                    if let Err(err) = p.handle(&req, &state, &body, &mut input) {
+                        return error_response(err);
+                    }
+                }
+
+                HttpResponse::Ok().json(serde_json::json!({
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "commit": GIT_COMMIT,
+                    "uptime_secs": started.elapsed().as_secs(),
+                }))
+            }
+        },
+    ))
+}
+
+fn error_response(err: XepakError) -> HttpResponse {
+    let (status_code, data) = to_error_object(err);
+    HttpResponse::build(status_code).json(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, http::StatusCode, test};
+
+    use super::*;
+    use crate::testing::sqlite_app_data;
+
+    #[actix_web::test]
+    async fn version_matches_crate() {
+        let app_data = sqlite_app_data("version", &[""], "").await;
+        let conf = VersionEndpointConf::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(app_data))
+                .service(version_resource(&conf, Instant::now())),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/version").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["uptime_secs"].is_u64(), "{body}");
+    }
+
+    #[actix_web::test]
+    async fn version_gated_by_auth() {
+        let app_data = sqlite_app_data("version-gated", &[""], "").await;
+        let conf: VersionEndpointConf = toml::from_str(
+            r#"
+            path = "/about/version"
+            allow = [{ kind = "id", v = "ops" }]
+            "#,
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(app_data))
+                .service(version_resource(&conf, Instant::now())),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/about/version").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
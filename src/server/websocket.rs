@@ -0,0 +1,257 @@
+use std::{future::Future, time::Duration};
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, CloseCode, CloseReason, Codec, Frame, Message};
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::{
+        StatusCode,
+        header::{HeaderValue, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY},
+    },
+    web::{Bytes, BytesMut, Payload},
+};
+use futures_util::{StreamExt, stream};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{XepakError, server::client_error_object};
+
+/// Encoded frames waiting to be written to the client.
+const OUTGOING_FRAMES: usize = 4;
+
+/// Text frame with JSON or binary frame with CBOR.
+pub fn encode_frame<R>(data: &R, cbor: bool) -> Result<Message, XepakError>
+where
+    R: Serialize + minicbor::Encode<()>,
+{
+    if cbor {
+        let body = minicbor::to_vec(data).map_err(|e| XepakError::WeScrewed(e.to_string()))?;
+        Ok(Message::Binary(Bytes::from(body)))
+    } else {
+        let body = serde_json::to_string(data).map_err(|e| XepakError::WeScrewed(e.to_string()))?;
+        Ok(Message::Text(body.into()))
+    }
+}
+
+/// Accept WebSocket handshake and push `poll` result right away and then every `interval`
+/// until the client closes the connection. Failed poll closes the connection with the error
+/// code and message as the reason.
+pub fn stream_polled<P, F>(
+    req: &HttpRequest,
+    mut payload: Payload,
+    interval: Duration,
+    expose_db_errors: bool,
+    mut poll: P,
+) -> HttpResponse
+where
+    P: FnMut() -> F + 'static,
+    F: Future<Output = Result<Message, XepakError>> + 'static,
+{
+    if let Err(e) = ws::verify_handshake(req.head()) {
+        return HttpResponse::from(actix_web::dev::Response::from(e));
+    }
+    let accept = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) => ws::hash_key(key.as_bytes()),
+        None => return HttpResponse::BadRequest().finish(),
+    };
+
+    let (tx, mut rx) = mpsc::channel::<Bytes>(OUTGOING_FRAMES);
+    actix_web::rt::spawn(async move {
+        let mut codec = Codec::new();
+        let mut incoming = BytesMut::new();
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+
+        // Snapshot is sent right away, then results of every tick
+        if !push(&mut codec, &tx, poll().await, expose_db_errors).await {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {
+                    if !push(&mut codec, &tx, poll().await, expose_db_errors).await {
+                        break;
+                    }
+                }
+                chunk = payload.next() => {
+                    let Some(Ok(chunk)) = chunk else {
+                        tracing::debug!("WebSocket client disconnected");
+                        break;
+                    };
+                    incoming.extend_from_slice(&chunk);
+                    if !handle_incoming(&mut codec, &mut incoming, &tx).await {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let body = stream::poll_fn(move |cx| rx.poll_recv(cx).map(|f| f.map(Ok::<_, XepakError>)));
+    HttpResponse::build(StatusCode::SWITCHING_PROTOCOLS)
+        .upgrade("websocket")
+        .insert_header((
+            SEC_WEBSOCKET_ACCEPT,
+            // Accept key is base64, so it is always a valid header value
+            HeaderValue::from_bytes(&accept).expect("Base64 is a valid header value"),
+        ))
+        .streaming(body)
+}
+
+/// Send poll result or close frame for the error, returns false when the connection is closed.
+async fn push(
+    codec: &mut Codec,
+    tx: &mpsc::Sender<Bytes>,
+    polled: Result<Message, XepakError>,
+    expose_db_errors: bool,
+) -> bool {
+    match polled {
+        Ok(message) => send(codec, tx, message).await.is_ok(),
+        Err(e) => {
+            tracing::error!("WebSocket poll failed: {e}");
+            let (_, data) = client_error_object(e, expose_db_errors);
+            let description = match (data.get("code"), data.get("message")) {
+                (Some(code), Some(message)) => {
+                    format!("{}: {}", code.as_string(), message.as_string())
+                }
+                (Some(code), None) => code.as_string(),
+                _ => String::new(),
+            };
+            let reason = CloseReason::from((CloseCode::Error, description));
+            let _ = send(codec, tx, Message::Close(Some(reason))).await;
+            false
+        }
+    }
+}
+
+/// Answer pings and close frames, returns false when the connection must be closed.
+async fn handle_incoming(
+    codec: &mut Codec,
+    incoming: &mut BytesMut,
+    tx: &mpsc::Sender<Bytes>,
+) -> bool {
+    loop {
+        let frame = match codec.decode(incoming) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return true,
+            Err(e) => {
+                tracing::debug!("Invalid WebSocket frame: {e}");
+                return false;
+            }
+        };
+
+        let reply = match frame {
+            Frame::Ping(data) => Message::Pong(data),
+            Frame::Close(reason) => {
+                let _ = send(codec, tx, Message::Close(reason)).await;
+                return false;
+            }
+            _ => continue,
+        };
+        if send(codec, tx, reply).await.is_err() {
+            return false;
+        }
+    }
+}
+
+async fn send(codec: &mut Codec, tx: &mpsc::Sender<Bytes>, message: Message) -> Result<(), ()> {
+    let mut buf = BytesMut::new();
+    if let Err(e) = codec.encode(message, &mut buf) {
+        tracing::error!("Can't encode WebSocket frame: {e}");
+        return Err(());
+    }
+    tx.send(buf.freeze()).await.map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+
+    use super::*;
+    use crate::testing::{TestEnv, test_service};
+
+    #[actix_web::test]
+    async fn websocket_pushes_initial_snapshot() {
+        let app = test_service(
+            "websocket",
+            "CREATE TABLE metrics (name TEXT, value INTEGER);
+            INSERT INTO metrics VALUES ('cpu', 42)",
+            &[r#"
+            uri = "/live/metrics"
+
+            [resource]
+            type = "web_socket_query"
+            query = "SELECT name, value FROM metrics"
+            poll_ms = 60000
+            "#],
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/live/metrics").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Test payload is empty, so the client "disconnects" right after the snapshot
+        let req = test::TestRequest::get()
+            .uri("/live/metrics")
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            resp.headers().get("sec-websocket-accept").unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let mut body = BytesMut::from(&test::read_body(resp).await[..]);
+        let frame = Codec::new().client_mode().decode(&mut body).unwrap();
+        let Some(Frame::Text(text)) = frame else {
+            panic!("Text frame expected: {frame:?}");
+        };
+        let snapshot: serde_json::Value = serde_json::from_slice(&text).unwrap();
+        assert_eq!(snapshot, serde_json::json!([{"name": "cpu", "value": 42}]));
+    }
+
+    #[actix_web::test]
+    async fn websocket_close_reason_redacted() {
+        for (conf, exposed) in [("", false), ("expose_db_errors = true", true)] {
+            let app = TestEnv::new("websocket-error", conf, "")
+                .await
+                .service(&[r#"
+                uri = "/live/broken"
+
+                [resource]
+                type = "web_socket_query"
+                query = "SELECT secret FROM missing_table"
+                poll_ms = 60000
+                "#])
+                .await;
+
+            let req = test::TestRequest::get()
+                .uri("/live/broken")
+                .insert_header(("upgrade", "websocket"))
+                .insert_header(("connection", "upgrade"))
+                .insert_header(("sec-websocket-version", "13"))
+                .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::SWITCHING_PROTOCOLS);
+
+            let mut body = BytesMut::from(&test::read_body(resp).await[..]);
+            let frame = Codec::new().client_mode().decode(&mut body).unwrap();
+            let Some(Frame::Close(Some(reason))) = frame else {
+                panic!("Close frame expected: {frame:?}");
+            };
+            let description = reason.description.unwrap();
+            assert!(description.starts_with("unknown_error"), "{description}");
+            assert_eq!(
+                description.contains("missing_table"),
+                exposed,
+                "{description}"
+            );
+        }
+    }
+}
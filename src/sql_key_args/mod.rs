@@ -58,7 +58,7 @@ impl<'a> ParametrizedQueryRef<'a> {
     pub fn new(query: &'a str) -> Self {
         let mut args = Vec::new();
         let mut positions = Vec::new();
-        for (arg, pos) in SqlLexer::new(&query) {
+        for (arg, pos) in SqlLexer::new(query) {
             args.push(arg);
             positions.push(pos);
         }
@@ -96,13 +96,43 @@ impl<'a> ParametrizedQueryRef<'a> {
             Cow::Borrowed(self.query)
         }
     }
+
+    /// Same as [`Self::build_query`] but replacement for each key argument
+    /// is provided by `replace` function (called in order of appearance).
+    pub fn build_query_with<'r, E>(
+        &self,
+        mut replace: impl FnMut(&'a str) -> Result<Cow<'r, str>, E>,
+    ) -> Result<Cow<'a, str>, E> {
+        if !self.has_args() {
+            return Ok(Cow::Borrowed(self.query));
+        }
+
+        let mut replacements = Vec::with_capacity(self.args.len());
+        for arg in &self.args {
+            replacements.push(replace(arg)?);
+        }
+
+        Ok(Cow::Owned(build_query_replaced(
+            self.query,
+            &self.positions,
+            |idx| replacements[idx].as_ref(),
+        )))
+    }
 }
 
 fn build_pos_query(query: &str, ranges: &[RangeInclusive<usize>], pos_arg: &str) -> String {
+    build_query_replaced(query, ranges, |_| pos_arg)
+}
+
+fn build_query_replaced<'r>(
+    query: &str,
+    ranges: &[RangeInclusive<usize>],
+    replacement: impl Fn(usize) -> &'r str,
+) -> String {
     let mut result = String::with_capacity(query.len());
     let mut last_index = 0;
 
-    for rng in ranges {
+    for (idx, rng) in ranges.iter().enumerate() {
         let start = *rng.start();
         let end = *rng.end() + 1;
         // Append the text before the range
@@ -111,7 +141,7 @@ fn build_pos_query(query: &str, ranges: &[RangeInclusive<usize>], pos_arg: &str)
         }
 
         // Append the placeholder
-        result.push_str(pos_arg);
+        result.push_str(replacement(idx));
 
         // Move the cursor forward
         last_index = last_index.max(end);
@@ -226,7 +256,7 @@ impl<'a> SqlLexer<'a> {
                     | LexerState::StringDoubleClosing => {
                         state = LexerState::CurlOpen(1, self.offset);
                     }
-                    LexerState::CurlOpen(c, offset) if c == 1 => {
+                    LexerState::CurlOpen(1, offset) => {
                         state = LexerState::CurlOpen(2, offset);
                     }
                     LexerState::CurlOpen(c, _) if c > 2 => state = LexerState::Sql,
@@ -239,10 +269,10 @@ impl<'a> SqlLexer<'a> {
                     LexerState::CurlOpen(c, _) if c != 2 => {
                         state = LexerState::Sql;
                     }
-                    LexerState::CurlOpen(c, offset) if c == 2 => {
+                    LexerState::CurlOpen(2, offset) => {
                         state = LexerState::CurlClose(1, offset);
                     }
-                    LexerState::CurlClose(c, offset) if c == 1 => {
+                    LexerState::CurlClose(1, offset) => {
                         let from = offset;
                         let to = self.offset;
                         let key = &self.sql[(from + 2)..=(to - 2)];
@@ -256,7 +286,12 @@ impl<'a> SqlLexer<'a> {
                 },
                 ch => match state {
                     LexerState::CurlOpen(c, _)
-                        if c == 2 && ch.is_ascii_alphanumeric() || ch == '_' || ch == '-' => {}
+                        if c == 2 && ch.is_ascii_alphanumeric()
+                            || ch == '_'
+                            || ch == '-'
+                            || ch == '!'
+                            || ch == '@'
+                            || ch == '.' => {}
                     LexerState::CurlOpen(_, _)
                     | LexerState::CurlClose(_, _)
                     | LexerState::StringSingleClosing
@@ -382,4 +417,23 @@ mod tests {
 
         assert_eq!(pos_query.to_string(), query_simple);
     }
+
+    #[test]
+    fn parametrized_query_with_replace() {
+        let query = "SELECT * FROM t WHERE x={{key1}} ORDER BY {{!col}} LIMIT {{key2}}";
+
+        let qa = ParametrizedQueryRef::new(query);
+        assert_eq!(qa.get_args(), &["key1", "!col", "key2"]);
+
+        let built = qa
+            .build_query_with(|arg| -> Result<Cow<str>, ()> {
+                Ok(match arg.strip_prefix('!') {
+                    Some(name) => Cow::Owned(format!("\"{name}\"")),
+                    None => Cow::Borrowed("?"),
+                })
+            })
+            .unwrap();
+
+        assert_eq!(built, "SELECT * FROM t WHERE x=? ORDER BY \"col\" LIMIT ?");
+    }
 }
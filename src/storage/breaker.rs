@@ -0,0 +1,146 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::XepakError;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive failures count that opens the circuit
+    pub failures: u32,
+
+    /// Failures must happen within this window (milliseconds) to be counted together
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+
+    /// How long circuit stays open before allowing a probe request (milliseconds)
+    #[serde(default = "default_cooldown_ms")]
+    pub cooldown_ms: u64,
+}
+
+/// Fast-fail requests to a storage that keeps failing.
+///
+/// After `failures` consecutive failures within a window the circuit opens
+/// and all requests fail immediately for a cooldown period.
+/// Then single probe request is allowed (half-open state):
+/// success closes the circuit, failure opens it again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    settings: CircuitBreakerSettings,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    window_start: Option<Instant>,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: CircuitBreakerSettings) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Check if request is allowed to proceed.
+    pub fn check(&self) -> Result<(), XepakError> {
+        let mut state = self.lock_state();
+
+        let Some(open_until) = state.open_until else {
+            return Ok(());
+        };
+
+        if Instant::now() < open_until || state.probing {
+            return Err(XepakError::Unavailable(
+                "Data source is temporarily unavailable".to_string(),
+            ));
+        }
+
+        // Half-open: let only one request through to test recovery
+        state.probing = true;
+        Ok(())
+    }
+
+    pub fn on_success(&self) {
+        let mut state = self.lock_state();
+        *state = BreakerState::default();
+    }
+
+    pub fn on_failure(&self) {
+        let mut state = self.lock_state();
+        let now = Instant::now();
+
+        if state.probing {
+            state.probing = false;
+            state.open_until = Some(now + Duration::from_millis(self.settings.cooldown_ms));
+            tracing::warn!("Circuit breaker probe failed, circuit is open again");
+            return;
+        }
+
+        let window = Duration::from_millis(self.settings.window_ms);
+        match state.window_start {
+            Some(start) if now.duration_since(start) <= window => state.failures += 1,
+            _ => {
+                state.window_start = Some(now);
+                state.failures = 1;
+            }
+        }
+
+        if state.failures >= self.settings.failures {
+            state.open_until = Some(now + Duration::from_millis(self.settings.cooldown_ms));
+            tracing::warn!(
+                "Circuit breaker opened after {} failures for {}ms",
+                state.failures,
+                self.settings.cooldown_ms
+            );
+        }
+    }
+
+    /// Report result of the storage call to the breaker.
+    /// Only connectivity errors are counted as failures, errors caused by the query itself
+    /// (constraint violations, missing tables, bad casts) mean that storage is reachable.
+    pub fn report<T>(&self, result: &Result<T, XepakError>) {
+        match result {
+            Ok(_) => self.on_success(),
+            Err(e) if is_availability_error(e) => self.on_failure(),
+            Err(XepakError::Storage(_)) => self.on_success(),
+            // Storage was not reached, so the probe (if any) must be retried by next request
+            Err(_) => self.lock_state().probing = false,
+        }
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        // State is always consistent so poisoning could be ignored
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn is_availability_error(error: &XepakError) -> bool {
+    match error {
+        XepakError::Unavailable(_) => true,
+        XepakError::Storage(e) => matches!(
+            e.as_ref(),
+            sqlx::Error::Io(_)
+                | sqlx::Error::Tls(_)
+                | sqlx::Error::Protocol(_)
+                | sqlx::Error::PoolTimedOut
+                | sqlx::Error::PoolClosed
+        ),
+        _ => false,
+    }
+}
+
+fn default_window_ms() -> u64 {
+    10_000
+}
+
+fn default_cooldown_ms() -> u64 {
+    5_000
+}
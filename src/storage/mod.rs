@@ -1,53 +1,137 @@
+pub mod breaker;
+
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::XepakError;
-use crate::sql_key_args::ParametrizedQueryRef;
-use crate::types::{Record, SqlxValue, XepakValue};
+use crate::cfg::XepakConf;
+use crate::sql_key_args::{ParametrizedQuery, ParametrizedQueryRef};
+use crate::storage::breaker::{CircuitBreaker, CircuitBreakerSettings};
+use crate::types::{Record, SqlxValue, XepakType, XepakValue};
+use futures_util::TryStreamExt;
 use serde::Deserialize;
-use sqlx::any::{AnyArguments, AnyConnectOptions, AnyRow};
+use sqlx::any::{AnyArguments, AnyConnectOptions, AnyPoolOptions, AnyRow, AnyValueRef};
+use sqlx::pool::PoolConnection;
 use sqlx::query::Query;
 use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::{Any, AnyPool, ConnectOptions, Row};
+use sqlx::{Any, AnyPool, ConnectOptions, Connection, Executor, Row, TypeInfo, ValueRef};
 use sqlx_core::column::Column;
+use strum::Display;
+use tokio::sync::mpsc;
 
 pub const LIMIT_KEY: &str = "-limit-";
 pub const OFFSET_KEY: &str = "-offset-";
 
+/// Replaced with keyset pagination condition (or always true condition for the first page).
+pub const KEYSET_KEY: &str = "-keyset-";
+
+/// Bound to the authenticated id (request must be authenticated).
+pub const AUTH_ID_KEY: &str = "-auth-id-";
+
+/// Bound to the comma-joined (sorted) roles of the authenticated user.
+pub const AUTH_ROLES_KEY: &str = "-auth-roles-";
+
+/// Bound to the expected row version from `If-Match` header.
+pub const IF_MATCH_KEY: &str = "-if-match-";
+
+/// Rows buffered between the streamed query and its consumer.
+const STREAM_BUFFER_ROWS: usize = 64;
+
+/// Row columns in the query order.
+pub type RecordColumns = Vec<(String, XepakValue)>;
+
+/// Key argument with this prefix (`{{!name}}`) is inserted into query as an identifier
+/// from the allowlist instead of being bound as a parameter.
+pub const IDENT_ARG_PREFIX: char = '!';
+
 pub async fn init_storage_connectors(
-    conf_dir: &PathBuf,
-    storages: &[StorageSettings],
-) -> HashMap<String, Storage> {
+    conf_dir: &Path,
+    config: &XepakConf,
+) -> Result<HashMap<String, Storage>, XepakError> {
     let mut links = HashMap::new();
+    let slow_query = config.slow_query_ms.map(Duration::from_millis);
+    let limit_key: Arc<str> = config.limit_key.as_deref().unwrap_or(LIMIT_KEY).into();
+    let offset_key: Arc<str> = config.offset_key.as_deref().unwrap_or(OFFSET_KEY).into();
 
-    for store_settings in storages {
+    for store_settings in &config.storage {
         match store_settings {
-            StorageSettings::Sqlite { id, file, wal } => {
-                let file_path = PathBuf::from(file);
-
-                let file = if file_path.is_absolute() {
-                    file_path
-                } else {
-                    conf_dir.join(file_path)
-                };
+            StorageSettings::Sqlite {
+                id,
+                file,
+                wal,
+                pragma,
+                circuit_breaker,
+                max_connections,
+                acquire_timeout_ms,
+                read_only,
+                max_bound_params,
+                type_map,
+                init_sql,
+                on_connect,
+            } => {
+                let file = resolve_path(conf_dir, PathBuf::from(file));
 
                 tracing::info!("Init sqlite storage \"{id}\" using path \"{file:?}\"");
-                let options = SqliteConnectOptions::new().filename(file);
-                let options = if *wal {
-                    options.journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
-                } else {
-                    options
-                };
+                let options = SqliteConnectOptions::new()
+                    .filename(file)
+                    .read_only(*read_only);
 
+                // Connect options are passed as URL to the Any driver which drops all pragmas,
+                // so they are applied with `after_connect` for every new connection instead.
                 let aco = AnyConnectOptions::from_str(options.to_url_lossy().as_str())
                     .expect("Query string must be valid but it is not");
 
+                for statement in on_connect {
+                    check_on_connect(statement)?;
+                }
+                let mut statements = pragma.to_statements(*wal);
+                statements.extend(on_connect.iter().cloned());
+                let statements = Arc::new(statements);
+                tracing::debug!("Sqlite storage \"{id}\" pragmas: {statements:?}");
+
+                let pool = AnyPoolOptions::new()
+                    .max_connections(*max_connections)
+                    .acquire_timeout(Duration::from_millis(*acquire_timeout_ms))
+                    .after_connect(move |conn, _meta| {
+                        let statements = statements.clone();
+                        Box::pin(async move {
+                            for s in statements.iter() {
+                                conn.execute(s.as_str()).await?;
+                            }
+                            Ok(())
+                        })
+                    })
+                    .connect_lazy_with(aco);
+
+                if let Some(init_sql) = init_sql {
+                    run_init_sql(id, &pool, &resolve_path(conf_dir, init_sql.clone())).await?;
+                }
+
+                let breaker = circuit_breaker
+                    .clone()
+                    .map(|s| Arc::new(CircuitBreaker::new(s)));
+
                 let res = links.insert(
                     id.clone(),
                     Storage {
-                        pool: AnyPool::connect_lazy_with(aco),
+                        pool,
+                        breaker,
+                        slow_query,
+                        read_only: *read_only,
+                        max_bound_params: *max_bound_params,
+                        limit_key: limit_key.clone(),
+                        offset_key: offset_key.clone(),
+                        type_map: Arc::new(
+                            type_map
+                                .iter()
+                                .map(|(name, ty)| (name.to_ascii_uppercase(), *ty))
+                                .collect(),
+                        ),
+                        dialect: SqlDialect::Sqlite,
                     },
                 );
 
@@ -57,7 +141,62 @@ pub async fn init_storage_connectors(
             }
         }
     }
-    links
+    Ok(links)
+}
+
+/// Statements that change data or schema are not allowed for connection setup
+const DESTRUCTIVE_KEYWORDS: [&str; 8] = [
+    "DROP", "DELETE", "TRUNCATE", "ALTER", "INSERT", "UPDATE", "REPLACE", "CREATE",
+];
+
+/// Reject `on_connect` statement that is empty, has many statements
+/// or starts with data or schema changing keyword.
+fn check_on_connect(statement: &str) -> Result<(), XepakError> {
+    let statement = statement.trim().trim_end_matches(';');
+    if statement.contains(';') {
+        return Err(XepakError::Cfg(format!(
+            "on_connect must have a single statement per item: {statement}"
+        )));
+    }
+
+    let first = statement
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .find(|w| !w.is_empty());
+    match first {
+        None => Err(XepakError::Cfg("on_connect statement is empty".to_string())),
+        Some(first)
+            if DESTRUCTIVE_KEYWORDS
+                .iter()
+                .any(|k| first.eq_ignore_ascii_case(k)) =>
+        {
+            Err(XepakError::Cfg(format!(
+                "on_connect statement must not change data or schema: {statement}"
+            )))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Relative paths are resolved against the config dir.
+fn resolve_path(conf_dir: &Path, path: PathBuf) -> PathBuf {
+    if path.is_absolute() {
+        path
+    } else {
+        conf_dir.join(path)
+    }
+}
+
+/// Execute init script (may have many statements) once the pool is created.
+async fn run_init_sql(id: &str, pool: &AnyPool, path: &Path) -> Result<(), XepakError> {
+    tracing::info!("Running init SQL for storage \"{id}\" from \"{path:?}\"");
+    let sql = std::fs::read_to_string(path)
+        .map_err(|e| XepakError::Cfg(format!("Can't read init SQL {path:?}: {e}")))?;
+    sqlx::raw_sql(&sql).execute(pool).await.map_err(|e| {
+        XepakError::Cfg(format!(
+            "Init SQL {path:?} failed for storage \"{id}\": {e}"
+        ))
+    })?;
+    Ok(())
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -69,9 +208,170 @@ pub enum StorageSettings {
         file: String,
         #[serde(default)]
         wal: bool,
+        #[serde(flatten)]
+        pragma: SqlitePragmas,
+        #[serde(default)]
+        circuit_breaker: Option<CircuitBreakerSettings>,
+        #[serde(default = "default_max_connections")]
+        max_connections: u32,
+        /// Max time to wait for a free pool connection before responding with 503
+        #[serde(default = "default_acquire_timeout_ms")]
+        acquire_timeout_ms: u64,
+        /// Open database read-only and reject any query that is not a `SELECT`
+        #[serde(default)]
+        read_only: bool,
+        /// Max parameters bound to a single query, checked before execution
+        #[serde(default = "default_max_bound_params")]
+        max_bound_params: usize,
+        /// Column values of these DB type names (case-insensitive) are converted to the type,
+        /// e.g. `{ TEXT = "int" }`. Checked before the built-in mapping.
+        #[serde(default)]
+        type_map: HashMap<String, XepakType>,
+        /// Idempotent SQL script (e.g. `CREATE TABLE IF NOT EXISTS`) executed once on startup,
+        /// path is relative to the config dir
+        #[serde(default)]
+        init_sql: Option<PathBuf>,
+        /// Session setup statements (e.g. `PRAGMA`, `SET`) executed on each new connection
+        /// after the pragmas above
+        #[serde(default)]
+        on_connect: Vec<String>,
     },
 }
 
+fn default_max_connections() -> u32 {
+    10
+}
+
+fn default_acquire_timeout_ms() -> u64 {
+    5_000
+}
+
+/// SQLite `SQLITE_MAX_VARIABLE_NUMBER` default for versions before 3.32.0
+fn default_max_bound_params() -> usize {
+    999
+}
+
+/// SQLite PRAGMAs applied on each new connection.
+/// Optional values that are not set keep SQLite (or sqlx) defaults.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SqlitePragmas {
+    /// Milliseconds to wait for a lock before failing with `database is locked`.
+    #[serde(default)]
+    pub busy_timeout: Option<u64>,
+
+    #[serde(default)]
+    pub synchronous: Option<SqliteSynchronous>,
+
+    /// Foreign keys are enforced by default (SQLite itself has them disabled).
+    #[serde(default = "default_foreign_keys")]
+    pub foreign_keys: bool,
+
+    /// Positive value is a number of pages, negative value is a size in KiB.
+    #[serde(default)]
+    pub cache_size: Option<i64>,
+
+    /// WAL auto-checkpoint threshold in pages (0 disables auto-checkpoint).
+    #[serde(default)]
+    pub wal_autocheckpoint: Option<u32>,
+}
+
+impl SqlitePragmas {
+    /// Build list of PRAGMA statements to execute on connection.
+    pub fn to_statements(&self, wal: bool) -> Vec<String> {
+        let mut result = Vec::new();
+
+        if wal {
+            result.push("PRAGMA journal_mode = WAL".to_string());
+        }
+        if let Some(v) = self.busy_timeout {
+            result.push(format!("PRAGMA busy_timeout = {v}"));
+        }
+        if let Some(v) = &self.synchronous {
+            result.push(format!("PRAGMA synchronous = {v}"));
+        }
+        result.push(format!(
+            "PRAGMA foreign_keys = {}",
+            if self.foreign_keys { "ON" } else { "OFF" }
+        ));
+        if let Some(v) = self.cache_size {
+            result.push(format!("PRAGMA cache_size = {v}"));
+        }
+        if let Some(v) = self.wal_autocheckpoint {
+            result.push(format!("PRAGMA wal_autocheckpoint = {v}"));
+        }
+
+        result
+    }
+}
+
+impl Default for SqlitePragmas {
+    fn default() -> Self {
+        Self {
+            busy_timeout: None,
+            synchronous: None,
+            foreign_keys: default_foreign_keys(),
+            cache_size: None,
+            wal_autocheckpoint: None,
+        }
+    }
+}
+
+fn default_foreign_keys() -> bool {
+    true
+}
+
+#[derive(Display, Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum SqliteSynchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+/// SQL flavor of the storage backend.
+#[derive(Display, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
+pub enum SqlDialect {
+    Sqlite,
+    Postgres,
+    MySql,
+}
+
+impl SqlDialect {
+    fn quote_char(&self) -> char {
+        match self {
+            Self::Sqlite | Self::Postgres => '"',
+            Self::MySql => '`',
+        }
+    }
+
+    /// Quote identifier (dot separated parts are quoted one by one, e.g. `"schema"."table"`).
+    /// Quote chars inside the identifier are escaped by doubling them.
+    pub fn quote_ident(&self, name: &str) -> Result<String, XepakError> {
+        let quote = self.quote_char();
+        let mut result = String::with_capacity(name.len() + 2);
+        for (idx, part) in name.split('.').enumerate() {
+            if part.is_empty() || part.contains('\0') {
+                return Err(XepakError::Input(format!("Invalid identifier \"{name}\"")));
+            }
+            if idx > 0 {
+                result.push('.');
+            }
+            result.push(quote);
+            for c in part.chars() {
+                if c == quote {
+                    result.push(quote);
+                }
+                result.push(c);
+            }
+            result.push(quote);
+        }
+        Ok(result)
+    }
+}
+
 impl StorageSettings {
     pub fn get_id(&self) -> &str {
         match self {
@@ -83,6 +383,91 @@ impl StorageSettings {
 #[derive(Clone)]
 pub struct Storage {
     pool: AnyPool,
+    breaker: Option<Arc<CircuitBreaker>>,
+    slow_query: Option<Duration>,
+    read_only: bool,
+    max_bound_params: usize,
+    /// Key argument bound to the rows limit ([`LIMIT_KEY`] by default)
+    limit_key: Arc<str>,
+    /// Key argument bound to the rows offset ([`OFFSET_KEY`] by default)
+    offset_key: Arc<str>,
+    /// Type overrides by uppercase DB type name
+    type_map: Arc<HashMap<String, XepakType>>,
+    dialect: SqlDialect,
+}
+
+/// Query with key args replaced by positional placeholders ahead of time,
+/// so it could be executed many times without lexing.
+#[derive(Debug, Clone)]
+pub struct PreparedQuery {
+    /// Original query with key args
+    pub query: String,
+    /// Query with positional placeholders
+    pub sql: String,
+    /// Key args names in order of placeholders
+    pub arg_names: Vec<String>,
+}
+
+impl PreparedQuery {
+    /// Returns `None` if query depends on request (identifiers or keyset pagination).
+    pub fn new(query: &str) -> Option<Self> {
+        let pquery = ParametrizedQuery::new(query.to_string());
+        let dynamic = pquery
+            .get_args()
+            .iter()
+            .any(|arg| arg.starts_with(IDENT_ARG_PREFIX) || arg == KEYSET_KEY);
+        if dynamic {
+            return None;
+        }
+
+        Some(Self {
+            query: query.to_string(),
+            sql: pquery.build_query("?"),
+            arg_names: pquery.get_args().to_vec(),
+        })
+    }
+}
+
+/// Keywords that make `WITH ...` statement a write
+const WRITE_KEYWORDS: [&str; 4] = ["INSERT", "UPDATE", "DELETE", "REPLACE"];
+
+/// Naive check that query only reads data: first keyword must be `SELECT`,
+/// or `WITH` without any of data modifying keywords.
+/// Anything else (including ambiguous and multiple statements) is treated as a write.
+pub fn is_read_query(query: &str) -> bool {
+    if has_multiple_statements(query) {
+        return false;
+    }
+
+    let mut keywords = query
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty());
+
+    match keywords.next() {
+        Some(first) if first.eq_ignore_ascii_case("SELECT") => true,
+        Some(first) if first.eq_ignore_ascii_case("WITH") => {
+            !keywords.any(|w| WRITE_KEYWORDS.iter().any(|k| w.eq_ignore_ascii_case(k)))
+        }
+        _ => false,
+    }
+}
+
+/// Whether anything but whitespace follows `;` outside of quoted text.
+fn has_multiple_statements(query: &str) -> bool {
+    let mut quote = None;
+    for (idx, c) in query.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, ';') => {
+                return !query[idx + 1..]
+                    .trim_start_matches([';', ' ', '\t', '\r', '\n'])
+                    .is_empty();
+            }
+            _ => {}
+        }
+    }
+    false
 }
 
 impl Storage {
@@ -91,40 +476,302 @@ impl Storage {
         &self,
         request: ResourceRequest<'_, RA>,
     ) -> Result<Vec<Record>, XepakError> {
-        let mut connection = self.pool.acquire().await.unwrap();
+        self.check_read_only(request.query)?;
+        self.check_breaker()?;
+        let query = request.query;
+        let started = Instant::now();
+        let result = self.query_unchecked(request).await;
+        self.log_slow_query(query, started);
+        self.report_breaker(&result);
+        result
+    }
+
+    /// Same as [`Self::query`] for a query prepared ahead of time, skips lexing entirely.
+    pub async fn query_prepared<RA: SqlxRequestArgs>(
+        &self,
+        query: &PreparedQuery,
+        args: &RA,
+    ) -> Result<Vec<Record>, XepakError> {
+        self.check_read_only(&query.sql)?;
+        self.check_breaker()?;
+        let started = Instant::now();
+        let result = self.query_prepared_unchecked(query, args).await;
+        self.log_slow_query(&query.query, started);
+        self.report_breaker(&result);
+        result
+    }
+
+    async fn query_prepared_unchecked<RA: SqlxRequestArgs>(
+        &self,
+        query: &PreparedQuery,
+        args: &RA,
+    ) -> Result<Vec<Record>, XepakError> {
+        let mut connection = self.acquire().await?;
+
+        let sql_query = self.bind_args(args, &query.sql, &query.arg_names)?;
+        let result = sql_query
+            .fetch_all(&mut *connection)
+            .await
+            .map_err(Arc::new)?;
+
+        Ok(result
+            .into_iter()
+            .map(|row| self.map_row(row, args))
+            .collect())
+    }
+
+    /// Execute query fetch first row and returns it, if result is empty return Null.
+    pub async fn query_one<RA: SqlxRequestArgs>(
+        &self,
+        request: ResourceRequest<'_, RA>,
+    ) -> Result<Option<Record>, XepakError> {
+        self.check_read_only(request.query)?;
+        self.check_breaker()?;
+        let query = request.query;
+        let started = Instant::now();
+        let result = self.query_one_unchecked(request).await;
+        self.log_slow_query(query, started);
+        self.report_breaker(&result);
+        result
+    }
+
+    /// Execute query in the background and send rows (columns in query order) to the returned
+    /// channel as they are fetched, so the whole result is never kept in memory.
+    /// Fetching stops when the receiver is dropped, an error is sent as the last item.
+    pub fn query_stream<RA: SqlxRequestArgs + Send + Sync + 'static>(
+        &self,
+        query: String,
+        args: RA,
+    ) -> Result<mpsc::Receiver<Result<RecordColumns, XepakError>>, XepakError> {
+        self.check_read_only(&query)?;
+        self.check_breaker()?;
+
+        let storage = self.clone();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_ROWS);
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let result = storage.stream_rows(&query, &args, &tx).await;
+            storage.log_slow_query(&query, started);
+            storage.report_breaker(&result);
+            if let Err(e) = result {
+                let _ = tx.send(Err(e)).await;
+            }
+        });
+
+        Ok(rx)
+    }
+
+    async fn stream_rows<RA: SqlxRequestArgs>(
+        &self,
+        query: &str,
+        args: &RA,
+        tx: &mpsc::Sender<Result<RecordColumns, XepakError>>,
+    ) -> Result<(), XepakError> {
+        let mut connection = self.acquire().await?;
+
+        let request = ResourceRequest::new(query, args);
+        let pquery = ParametrizedQueryRef::new(query);
+        let built = self.build_query(&request, &pquery)?;
+        let sql_query = self.prepare_query(&request, &pquery, &built)?;
+
+        let mut rows = sql_query.fetch(&mut *connection);
+        while let Some(row) = rows.try_next().await.map_err(Arc::new)? {
+            // Column error is sent through the channel by the caller and aborts the body
+            let row = self.map_row_columns(row, args)?;
+            if tx.send(Ok(row)).await.is_err() {
+                tracing::debug!("Stream receiver dropped, stop fetching rows");
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Execute query that does not return rows, returns count of affected rows.
+    pub async fn execute<RA: SqlxRequestArgs>(
+        &self,
+        request: ResourceRequest<'_, RA>,
+    ) -> Result<u64, XepakError> {
+        self.check_read_only(request.query)?;
+        self.check_breaker()?;
+        let query = request.query;
+        let started = Instant::now();
+        let result = self.execute_unchecked(request).await;
+        self.log_slow_query(query, started);
+        self.report_breaker(&result);
+        result
+    }
+
+    async fn execute_unchecked<RA: SqlxRequestArgs>(
+        &self,
+        request: ResourceRequest<'_, RA>,
+    ) -> Result<u64, XepakError> {
+        let mut connection = self.acquire().await?;
+
+        let pquery = ParametrizedQueryRef::new(request.query);
+        let query = self.build_query(&request, &pquery)?;
+        let sql_query = self.prepare_query(&request, &pquery, &query)?;
+
+        let result = sql_query
+            .execute(&mut *connection)
+            .await
+            .map_err(Arc::new)?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Execute query for every args item within a single transaction.
+    /// Returns total count of affected rows, on any error all changes are rolled back.
+    pub async fn execute_batch<RA: SqlxRequestArgs>(
+        &self,
+        query: &str,
+        batch: &[RA],
+    ) -> Result<u64, XepakError> {
+        self.check_read_only(query)?;
+        self.check_breaker()?;
+        let started = Instant::now();
+        let result = self.execute_batch_unchecked(query, batch).await;
+        self.log_slow_query(query, started);
+        self.report_breaker(&result);
+        result
+    }
+
+    async fn execute_batch_unchecked<RA: SqlxRequestArgs>(
+        &self,
+        query: &str,
+        batch: &[RA],
+    ) -> Result<u64, XepakError> {
+        let mut connection = self.acquire().await?;
+        let mut tx = connection.begin().await.map_err(Arc::new)?;
+
+        let mut affected = 0;
+        for args in batch {
+            let request = ResourceRequest::new(query, args);
+            let pquery = ParametrizedQueryRef::new(request.query);
+            let built = self.build_query(&request, &pquery)?;
+            let sql_query = self.prepare_query(&request, &pquery, &built)?;
+
+            affected += sql_query
+                .execute(&mut *tx)
+                .await
+                .map_err(Arc::new)?
+                .rows_affected();
+        }
+
+        tx.commit().await.map_err(Arc::new)?;
+        Ok(affected)
+    }
+
+    /// Close all connections, waits for the acquired ones to be returned first.
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.pool.is_closed()
+    }
+
+    pub fn get_dialect(&self) -> SqlDialect {
+        self.dialect
+    }
+
+    /// Check that a connection could be acquired and the database responds.
+    pub async fn ping(&self) -> Result<(), XepakError> {
+        let mut connection = self.acquire().await?;
+        connection.ping().await.map_err(Arc::new)?;
+        Ok(())
+    }
+
+    async fn acquire(&self) -> Result<PoolConnection<Any>, XepakError> {
+        self.pool.acquire().await.map_err(|e| match e {
+            sqlx::Error::PoolTimedOut => {
+                tracing::warn!("Timed out waiting for a storage connection");
+                XepakError::Unavailable("Database busy".to_string())
+            }
+            e => Arc::new(e).into(),
+        })
+    }
+
+    /// Log query and its argument names (never values) if it took longer than configured.
+    fn log_slow_query(&self, query: &str, started: Instant) {
+        let Some(threshold) = self.slow_query else {
+            return;
+        };
+
+        let elapsed = started.elapsed();
+        if elapsed >= threshold {
+            let pquery = ParametrizedQueryRef::new(query);
+            tracing::warn!(
+                "Slow query took {}ms, args {:?}: {}",
+                elapsed.as_millis(),
+                pquery.get_args(),
+                query
+            );
+        }
+    }
+
+    fn check_read_only(&self, query: &str) -> Result<(), XepakError> {
+        if self.read_only && !is_read_query(query) {
+            return Err(XepakError::Forbidden(
+                "Data source is read-only, only SELECT queries are allowed".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_breaker(&self) -> Result<(), XepakError> {
+        match &self.breaker {
+            Some(b) => b.check(),
+            None => Ok(()),
+        }
+    }
+
+    fn report_breaker<T>(&self, result: &Result<T, XepakError>) {
+        if let Some(b) = &self.breaker {
+            b.report(result);
+        }
+    }
+
+    async fn query_unchecked<RA: SqlxRequestArgs>(
+        &self,
+        request: ResourceRequest<'_, RA>,
+    ) -> Result<Vec<Record>, XepakError> {
+        let mut connection = self.acquire().await?;
 
         let pquery = ParametrizedQueryRef::new(request.query);
-        let query = pquery.build_query("?");
+        let query = self.build_query(&request, &pquery)?;
         let sql_query = self.prepare_query(&request, &pquery, &query)?;
 
-        let result = sql_query.fetch_all(&mut *connection).await.expect("TODO");
+        let result = sql_query
+            .fetch_all(&mut *connection)
+            .await
+            .map_err(Arc::new)?;
 
         let mut out = Vec::new();
         for row in result {
-            let out_row = self.map_row(row);
+            let out_row = self.map_row(row, request.args);
             out.push(out_row);
         }
 
         Ok(out)
     }
 
-    /// Execute query fetch first row and returns it, if result is empty return Null.
-    pub async fn query_one<RA: SqlxRequestArgs>(
+    async fn query_one_unchecked<RA: SqlxRequestArgs>(
         &self,
         request: ResourceRequest<'_, RA>,
     ) -> Result<Option<Record>, XepakError> {
-        let mut connection = self.pool.acquire().await.unwrap();
+        let mut connection = self.acquire().await?;
 
         let pquery = ParametrizedQueryRef::new(request.query);
-        let query = pquery.build_query("?");
+        let query = self.build_query(&request, &pquery)?;
         let sql_query = self.prepare_query(&request, &pquery, &query)?;
 
         let result = sql_query
             .fetch_optional(&mut *connection)
             .await
-            .expect("TODO");
+            .map_err(Arc::new)?;
 
-        Ok(result.map(|r| self.map_row(r)))
+        Ok(result.map(|r| self.map_row(r, request.args)))
     }
 
     /// Execute query that must have one row with only one column.
@@ -157,6 +804,35 @@ impl Storage {
         }
     }
 
+    /// Build query with positional args placeholders and allowed identifiers inserted.
+    fn build_query<'q, RA: SqlxRequestArgs>(
+        &self,
+        request: &'q ResourceRequest<'q, RA>,
+        pquery: &ParametrizedQueryRef<'q>,
+    ) -> Result<Cow<'q, str>, XepakError> {
+        pquery.build_query_with(|arg| {
+            if let Some(name) = arg.strip_prefix(IDENT_ARG_PREFIX) {
+                let ident = request.args.get_identifier(name)?;
+                if request.args.is_quoted_identifier(name) {
+                    return self.dialect.quote_ident(ident).map(Cow::Owned);
+                }
+                return Ok(Cow::Borrowed(ident));
+            }
+
+            if arg == KEYSET_KEY {
+                return match request.args.get_keyset() {
+                    Some((column, Some(_))) => Ok(Cow::Owned(format!("{column} > ?"))),
+                    Some((_, None)) => Ok(Cow::Borrowed("1 = 1")),
+                    None => Err(XepakError::Cfg(
+                        "Keyset pagination is not configured for this query".to_string(),
+                    )),
+                };
+            }
+
+            Ok(Cow::Borrowed("?"))
+        })
+    }
+
     fn prepare_query<'q, RA: SqlxRequestArgs>(
         &self,
         request: &'q ResourceRequest<'q, RA>,
@@ -164,39 +840,130 @@ impl Storage {
         query: &'q Cow<'q, str>,
     ) -> Result<Query<'q, Any, AnyArguments<'q>>, XepakError> {
         tracing::debug!("Executing query: {}", pquery.get_query());
-        tracing::debug!("Executing build: {}", query);
-        let mut sql_query = sqlx::query(query.as_ref());
+        self.bind_args(request.args, query.as_ref(), pquery.get_args())
+    }
+
+    /// Build sqlx query from positional `sql` binding values of `arg_names` in order.
+    fn bind_args<'q, RA: SqlxRequestArgs, N: AsRef<str>>(
+        &self,
+        args: &'q RA,
+        sql: &'q str,
+        arg_names: &[N],
+    ) -> Result<Query<'q, Any, AnyArguments<'q>>, XepakError> {
+        tracing::debug!("Executing build: {}", sql);
+        let mut sql_query = sqlx::query(sql);
+
+        let arg_names: Vec<&str> = arg_names.iter().map(AsRef::as_ref).collect();
+        tracing::debug!("Query arguments: {:?}", arg_names);
+        let bound_count = arg_names
+            .iter()
+            .filter(|arg| !arg.starts_with(IDENT_ARG_PREFIX))
+            .filter(|arg| **arg != KEYSET_KEY || matches!(args.get_keyset(), Some((_, Some(_)))))
+            .count();
+        if bound_count > self.max_bound_params {
+            return Err(XepakError::Input(format!(
+                "Too many parameters ({bound_count}), max {}",
+                self.max_bound_params
+            )));
+        }
 
-        tracing::debug!("Query arguments: {:?}", pquery.get_args());
-        for argument_name in pquery.get_args() {
-            let arg = *argument_name;
+        for arg in arg_names {
+            if arg.starts_with(IDENT_ARG_PREFIX) {
+                continue;
+            }
             sql_query = match arg {
-                LIMIT_KEY => {
-                    tracing::debug!("Query limit: {}", request.args.get_rows_limit());
-                    sql_query.bind(request.args.get_rows_limit() as i64)
+                _ if arg == &*self.limit_key => {
+                    tracing::debug!("Query limit: {}", args.get_rows_limit());
+                    sql_query.bind(args.get_rows_limit() as i64)
                 }
-                OFFSET_KEY => {
-                    tracing::debug!("Query offset: {}", request.args.get_rows_offset());
-                    sql_query.bind(request.args.get_rows_offset() as i64)
+                _ if arg == &*self.offset_key => {
+                    tracing::debug!("Query offset: {}", args.get_rows_offset());
+                    sql_query.bind(args.get_rows_offset() as i64)
                 }
-                _ => request.args.bind_arg(arg, sql_query)?,
+                KEYSET_KEY => match args.get_keyset() {
+                    Some((_, Some(after))) => after.bind_sqlx(sql_query)?,
+                    _ => sql_query,
+                },
+                IF_MATCH_KEY => match args.get_if_match() {
+                    Some(version) => sql_query.bind(version),
+                    None => {
+                        return Err(XepakError::Input("If-Match header is required".to_string()));
+                    }
+                },
+                AUTH_ID_KEY => match args.get_auth() {
+                    Some((id, _)) => id.bind_sqlx(sql_query)?,
+                    None => return Err(not_authenticated(arg)),
+                },
+                AUTH_ROLES_KEY => match args.get_auth() {
+                    Some((_, roles)) => {
+                        let mut roles: Vec<&str> = roles.iter().map(String::as_str).collect();
+                        roles.sort_unstable();
+                        sql_query.bind(roles.join(","))
+                    }
+                    None => return Err(not_authenticated(arg)),
+                },
+                _ => args.bind_arg(arg, sql_query)?,
             };
         }
 
         Ok(sql_query)
     }
 
-    fn map_row(&self, row: AnyRow) -> Record {
+    fn map_row_columns<RA: StorageRequestArgs>(
+        &self,
+        row: AnyRow,
+        args: &RA,
+    ) -> Result<RecordColumns, XepakError> {
         let cols = row.columns();
-        let mut out_row = HashMap::new();
+        let mut out_row = Vec::with_capacity(cols.len());
         for (idx, c) in cols.iter().enumerate() {
-            let col = row.try_get_raw(idx).expect("TODO");
-            let cval = XepakValue::try_from(SqlxValue::new(col)).expect("TODO");
+            let col = row.try_get_raw(idx).map_err(Arc::new)?;
+            out_row.push((c.name().to_string(), self.map_value(c.name(), col, args)));
+        }
+        Ok(out_row)
+    }
 
-            out_row.insert(c.name().to_string(), cval);
+    fn map_row<RA: StorageRequestArgs>(&self, row: AnyRow, args: &RA) -> Record {
+        let cols = row.columns();
+        let mut out_row = Record::with_capacity(cols.len());
+        for (idx, c) in cols.iter().enumerate() {
+            let col = row.try_get_raw(idx).expect("TODO");
+            out_row.insert(c.name().to_string(), self.map_value(c.name(), col, args));
         }
         out_row
     }
+
+    /// Decode column value applying declared column type or type overrides
+    /// (value is kept as is if it can't be converted).
+    fn map_value<RA: StorageRequestArgs>(
+        &self,
+        column: &str,
+        col: AnyValueRef<'_>,
+        args: &RA,
+    ) -> XepakValue {
+        let override_type = if args.is_boolean_column(column) {
+            Some(XepakType::Boolean)
+        } else if self.type_map.is_empty() {
+            None
+        } else {
+            self.type_map
+                .get(&col.type_info().name().to_ascii_uppercase())
+                .copied()
+        };
+
+        let cval = XepakValue::try_from(SqlxValue::new(col)).expect("TODO");
+        let Some(ty) = override_type else {
+            return cval;
+        };
+
+        match cval.clone().convert_to(ty) {
+            Ok(converted) => converted,
+            Err(e) => {
+                tracing::warn!("Can't convert column \"{column}\" value to {ty}: {e}");
+                cval
+            }
+        }
+    }
 }
 
 pub struct ResourceRequest<'a, RA: StorageRequestArgs> {
@@ -210,12 +977,40 @@ impl<'a, RA: StorageRequestArgs> ResourceRequest<'a, RA> {
     }
 }
 
+fn not_authenticated(arg: &str) -> XepakError {
+    XepakError::Forbidden(format!("Query argument \"{arg}\" requires authentication"))
+}
+
 pub trait StorageRequestArgs {
     /// Return records fetch limit
     fn get_rows_limit(&self) -> usize;
 
     /// Return records fetch offset
     fn get_rows_offset(&self) -> usize;
+
+    /// Return keyset pagination column and a page key to continue after (if any).
+    fn get_keyset(&self) -> Option<(&str, Option<&XepakValue>)>;
+
+    /// Return authenticated id and roles (if request is authenticated).
+    fn get_auth(&self) -> Option<(&XepakValue, &HashSet<String>)>;
+
+    /// Return expected row version (if provided).
+    fn get_if_match(&self) -> Option<&str>;
+
+    /// Return identifier (e.g. column name) selected by the argument value.
+    /// Must only return values from the allowlist, because it is inserted into the query as is.
+    fn get_identifier(&self, arg_name: &str) -> Result<&str, XepakError>;
+
+    /// Identifier selected by the argument must be quoted for the storage backend.
+    fn is_quoted_identifier(&self, _arg_name: &str) -> bool {
+        false
+    }
+
+    /// Result column is declared boolean, its values are converted to booleans
+    /// (SQLite returns them as integers).
+    fn is_boolean_column(&self, _column: &str) -> bool {
+        false
+    }
 }
 
 /// SQLx related request args bind functionality
@@ -226,3 +1021,516 @@ pub trait SqlxRequestArgs: StorageRequestArgs {
         query: sqlx::query::Query<'a, sqlx::Any, sqlx::any::AnyArguments<'a>>,
     ) -> Result<sqlx::query::Query<'a, sqlx::Any, sqlx::any::AnyArguments<'a>>, XepakError>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{RequestInput, init_app_data, to_error_object};
+    use crate::testing::{LogCapture, TestEnv, exec, temp_db_file};
+
+    /// Create storage from `conf` TOML with a single sqlite storage with `extra` settings.
+    async fn sqlite_storage(name: &str, conf: &str, extra: &str) -> Storage {
+        sqlx::any::install_default_drivers();
+
+        let file = temp_db_file(name);
+        let config: XepakConf = toml::from_str(&format!(
+            "{conf}\n[[storage]]\ntype = \"sqlite\"\nfile = {:?}\n{extra}",
+            file.to_string_lossy()
+        ))
+        .expect("Config must be valid");
+
+        let mut links = init_storage_connectors(Path::new("."), &config)
+            .await
+            .expect("Storage must be initialized");
+        links.remove("").expect("Storage must exist")
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_opens_and_recovers() {
+        let storage = sqlite_storage(
+            "circuit-breaker",
+            "",
+            "max_connections = 1\nacquire_timeout_ms = 50\n\
+            circuit_breaker = { failures = 2, window_ms = 10000, cooldown_ms = 100 }",
+        )
+        .await;
+        exec(&storage, "CREATE TABLE items (id INTEGER PRIMARY KEY)")
+            .await
+            .unwrap();
+        exec(&storage, "INSERT INTO items VALUES (1)")
+            .await
+            .unwrap();
+
+        // Errors caused by the query itself don't open the circuit
+        for query in ["INSERT INTO items VALUES (1)", "SELECT * FROM not_exists"].repeat(2) {
+            let result = exec(&storage, query).await;
+            assert!(matches!(result, Err(XepakError::Storage(_))), "{result:?}");
+        }
+        exec(&storage, "SELECT 1").await.unwrap();
+
+        // Database can't be reached while the only connection is taken
+        let busy = storage.pool.acquire().await.unwrap();
+        for _ in 0..2 {
+            let result = exec(&storage, "SELECT 1").await;
+            assert!(
+                matches!(result, Err(XepakError::Unavailable(_))),
+                "{result:?}"
+            );
+        }
+        drop(busy);
+
+        // Circuit is open, even a valid query fails fast
+        let started = std::time::Instant::now();
+        let result = exec(&storage, "SELECT 1").await;
+        assert!(
+            matches!(result, Err(XepakError::Unavailable(_))),
+            "{result:?}"
+        );
+        assert!(started.elapsed() < std::time::Duration::from_millis(50));
+
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+
+        // Half-open probe succeeds and closes the circuit
+        exec(&storage, "SELECT 1").await.unwrap();
+        exec(&storage, "SELECT 1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn exhausted_pool_is_unavailable() {
+        let storage = sqlite_storage(
+            "exhausted-pool",
+            "",
+            "max_connections = 1\nacquire_timeout_ms = 50",
+        )
+        .await;
+
+        let _busy = storage.pool.acquire().await.unwrap();
+
+        let err = exec(&storage, "SELECT 1").await.unwrap_err();
+        assert!(matches!(err, XepakError::Unavailable(_)), "{err:?}");
+
+        let (code, _) = to_error_object(err);
+        assert_eq!(code, actix_web::http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn read_only_rejects_writes() {
+        let storage = sqlite_storage("read-only", "", "read_only = true").await;
+
+        let result = exec(&storage, "INSERT INTO t (id) VALUES (1)").await;
+        assert!(
+            matches!(result, Err(XepakError::Forbidden(_))),
+            "{result:?}"
+        );
+
+        exec(&storage, "SELECT 1").await.unwrap();
+        exec(&storage, "WITH x AS (SELECT 1 AS v) SELECT v FROM x")
+            .await
+            .unwrap();
+
+        let result = exec(
+            &storage,
+            "WITH x AS (SELECT 1) SELECT 1; CREATE TABLE t (id)",
+        )
+        .await;
+        assert!(
+            matches!(result, Err(XepakError::Forbidden(_))),
+            "{result:?}"
+        );
+
+        // Connection itself is read-only too
+        let result = sqlx::query("CREATE TABLE t (id)")
+            .execute(&storage.pool)
+            .await;
+        assert!(result.is_err(), "{result:?}");
+    }
+
+    #[test]
+    fn read_query_detection() {
+        assert!(is_read_query("SELECT 1"));
+        assert!(is_read_query("SELECT 1;  \n"));
+        assert!(is_read_query("SELECT ';' AS sep, \"a;b\" FROM t"));
+        assert!(is_read_query("WITH x AS (SELECT 1 AS v) SELECT v FROM x"));
+
+        assert!(!is_read_query("SELECT 1; DELETE FROM t"));
+        assert!(!is_read_query("select 1 ;delete from t;"));
+        assert!(!is_read_query(
+            "WITH x AS (SELECT 1 AS v) SELECT v FROM x; UPDATE t SET v = 2"
+        ));
+        assert!(!is_read_query("SELECT ';'; DROP TABLE t"));
+        assert!(!is_read_query(
+            "WITH x AS (DELETE FROM t RETURNING id) SELECT id FROM x"
+        ));
+        assert!(!is_read_query("DELETE FROM t"));
+    }
+
+    #[tokio::test]
+    async fn too_many_bound_params() {
+        let storage = sqlite_storage("max-params", "", "max_bound_params = 3").await;
+
+        let args = RequestInput::new_in_script(
+            HashMap::from([("v".to_string(), XepakValue::from(1))]),
+            0,
+            0,
+        );
+        storage
+            .query(ResourceRequest::new("SELECT {{v}}, {{v}}, {{v}}", &args))
+            .await
+            .unwrap();
+
+        let err = storage
+            .query(ResourceRequest::new(
+                "SELECT {{v}}, {{v}}, {{v}}, {{v}}",
+                &args,
+            ))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, XepakError::Input(msg) if msg.contains("max 3")),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn type_map_overrides_column_type() {
+        let storage = sqlite_storage("type-map", "", "type_map = { text = \"int\" }").await;
+
+        let rows = exec(&storage, "SELECT '42' AS n, 'abc' AS s, 1.5 AS f")
+            .await
+            .unwrap();
+        assert!(matches!(rows[0]["n"], XepakValue::Integer(42)), "{rows:?}");
+        // Not convertible values are kept as is
+        assert!(
+            matches!(&rows[0]["s"], XepakValue::Text(s) if s == "abc"),
+            "{rows:?}"
+        );
+        assert!(matches!(rows[0]["f"], XepakValue::Float(_)), "{rows:?}");
+    }
+
+    #[tokio::test]
+    async fn custom_limit_key() {
+        let storage = sqlite_storage(
+            "custom-limit-key",
+            "limit_key = \"-page-size-\"\noffset_key = \"-skip-\"",
+            "",
+        )
+        .await;
+
+        let args = RequestInput::new_in_script(HashMap::new(), 2, 1);
+        let rows = storage
+            .query(ResourceRequest::new(
+                "SELECT column1 AS v FROM (VALUES (1), (2), (3), (4))
+                LIMIT {{-page-size-}} OFFSET {{-skip-}}",
+                &args,
+            ))
+            .await
+            .unwrap();
+        let values: Vec<i128> = rows.iter().map(|r| r["v"].as_int().unwrap()).collect();
+        assert_eq!(values, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn integer_above_i64_rejected() {
+        let storage = sqlite_storage("integer-overflow", "", "").await;
+
+        let args = RequestInput::new_in_script(
+            HashMap::from([("v".to_string(), XepakValue::Integer(i64::MAX as i128 + 1))]),
+            0,
+            0,
+        );
+        let result = storage
+            .query(ResourceRequest::new("SELECT {{v}} AS v", &args))
+            .await;
+        assert!(matches!(result, Err(XepakError::ConvertValue(..))));
+
+        let args = RequestInput::new_in_script(
+            HashMap::from([("v".to_string(), XepakValue::Integer(i64::MIN as i128))]),
+            0,
+            0,
+        );
+        let rows = storage
+            .query(ResourceRequest::new("SELECT {{v}} AS v", &args))
+            .await
+            .unwrap();
+        assert_eq!(rows[0]["v"].as_int().unwrap(), i64::MIN as i128);
+    }
+
+    #[tokio::test]
+    async fn boolean_bound_as_integer() {
+        let storage = sqlite_storage("boolean-bound", "", "").await;
+        exec(&storage, "CREATE TABLE flags (id INTEGER, v BOOLEAN)")
+            .await
+            .unwrap();
+
+        for (id, v) in [(1, true), (2, false)] {
+            let args = RequestInput::new_in_script(
+                HashMap::from([
+                    ("id".to_string(), XepakValue::from(id)),
+                    ("v".to_string(), XepakValue::Boolean(v)),
+                ]),
+                0,
+                0,
+            );
+            storage
+                .execute(ResourceRequest::new(
+                    "INSERT INTO flags VALUES ({{id}}, {{v}})",
+                    &args,
+                ))
+                .await
+                .unwrap();
+        }
+
+        // Stored as integers (Any driver can't decode BOOLEAN declared columns of SQLite)
+        let rows = exec(
+            &storage,
+            "SELECT CAST(v AS INTEGER) AS raw FROM flags ORDER BY id",
+        )
+        .await
+        .unwrap();
+        assert_eq!(rows[0]["raw"].as_int().unwrap(), 1);
+        assert_eq!(rows[1]["raw"].as_int().unwrap(), 0);
+
+        let rows = exec(&storage, "SELECT id FROM flags WHERE v = TRUE")
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["id"].as_int().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn prepared_query_same_as_lexed() {
+        let storage = sqlite_storage("prepared-query", "", "").await;
+
+        let query = "SELECT {{a}} AS a, '{{not_arg}}' AS s, {{b}} AS b LIMIT {{-limit-}}";
+        let args = RequestInput::new_in_script(
+            HashMap::from([
+                ("a".to_string(), XepakValue::from(1)),
+                ("b".to_string(), XepakValue::from("two")),
+            ]),
+            10,
+            0,
+        );
+
+        let prepared = PreparedQuery::new(query).unwrap();
+        assert_eq!(prepared.arg_names, ["a", "b", "-limit-"]);
+
+        let lexed = storage
+            .query(ResourceRequest::new(query, &args))
+            .await
+            .unwrap();
+        let result = storage.query_prepared(&prepared, &args).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::to_value(&lexed).unwrap()
+        );
+        assert_eq!(result[0]["s"].as_string(), "{{not_arg}}");
+
+        // Request dependent queries can't be prepared
+        assert!(PreparedQuery::new("SELECT * FROM t ORDER BY {{!sort}}").is_none());
+        assert!(PreparedQuery::new("SELECT * FROM t WHERE {{-keyset-}}").is_none());
+    }
+
+    #[tokio::test]
+    async fn slow_query_logged() {
+        let logs = LogCapture::start();
+        let storage = sqlite_storage("slow-query", "slow_query_ms = 0", "").await;
+
+        let args = RequestInput::new_in_script(
+            HashMap::from([("secret".to_string(), XepakValue::from("s3cr3t"))]),
+            0,
+            0,
+        );
+        storage
+            .query(ResourceRequest::new("SELECT {{secret}} AS v", &args))
+            .await
+            .unwrap();
+
+        let warnings = logs.messages(tracing::Level::WARN);
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].starts_with("Slow query took"));
+        assert!(warnings[0].contains("[\"secret\"]"));
+        assert!(!warnings[0].contains("s3cr3t"));
+    }
+
+    #[test]
+    fn sqlite_pragmas_statements() {
+        let settings: StorageSettings = toml::from_str(
+            "type = \"sqlite\"\nfile = \"db.sqlite\"\nbusy_timeout = 3000\nsynchronous = \"normal\"\nwal_autocheckpoint = 500",
+        )
+        .unwrap();
+
+        let StorageSettings::Sqlite { wal, pragma, .. } = settings;
+        assert_eq!(
+            pragma.to_statements(wal),
+            vec![
+                "PRAGMA busy_timeout = 3000",
+                "PRAGMA synchronous = NORMAL",
+                "PRAGMA foreign_keys = ON",
+                "PRAGMA wal_autocheckpoint = 500",
+            ]
+        );
+    }
+
+    #[test]
+    fn quote_ident_per_dialect() {
+        assert_eq!(SqlDialect::Sqlite.quote_ident("name").unwrap(), r#""name""#);
+        assert_eq!(
+            SqlDialect::Postgres.quote_ident("app.users").unwrap(),
+            r#""app"."users""#
+        );
+        assert_eq!(SqlDialect::MySql.quote_ident("order").unwrap(), "`order`");
+
+        // Quote chars are doubled, the other dialect quote char is kept as is
+        assert_eq!(
+            SqlDialect::Postgres.quote_ident(r#"a"b`c"#).unwrap(),
+            r#""a""b`c""#
+        );
+        assert_eq!(
+            SqlDialect::MySql.quote_ident(r#"a`b"c"#).unwrap(),
+            r#"`a``b"c`"#
+        );
+
+        assert!(SqlDialect::Sqlite.quote_ident("").is_err());
+        assert!(SqlDialect::Sqlite.quote_ident("app.").is_err());
+        assert!(SqlDialect::MySql.quote_ident("a\0b").is_err());
+    }
+
+    #[tokio::test]
+    async fn on_connect_statements_applied() {
+        let storage = sqlite_storage(
+            "on-connect",
+            "",
+            "on_connect = [\"PRAGMA foreign_keys = OFF\", \"PRAGMA cache_size = -4096\"]",
+        )
+        .await;
+
+        // Runs after the built-in pragmas, so it overrides the default foreign keys
+        let rows = exec(
+            &storage,
+            "SELECT foreign_keys AS v FROM pragma_foreign_keys",
+        )
+        .await
+        .unwrap();
+        assert!(
+            matches!(rows[0].get("v"), Some(XepakValue::Integer(0))),
+            "{rows:?}"
+        );
+        let rows = exec(&storage, "SELECT cache_size AS v FROM pragma_cache_size")
+            .await
+            .unwrap();
+        assert!(
+            matches!(rows[0].get("v"), Some(XepakValue::Integer(-4096))),
+            "{rows:?}"
+        );
+
+        assert!(check_on_connect("SET search_path = app").is_ok());
+        assert!(check_on_connect("drop table users").is_err());
+        assert!(check_on_connect("PRAGMA foreign_keys = ON; DELETE FROM users").is_err());
+        assert!(check_on_connect("  ").is_err());
+    }
+
+    #[tokio::test]
+    async fn sqlite_foreign_keys_enforced() {
+        let storage = sqlite_storage("fk-enforced", "", "foreign_keys = true").await;
+
+        exec(&storage, "CREATE TABLE parent (id INTEGER PRIMARY KEY)")
+            .await
+            .unwrap();
+        exec(
+            &storage,
+            "CREATE TABLE child (id INTEGER PRIMARY KEY, parent_id INTEGER REFERENCES parent(id))",
+        )
+        .await
+        .unwrap();
+
+        let result = exec(&storage, "INSERT INTO child (id, parent_id) VALUES (1, 42)").await;
+        assert!(matches!(result, Err(XepakError::Storage(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn sqlite_foreign_keys_cascade_by_default() {
+        let storage = sqlite_storage("fk-cascade", "", "").await;
+
+        exec(&storage, "CREATE TABLE parent (id INTEGER PRIMARY KEY)")
+            .await
+            .unwrap();
+        exec(
+            &storage,
+            "CREATE TABLE child (id INTEGER PRIMARY KEY, parent_id INTEGER REFERENCES parent(id) ON DELETE CASCADE)",
+        )
+        .await
+        .unwrap();
+        exec(&storage, "INSERT INTO parent (id) VALUES (1)")
+            .await
+            .unwrap();
+        exec(
+            &storage,
+            "INSERT INTO child (id, parent_id) VALUES (1, 1), (2, 1)",
+        )
+        .await
+        .unwrap();
+
+        exec(&storage, "DELETE FROM parent WHERE id = 1")
+            .await
+            .unwrap();
+
+        let rows = exec(&storage, "SELECT * FROM child").await.unwrap();
+        assert!(rows.is_empty(), "{rows:?}");
+    }
+
+    #[tokio::test]
+    async fn sqlite_foreign_keys_opt_out() {
+        let storage = sqlite_storage("fk-opt-out", "", "foreign_keys = false").await;
+
+        let rows = exec(&storage, "PRAGMA foreign_keys").await.unwrap();
+        assert_eq!(rows[0]["foreign_keys"].as_int().unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn init_sql_creates_table_on_startup() {
+        sqlx::any::install_default_drivers();
+
+        let conf_dir = std::env::temp_dir();
+        let db_file = temp_db_file("init-sql");
+        let init_file = format!("xepak-{}-init.sql", std::process::id());
+        std::fs::write(
+            conf_dir.join(&init_file),
+            "CREATE TABLE IF NOT EXISTS greetings (word TEXT);\n\
+             INSERT INTO greetings SELECT 'hello' WHERE NOT EXISTS (SELECT 1 FROM greetings);",
+        )
+        .unwrap();
+
+        let conf = |init_file: &str| -> XepakConf {
+            toml::from_str(&format!(
+                "[[storage]]\ntype = \"sqlite\"\nfile = {:?}\ninit_sql = {init_file:?}",
+                db_file.to_string_lossy()
+            ))
+            .unwrap()
+        };
+
+        // Script is idempotent, so the second start sees the same data
+        init_app_data(&conf_dir, &conf(&init_file)).await.unwrap();
+        let env = TestEnv {
+            app_data: init_app_data(&conf_dir, &conf(&init_file)).await.unwrap(),
+        };
+        let app = env
+            .service(&[r#"
+            uri = "/greetings"
+            resource = { type = "query", query = "SELECT word FROM greetings" }
+            "#])
+            .await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/greetings")
+            .to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"word": "hello"}]));
+
+        std::fs::write(conf_dir.join(&init_file), "CREATE TABLE broken (").unwrap();
+        let err = init_app_data(&conf_dir, &conf(&init_file)).await.err();
+        assert!(matches!(err, Some(XepakError::Cfg(_))));
+
+        let err = init_app_data(&conf_dir, &conf("missing.sql")).await.err();
+        assert!(matches!(err, Some(XepakError::Cfg(_))));
+    }
+}
@@ -0,0 +1,189 @@
+//! Helpers shared by unit tests.
+
+use std::{
+    fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{
+    App,
+    dev::{Service, ServiceResponse},
+    test,
+    web::Data,
+};
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+    subscriber::DefaultGuard,
+};
+use tracing_subscriber::{
+    Layer,
+    layer::{Context, SubscriberExt},
+};
+
+use crate::{
+    XepakError,
+    cfg::{EndpointSpecs, XepakConf},
+    server::{RequestInput, XepakAppData, handler::EndpointHandler, init_app_data},
+    storage::{ResourceRequest, Storage},
+    types::Record,
+};
+
+/// Create empty DB file in temp directory unique for the test name.
+pub fn temp_db_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("xepak-{}-{name}.sqlite3", std::process::id()));
+    let _ = fs::remove_file(&path);
+    fs::File::create(&path).expect("Can't create temp DB file");
+    path
+}
+
+/// Build app data from config TOML adding sqlite storage for each of `storage_ids`.
+pub async fn sqlite_app_data(test_name: &str, storage_ids: &[&str], conf: &str) -> XepakAppData {
+    sqlx::any::install_default_drivers();
+
+    let mut conf_str = conf.to_string();
+    for id in storage_ids {
+        let file = temp_db_file(&format!("{test_name}-{id}"));
+        conf_str.push_str(&format!(
+            "\n[[storage]]\ntype = \"sqlite\"\nid = \"{id}\"\nfile = {:?}\n",
+            file.to_string_lossy()
+        ));
+    }
+
+    let conf: XepakConf = toml::from_str(&conf_str).expect("Config must be valid");
+    init_app_data(Path::new("."), &conf)
+        .await
+        .expect("App data must be initialized")
+}
+
+fn endpoint_specs(specs: &str) -> EndpointSpecs {
+    toml::from_str(specs).expect("Endpoint specs must be valid")
+}
+
+/// Sqlite backed app data to serve endpoints from spec TOMLs in tests.
+pub struct TestEnv {
+    pub app_data: XepakAppData,
+}
+
+impl TestEnv {
+    /// App data from config TOML with default sqlite storage prepared by `setup_sql`.
+    pub async fn new(test_name: &str, conf: &str, setup_sql: &str) -> Self {
+        let app_data = sqlite_app_data(test_name, &[""], conf).await;
+        exec_all(&app_data, "", setup_sql).await;
+        Self { app_data }
+    }
+
+    /// Default storage
+    pub fn storage(&self) -> &Storage {
+        self.app_data
+            .get_data_source("")
+            .expect("Default storage must exist")
+    }
+
+    pub fn handler(&self, spec: &str) -> Result<EndpointHandler, XepakError> {
+        EndpointHandler::new(endpoint_specs(spec), &self.app_data)
+    }
+
+    /// Test service with endpoint for each spec TOML.
+    pub async fn service(
+        &self,
+        specs: &[&str],
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> + use<>
+    {
+        let mut app = App::new().app_data(Data::new(self.app_data.clone()));
+        for spec in specs {
+            app = app.service(self.handler(spec).expect("Endpoint must be valid"));
+        }
+        test::init_service(app).await
+    }
+}
+
+/// Test service with endpoint for each spec TOML over sqlite storage prepared by `setup_sql`.
+pub async fn test_service(
+    test_name: &str,
+    setup_sql: &str,
+    specs: &[&str],
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> + use<>
+{
+    TestEnv::new(test_name, "", setup_sql)
+        .await
+        .service(specs)
+        .await
+}
+
+/// Execute query without arguments.
+pub async fn exec(storage: &Storage, query: &str) -> Result<Vec<Record>, XepakError> {
+    let args = RequestInput::new_in_script(Default::default(), 0, 0);
+    storage.query(ResourceRequest::new(query, &args)).await
+}
+
+/// Execute all statements separated by `;` on the data source or panic.
+pub async fn exec_all(app_data: &XepakAppData, data_source: &str, statements: &str) {
+    let storage = app_data
+        .get_data_source(data_source)
+        .expect("Data source must exist");
+
+    for statement in statements.split(';').filter(|s| !s.trim().is_empty()) {
+        exec(storage, statement)
+            .await
+            .expect("Statement must succeed");
+    }
+}
+
+/// Collects log messages emitted on the current thread while alive.
+pub struct LogCapture {
+    events: Arc<Mutex<Vec<(Level, String)>>>,
+    _guard: DefaultGuard,
+}
+
+impl LogCapture {
+    pub fn start() -> Self {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let layer = CaptureLayer {
+            events: events.clone(),
+        };
+        let guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        Self {
+            events,
+            _guard: guard,
+        }
+    }
+
+    /// Messages logged with exactly this level
+    pub fn messages(&self, level: Level) -> Vec<String> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(l, _)| *l == level)
+            .map(|(_, m)| m.clone())
+            .collect()
+    }
+}
+
+struct CaptureLayer {
+    events: Arc<Mutex<Vec<(Level, String)>>>,
+}
+
+impl<S: Subscriber> Layer<S> for CaptureLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        self.events
+            .lock()
+            .unwrap()
+            .push((*event.metadata().level(), visitor.0));
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
@@ -6,18 +6,154 @@
 
 use std::collections::HashMap;
 
-use serde::Deserialize;
+use indexmap::IndexMap;
+use serde::{Deserialize, ser::SerializeMap};
+#[cfg(feature = "server")]
 use sqlx::{TypeInfo, ValueRef as _};
 use strum::Display;
+use time::{
+    Date, OffsetDateTime, format_description::well_known::Rfc3339, macros::format_description,
+};
 
 use crate::XepakError;
 
-/// Record representation from storage
-pub type Record = HashMap<String, XepakValue>;
+/// Record representation from storage, keys are in the query column order
+pub type Record = IndexMap<String, XepakValue>;
+
+/// Result of the resource handling that goes to response.
+#[derive(Debug, Clone)]
+pub enum ResourceOutput {
+    Records(Vec<Record>),
+    /// Single record or null if nothing was found
+    Record(Option<Record>),
+    /// Named outputs combined into an object (keeps parts order)
+    Object(Vec<(String, ResourceOutput)>),
+}
+
+impl ResourceOutput {
+    /// Replace integers with absolute value above `max` with their text representation.
+    pub fn big_ints_to_text(&mut self, max: u64) {
+        match self {
+            ResourceOutput::Records(records) => records
+                .iter_mut()
+                .for_each(|r| record_big_ints_to_text(r, max)),
+            ResourceOutput::Record(Some(r)) => record_big_ints_to_text(r, max),
+            ResourceOutput::Record(None) => {}
+            ResourceOutput::Object(parts) => {
+                parts.iter_mut().for_each(|(_, v)| v.big_ints_to_text(max))
+            }
+        }
+    }
+
+    /// Round floats to the number of decimal `digits`.
+    pub fn round_floats(&mut self, digits: u32) {
+        match self {
+            ResourceOutput::Records(records) => records
+                .iter_mut()
+                .for_each(|r| record_round_floats(r, digits)),
+            ResourceOutput::Record(Some(r)) => record_round_floats(r, digits),
+            ResourceOutput::Record(None) => {}
+            ResourceOutput::Object(parts) => {
+                parts.iter_mut().for_each(|(_, v)| v.round_floats(digits))
+            }
+        }
+    }
+
+    /// Turn records into an object keyed by the `column` value (in the order of first occurrence).
+    /// Repeated key is an error unless `keep_last` is set, then the last record wins.
+    pub fn key_by(self, column: &str, keep_last: bool) -> Result<ResourceOutput, XepakError> {
+        let ResourceOutput::Records(records) = self else {
+            return Ok(self);
+        };
+
+        let mut positions: HashMap<String, usize> = HashMap::with_capacity(records.len());
+        let mut keyed: Vec<(String, ResourceOutput)> = Vec::with_capacity(records.len());
+        for record in records {
+            let Some(key) = record.get(column).map(XepakValue::as_string) else {
+                return Err(XepakError::WeScrewed(format!(
+                    "Key column \"{column}\" is missing in the record"
+                )));
+            };
+
+            match positions.get(&key) {
+                Some(&idx) if keep_last => {
+                    tracing::warn!("Duplicate key \"{key}\" in column \"{column}\", last wins");
+                    keyed[idx].1 = ResourceOutput::Record(Some(record));
+                }
+                Some(_) => {
+                    return Err(XepakError::WeScrewed(format!(
+                        "Duplicate key \"{key}\" in column \"{column}\""
+                    )));
+                }
+                None => {
+                    positions.insert(key.clone(), keyed.len());
+                    keyed.push((key, ResourceOutput::Record(Some(record))));
+                }
+            }
+        }
+
+        Ok(ResourceOutput::Object(keyed))
+    }
+
+    /// Remove listed fields from every record.
+    pub fn remove_fields(&mut self, fields: &[String]) {
+        match self {
+            ResourceOutput::Records(records) => records
+                .iter_mut()
+                .for_each(|r| fields.iter().for_each(|f| _ = r.shift_remove(f))),
+            ResourceOutput::Record(Some(r)) => fields.iter().for_each(|f| _ = r.shift_remove(f)),
+            ResourceOutput::Record(None) => {}
+            ResourceOutput::Object(parts) => {
+                parts.iter_mut().for_each(|(_, v)| v.remove_fields(fields))
+            }
+        }
+    }
+
+    /// Keep only listed fields in every record.
+    pub fn retain_fields(&mut self, fields: &[&str]) {
+        match self {
+            ResourceOutput::Records(records) => records
+                .iter_mut()
+                .for_each(|r| r.retain(|k, _| fields.contains(&k.as_str()))),
+            ResourceOutput::Record(Some(r)) => r.retain(|k, _| fields.contains(&k.as_str())),
+            ResourceOutput::Record(None) => {}
+            ResourceOutput::Object(parts) => {
+                parts.iter_mut().for_each(|(_, v)| v.retain_fields(fields))
+            }
+        }
+    }
+}
+
+fn record_big_ints_to_text(record: &mut Record, max: u64) {
+    for value in record.values_mut() {
+        if let XepakValue::Integer(v) = value
+            && v.unsigned_abs() > max as u128
+        {
+            *value = XepakValue::Text(v.to_string());
+        }
+    }
+}
+
+fn record_round_floats(record: &mut Record, digits: u32) {
+    let scale = 10f64.powi(digits as i32);
+    for value in record.values_mut() {
+        if let XepakValue::Float(v) = value
+            && v.is_finite()
+        {
+            let rounded = (*v * scale).round() / scale;
+            // Scaling could overflow for huge values, keep them as is
+            if rounded.is_finite() {
+                *v = rounded;
+            }
+        }
+    }
+}
 
 /// A workaround to fix rust error: `try_from` has an incompatible type for trait.
+#[cfg(feature = "server")]
 pub struct SqlxValue<'r>(pub sqlx::any::AnyValueRef<'r>);
 
+#[cfg(feature = "server")]
 impl<'r> SqlxValue<'r> {
     pub fn new(value: sqlx::any::AnyValueRef<'r>) -> Self {
         Self(value)
@@ -36,6 +172,40 @@ pub enum XepakType {
     Boolean,
     Int,
     Float,
+    /// UUID stored as text, normalized to canonical hyphenated form
+    Uuid,
+    /// Text from a closed set of variants defined in the schema
+    Enum,
+    /// Date and time stored as RFC 3339 text in UTC, input formats are defined in the schema
+    DateTime,
+}
+
+/// Accepted input formats for the `date_time` type.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DateTimeFormat {
+    /// `2024-05-01T10:00:00+02:00`
+    Rfc3339,
+    /// `2024-05-01`, midnight in UTC
+    Date,
+    /// Seconds since Unix epoch, integer or text
+    Epoch,
+}
+
+impl DateTimeFormat {
+    fn parse(&self, v: &str) -> Option<OffsetDateTime> {
+        match self {
+            Self::Rfc3339 => OffsetDateTime::parse(v, &Rfc3339).ok(),
+            Self::Date => Date::parse(v, format_description!("[year]-[month]-[day]"))
+                .ok()
+                .map(|d| d.midnight().assume_utc()),
+            Self::Epoch => {
+                let seconds = v.parse().ok()?;
+                OffsetDateTime::from_unix_timestamp(seconds).ok()
+            }
+        }
+    }
 }
 
 /// Unified value wrapper for input/output (IDK a better solution than using enum yet).
@@ -56,6 +226,7 @@ pub enum XepakValue {
     /// Any text type: TEXT, VARCHAR, etc.
     /// It is default type for de/serialization of any unknown data.
     Text(String),
+    Uuid(uuid::Uuid),
     //TODO add BLOB
 }
 
@@ -68,20 +239,18 @@ impl XepakValue {
             Self::Integer(_) => XepakType::Int,
             Self::Float(_) => XepakType::Float,
             Self::Text(_) => XepakType::Text,
+            Self::Uuid(_) => XepakType::Uuid,
         }
     }
 
     pub fn is_null(&self) -> bool {
-        if let Self::Null = self { true } else { false }
+        matches!(self, Self::Null)
     }
 
     pub fn from_str_as(v: &str, parse_as: XepakType) -> Result<Self, XepakError> {
         let xv = match parse_as {
             XepakType::Null => Self::Null,
-            XepakType::Boolean => {
-                let parsed = v.parse().map_err(|e| XepakError::Decode(format!("{e}")))?;
-                Self::Boolean(parsed)
-            }
+            XepakType::Boolean => Self::Boolean(parse_bool(v)?),
             XepakType::Int => {
                 let parsed = v.parse().map_err(|e| XepakError::Decode(format!("{e}")))?;
                 Self::Integer(parsed)
@@ -90,11 +259,32 @@ impl XepakValue {
                 let parsed = v.parse().map_err(|e| XepakError::Decode(format!("{e}")))?;
                 Self::Float(parsed)
             }
-            XepakType::Text => Self::Text(v.to_string()),
+            XepakType::Text | XepakType::Enum => Self::Text(v.to_string()),
+            XepakType::Uuid => Self::Uuid(parse_uuid(v)?),
+            XepakType::DateTime => {
+                Self::Text(Self::from(v).as_datetime(&[DateTimeFormat::Rfc3339])?)
+            }
         };
         Ok(xv)
     }
 
+    /// Convert value to another type, null stays null for any type.
+    pub fn convert_to(self, ty: XepakType) -> Result<Self, XepakError> {
+        if self.is_null() {
+            return Ok(self);
+        }
+
+        match ty {
+            XepakType::Null => Ok(Self::Null),
+            XepakType::Text | XepakType::Enum => Ok(Self::Text(self.as_string())),
+            XepakType::Boolean => self.as_bool().map(Self::Boolean),
+            XepakType::Int => self.as_int().map(Self::Integer),
+            XepakType::Float => self.as_float().map(Self::Float),
+            XepakType::Uuid => self.as_uuid().map(Self::Uuid),
+            XepakType::DateTime => self.as_datetime(&[DateTimeFormat::Rfc3339]).map(Self::Text),
+        }
+    }
+
     pub fn as_int(&self) -> Result<i128, XepakError> {
         const TO_TYPE: XepakType = XepakType::Int;
         match self {
@@ -129,9 +319,57 @@ impl XepakValue {
 
                 Ok(parsed)
             }
+            XepakValue::Uuid(_) => Err(XepakError::ConvertValue(
+                self.get_type(),
+                TO_TYPE,
+                "Not possible".to_string(),
+            )),
+        }
+    }
+
+    pub fn as_uuid(&self) -> Result<uuid::Uuid, XepakError> {
+        match self {
+            XepakValue::Uuid(v) => Ok(*v),
+            XepakValue::Text(v) => parse_uuid(v),
+            _ => Err(XepakError::ConvertValue(
+                self.get_type(),
+                XepakType::Uuid,
+                "Not possible".to_string(),
+            )),
         }
     }
 
+    /// Parse date/time with the first matching format and normalize it to RFC 3339 in UTC.
+    pub fn as_datetime(&self, formats: &[DateTimeFormat]) -> Result<String, XepakError> {
+        let parsed = match self {
+            XepakValue::Text(v) => formats.iter().find_map(|f| f.parse(v)),
+            XepakValue::Integer(v) if formats.contains(&DateTimeFormat::Epoch) => i64::try_from(*v)
+                .ok()
+                .and_then(|s| OffsetDateTime::from_unix_timestamp(s).ok()),
+            _ => {
+                return Err(XepakError::ConvertValue(
+                    self.get_type(),
+                    XepakType::DateTime,
+                    "Not possible".to_string(),
+                ));
+            }
+        };
+
+        let Some(parsed) = parsed else {
+            let names: Vec<String> = formats.iter().map(|f| f.to_string()).collect();
+            return Err(XepakError::Decode(format!(
+                "Date/time \"{}\" does not match any of formats: {}",
+                self.as_string(),
+                names.join(", ")
+            )));
+        };
+
+        parsed
+            .to_offset(time::UtcOffset::UTC)
+            .format(&Rfc3339)
+            .map_err(|e| XepakError::Decode(format!("Can't format date/time: {e}")))
+    }
+
     pub fn as_string(&self) -> String {
         match self {
             XepakValue::Null => "".to_string(),
@@ -139,6 +377,7 @@ impl XepakValue {
             XepakValue::Integer(v) => v.to_string(),
             XepakValue::Float(v) => v.to_string(),
             XepakValue::Text(v) => v.clone(),
+            XepakValue::Uuid(v) => v.to_string(),
         }
     }
 
@@ -179,7 +418,14 @@ impl XepakValue {
                     ));
                 }
             }
-            XepakValue::Text(v) => v.parse().map_err(|e| XepakError::Decode(format!("{e}")))?,
+            XepakValue::Text(v) => parse_bool(v)?,
+            XepakValue::Uuid(_) => {
+                return Err(XepakError::ConvertValue(
+                    self.get_type(),
+                    TO_TYPE,
+                    "Not possible".to_string(),
+                ));
+            }
         })
     }
     pub fn as_float(&self) -> Result<f64, XepakError> {
@@ -212,23 +458,54 @@ impl XepakValue {
             }
             XepakValue::Float(v) => *v,
             XepakValue::Text(v) => v.parse().map_err(|e| XepakError::Decode(format!("{e}")))?,
+            XepakValue::Uuid(_) => {
+                return Err(XepakError::ConvertValue(
+                    self.get_type(),
+                    TO_TYPE,
+                    "Not possible".to_string(),
+                ));
+            }
         })
     }
 
+    #[cfg(feature = "server")]
     pub fn bind_sqlx<'a>(
         &'a self,
         query: sqlx::query::Query<'a, sqlx::Any, sqlx::any::AnyArguments<'a>>,
-    ) -> sqlx::query::Query<'a, sqlx::Any, sqlx::any::AnyArguments<'a>> {
-        match self {
+    ) -> Result<sqlx::query::Query<'a, sqlx::Any, sqlx::any::AnyArguments<'a>>, XepakError> {
+        let query = match self {
             XepakValue::Null => query.bind(None::<String>),
             XepakValue::Boolean(v) => query.bind(*v),
-            XepakValue::Integer(v) => query.bind(*v as i64),
+            // Any driver has no 128-bit or unsigned integers, so i64 is the widest type
+            XepakValue::Integer(v) => query.bind(i64::try_from(*v).map_err(|_| {
+                XepakError::ConvertValue(
+                    XepakType::Int,
+                    XepakType::Int,
+                    format!("Integer {v} is out of 64-bit range"),
+                )
+            })?),
             XepakValue::Float(v) => query.bind(v),
             XepakValue::Text(v) => query.bind(v),
-        }
+            // Any driver has no UUID type, so it is stored as text
+            XepakValue::Uuid(v) => query.bind(v.to_string()),
+        };
+        Ok(query)
+    }
+}
+
+/// Booleans the way HTML forms and query strings send them (case-insensitive).
+fn parse_bool(v: &str) -> Result<bool, XepakError> {
+    match v.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(XepakError::Decode(format!("Invalid boolean \"{v}\""))),
     }
 }
 
+fn parse_uuid(v: &str) -> Result<uuid::Uuid, XepakError> {
+    uuid::Uuid::parse_str(v).map_err(|e| XepakError::Decode(format!("Invalid UUID \"{v}\": {e}")))
+}
+
 impl From<&str> for XepakValue {
     fn from(value: &str) -> Self {
         Self::Text(value.to_string())
@@ -280,6 +557,7 @@ impl TryFrom<&serde_json::Value> for XepakValue {
     }
 }
 
+#[cfg(feature = "server")]
 impl<'r> TryFrom<SqlxValue<'r>> for XepakValue {
     type Error = sqlx::error::BoxDynError;
 
@@ -292,6 +570,12 @@ impl<'r> TryFrom<SqlxValue<'r>> for XepakValue {
         //Maybe use type_info.type_compatible(other)
         let res = match type_info.name() {
             "NULL" => Self::Null,
+            // Reported by Any drivers with native booleans.
+            // SQLite returns integers, they are mapped by `boolean` type in the endpoint schema.
+            "BOOLEAN" | "BOOL" => {
+                let v: bool = sqlx::Decode::<sqlx::Any>::decode(value)?;
+                Self::Boolean(v)
+            }
             "INTEGER" | "INT" | "BIGINT" => {
                 // TODO handle unsigned integers better
                 let v: i64 = sqlx::Decode::<sqlx::Any>::decode(value)?;
@@ -346,12 +630,64 @@ impl serde::Serialize for XepakValue {
             XepakValue::Null => ser.serialize_none(),
             XepakValue::Boolean(v) => ser.serialize_bool(*v),
             XepakValue::Integer(v) => ser.serialize_i128(*v),
+            // JSON has no NaN/Infinity, CBOR keeps them as is
+            XepakValue::Float(v) if !v.is_finite() => ser.serialize_none(),
             XepakValue::Float(v) => ser.serialize_f64(*v),
             XepakValue::Text(v) => ser.serialize_str(v.as_str()),
+            XepakValue::Uuid(v) => ser.collect_str(v),
+        }
+    }
+}
+
+impl serde::Serialize for ResourceOutput {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            ResourceOutput::Records(v) => v.serialize(ser),
+            ResourceOutput::Record(v) => v.serialize(ser),
+            ResourceOutput::Object(parts) => {
+                let mut map = ser.serialize_map(Some(parts.len()))?;
+                for (k, v) in parts {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
         }
     }
 }
 
+/// All arrays and maps are encoded with definite length, so strict decoders could read the output.
+impl minicbor::Encode<()> for ResourceOutput {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        match self {
+            ResourceOutput::Records(v) => {
+                e.array(v.len() as u64)?;
+                for r in v {
+                    RecordOutput(r).encode(e, ctx)?;
+                }
+            }
+            ResourceOutput::Record(Some(v)) => RecordOutput(v).encode(e, ctx)?,
+            ResourceOutput::Record(None) => {
+                e.null()?;
+            }
+            ResourceOutput::Object(parts) => {
+                e.map(parts.len() as u64)?;
+                for (k, v) in parts {
+                    e.str(k)?;
+                    v.encode(e, ctx)?;
+                }
+            }
+        };
+        Ok(())
+    }
+}
+
 impl minicbor::Encode<()> for XepakValue {
     fn encode<W: minicbor::encode::Write>(
         &self,
@@ -364,7 +700,288 @@ impl minicbor::Encode<()> for XepakValue {
             XepakValue::Integer(v) => e.encode(*v as i64)?,
             XepakValue::Float(v) => e.encode(v)?,
             XepakValue::Text(v) => e.encode(v)?,
+            XepakValue::Uuid(v) => e.str(&v.to_string())?,
+        };
+        Ok(())
+    }
+}
+
+impl<'b> minicbor::Decode<'b, ()> for XepakValue {
+    fn decode(
+        d: &mut minicbor::Decoder<'b>,
+        _ctx: &mut (),
+    ) -> Result<Self, minicbor::decode::Error> {
+        use minicbor::data::Type;
+
+        match d.datatype()? {
+            Type::Null | Type::Undefined => {
+                d.skip()?;
+                Ok(XepakValue::Null)
+            }
+            Type::Bool => Ok(XepakValue::Boolean(d.bool()?)),
+            Type::U8
+            | Type::U16
+            | Type::U32
+            | Type::U64
+            | Type::I8
+            | Type::I16
+            | Type::I32
+            | Type::I64
+            | Type::Int => Ok(XepakValue::Integer(d.int()?.into())),
+            Type::F16 | Type::F32 | Type::F64 => Ok(XepakValue::Float(d.f64()?)),
+            Type::String | Type::StringIndef => {
+                let mut text = String::new();
+                for part in d.str_iter()? {
+                    text.push_str(part?);
+                }
+                Ok(XepakValue::Text(text))
+            }
+            other => Err(minicbor::decode::Error::message(format!(
+                "Cant decode from CBOR {other}"
+            ))),
+        }
+    }
+}
+
+/// Output wrapper for a single record, keys are kept in the record (query column) order.
+pub struct RecordOutput<'a>(pub &'a Record);
+
+impl serde::Serialize for RecordOutput<'_> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(ser)
+    }
+}
+
+impl minicbor::Encode<()> for RecordOutput<'_> {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        e.map(self.0.len() as u64)?;
+        for (k, v) in self.0 {
+            e.str(k)?;
+            v.encode(e, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Output wrapper that serializes record keys sorted by name, so the output is deterministic.
+pub struct SortedKeys<'a, T>(pub &'a T);
+
+fn sorted_entries(record: &Record) -> Vec<(&String, &XepakValue)> {
+    let mut entries: Vec<_> = record.iter().collect();
+    entries.sort_unstable_by_key(|(k, _)| *k);
+    entries
+}
+
+impl serde::Serialize for SortedKeys<'_, Record> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        ser.collect_map(sorted_entries(self.0))
+    }
+}
+
+impl serde::Serialize for SortedKeys<'_, ResourceOutput> {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self.0 {
+            ResourceOutput::Records(v) => ser.collect_seq(v.iter().map(SortedKeys)),
+            ResourceOutput::Record(Some(v)) => SortedKeys(v).serialize(ser),
+            ResourceOutput::Record(None) => ser.serialize_none(),
+            ResourceOutput::Object(parts) => {
+                ser.collect_map(parts.iter().map(|(k, v)| (k, SortedKeys(v))))
+            }
+        }
+    }
+}
+
+impl minicbor::Encode<()> for SortedKeys<'_, Record> {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        e.map(self.0.len() as u64)?;
+        for (k, v) in sorted_entries(self.0) {
+            e.str(k)?;
+            v.encode(e, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl minicbor::Encode<()> for SortedKeys<'_, ResourceOutput> {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        match self.0 {
+            ResourceOutput::Records(v) => {
+                e.array(v.len() as u64)?;
+                for r in v {
+                    SortedKeys(r).encode(e, ctx)?;
+                }
+            }
+            ResourceOutput::Record(Some(v)) => SortedKeys(v).encode(e, ctx)?,
+            ResourceOutput::Record(None) => {
+                e.null()?;
+            }
+            ResourceOutput::Object(parts) => {
+                e.map(parts.len() as u64)?;
+                for (k, v) in parts {
+                    e.str(k)?;
+                    SortedKeys(v).encode(e, ctx)?;
+                }
+            }
         };
         Ok(())
     }
 }
+
+/// Output wrapper for arbitrary JSON value that could be encoded to CBOR too.
+pub struct JsonOutput(pub serde_json::Value);
+
+impl serde::Serialize for JsonOutput {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(ser)
+    }
+}
+
+impl minicbor::Encode<()> for JsonOutput {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        _ctx: &mut (),
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        encode_json(&self.0, e)
+    }
+}
+
+fn encode_json<W: minicbor::encode::Write>(
+    value: &serde_json::Value,
+    e: &mut minicbor::Encoder<W>,
+) -> Result<(), minicbor::encode::Error<W::Error>> {
+    match value {
+        serde_json::Value::Null => {
+            e.null()?;
+        }
+        serde_json::Value::Bool(v) => {
+            e.bool(*v)?;
+        }
+        serde_json::Value::Number(n) => {
+            if let Some(v) = n.as_i64() {
+                e.i64(v)?;
+            } else if let Some(v) = n.as_u64() {
+                e.u64(v)?;
+            } else {
+                e.f64(n.as_f64().unwrap_or_default())?;
+            }
+        }
+        serde_json::Value::String(v) => {
+            e.str(v)?;
+        }
+        serde_json::Value::Array(values) => {
+            e.array(values.len() as u64)?;
+            for v in values {
+                encode_json(v, e)?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            e.map(map.len() as u64)?;
+            for (k, v) in map {
+                e.str(k)?;
+                encode_json(v, e)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bool_from_text_forms() {
+        for (text, expected) in [
+            ("true", true),
+            ("TRUE", true),
+            ("1", true),
+            ("yes", true),
+            ("On", true),
+            ("false", false),
+            ("0", false),
+            ("No", false),
+            ("off", false),
+        ] {
+            assert_eq!(
+                XepakValue::from(text).as_bool().unwrap(),
+                expected,
+                "{text}"
+            );
+            let value = XepakValue::from_str_as(text, XepakType::Boolean).unwrap();
+            assert!(
+                matches!(value, XepakValue::Boolean(v) if v == expected),
+                "{text}"
+            );
+        }
+
+        let err = XepakValue::from("2").as_bool().unwrap_err();
+        assert!(matches!(err, XepakError::Decode(_)));
+    }
+
+    #[test]
+    fn datetime_from_str_normalized() {
+        let value = XepakValue::from_str_as("2024-05-01T10:30:00.5-01:00", XepakType::DateTime);
+        assert_eq!(value.unwrap().as_string(), "2024-05-01T11:30:00.5Z");
+
+        let err = XepakValue::from_str_as("2024-05-01", XepakType::DateTime).unwrap_err();
+        assert!(matches!(err, XepakError::Decode(_)));
+    }
+
+    fn to_json(v: f64) -> String {
+        serde_json::to_string(&XepakValue::Float(v)).unwrap()
+    }
+
+    fn cbor_roundtrip(v: f64) -> f64 {
+        let buf = minicbor::to_vec(XepakValue::Float(v)).unwrap();
+        minicbor::decode(&buf).unwrap()
+    }
+
+    #[test]
+    fn non_finite_floats() {
+        assert_eq!(to_json(f64::NAN), "null");
+        assert_eq!(to_json(f64::INFINITY), "null");
+        assert_eq!(to_json(f64::NEG_INFINITY), "null");
+
+        assert!(cbor_roundtrip(f64::NAN).is_nan());
+        assert_eq!(cbor_roundtrip(f64::INFINITY), f64::INFINITY);
+    }
+
+    #[test]
+    fn normal_float() {
+        assert_eq!(to_json(1.5), "1.5");
+        assert_eq!(cbor_roundtrip(1.5), 1.5);
+
+        let mut output = ResourceOutput::Records(vec![Record::from([
+            ("avg".to_string(), XepakValue::Float(2.0 / 3.0)),
+            ("nan".to_string(), XepakValue::Float(f64::NAN)),
+        ])]);
+        output.round_floats(2);
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json, serde_json::json!([{"avg": 0.67, "nan": null}]));
+    }
+}